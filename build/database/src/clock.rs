@@ -0,0 +1,65 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The source of the current time, in seconds since the Unix epoch, which decides when a
+/// time-limited delegation expires. Tests can supply their own clock to stay deterministic.
+///
+/// ```
+/// use bibifi_database::Clock;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// let time = Arc::new(AtomicU64::new(100));
+/// let clock = Clock::new({
+///     let time = time.clone();
+///     move || time.load(Ordering::SeqCst)
+/// });
+/// time.store(200, Ordering::SeqCst);
+/// assert_eq!(200, clock.now());
+/// ```
+#[derive(Clone)]
+pub struct Clock(Arc<dyn Fn() -> u64 + Send + Sync>);
+
+impl Clock {
+    /// A clock reading the time from `now`.
+    pub fn new(now: impl Fn() -> u64 + Send + Sync + 'static) -> Clock {
+        Clock(Arc::new(now))
+    }
+
+    /// A clock reading the system time.
+    pub fn system() -> Clock {
+        Clock::new(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        })
+    }
+
+    /// The current time, in seconds since the Unix epoch.
+    pub fn now(&self) -> u64 {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::system()
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Clock({})", self.now())
+    }
+}
+
+/// Databases are compared by their contents, so any two clocks are considered equal.
+impl PartialEq for Clock {
+    fn eq(&self, _: &Clock) -> bool {
+        true
+    }
+}
+
+impl Eq for Clock {}
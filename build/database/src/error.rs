@@ -0,0 +1,155 @@
+use crate::{Right, Status, Weakness};
+use std::fmt;
+
+/// The reason a database operation did not succeed. Every error maps onto one of the wire-level
+/// status codes through [status](#method.status), so callers can report the reason internally
+/// while clients still only see DENIED or FAILED.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The named principal does not exist.
+    UnknownPrincipal(String),
+    /// The named global variable does not exist.
+    UnknownVariable(String),
+    /// The record stored in `variable` has no field named `field`.
+    UnknownField { variable: String, field: String },
+    /// A principal by this name already exists.
+    PrincipalExists(String),
+    /// The password provided for the principal did not match. `anyone` never matches.
+    BadPassword(String),
+    /// The session token provided for the principal is unknown, has expired, was revoked, or
+    /// belongs to another principal.
+    BadToken(String),
+    /// The principal has no password which could be changed (i.e. it is `anyone`).
+    NoPassword(String),
+    /// The new password for the principal does not satisfy the password policy.
+    WeakPassword {
+        principal: String,
+        weakness: Weakness,
+    },
+    /// The principal is admin or `anyone`, which can be neither deleted, disabled, added to a group
+    /// nor granted or denied the superuser role.
+    Builtin(String),
+    /// The principal is not a user, so it cannot own a group.
+    NotUser(String),
+    /// The principal is not a group.
+    NotGroup(String),
+    /// The acting principal is neither admin nor the owner of the group.
+    NotOwner { principal: String, group: String },
+    /// The principal has been disabled, and may not log in until it is enabled again.
+    Disabled(String),
+    /// The acting principal is not a superuser (such as admin), and the operation is not on its own
+    /// behalf.
+    NotAdmin(String),
+    /// The acting principal is neither admin nor the delegator (nor, when revoking, the
+    /// delegated principal).
+    NotDelegator {
+        principal: String,
+        delegator: String,
+    },
+    /// The principal does not hold `right` on `variable`.
+    MissingRight {
+        principal: String,
+        variable: String,
+        right: Right,
+    },
+    /// Delegations to admin are implicit and cannot be revoked, nor can admin be denied a right.
+    AdminRights,
+    /// No version of `variable` from the program with commit sequence number `sequence`, or
+    /// before it, is kept.
+    UnknownVersion { variable: String, sequence: u64 },
+    /// The value stored in `variable` is not of the type the operation requires.
+    TypeMismatch {
+        variable: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl Error {
+    /// The wire-level status code this error is reported as.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::BadPassword(_)
+            | Error::BadToken(_)
+            | Error::Disabled(_)
+            | Error::NotAdmin(_)
+            | Error::NotOwner { .. }
+            | Error::NotDelegator { .. }
+            | Error::MissingRight { .. } => Status::DENIED,
+            Error::UnknownPrincipal(_)
+            | Error::UnknownVariable(_)
+            | Error::UnknownField { .. }
+            | Error::PrincipalExists(_)
+            | Error::NoPassword(_)
+            | Error::WeakPassword { .. }
+            | Error::Builtin(_)
+            | Error::NotUser(_)
+            | Error::NotGroup(_)
+            | Error::AdminRights
+            | Error::UnknownVersion { .. }
+            | Error::TypeMismatch { .. } => Status::FAILED,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownPrincipal(p) => write!(f, "principal {} does not exist", p),
+            Error::UnknownVariable(x) => write!(f, "variable {} does not exist", x),
+            Error::UnknownField { variable, field } => {
+                write!(f, "variable {} has no field {}", variable, field)
+            }
+            Error::PrincipalExists(p) => write!(f, "principal {} already exists", p),
+            Error::BadPassword(p) => write!(f, "incorrect password for principal {}", p),
+            Error::BadToken(p) => write!(f, "invalid session token for principal {}", p),
+            Error::NoPassword(p) => write!(f, "principal {} has no password", p),
+            Error::WeakPassword {
+                principal,
+                weakness,
+            } => write!(f, "password for principal {} {}", principal, weakness),
+            Error::Builtin(p) => write!(f, "principal {} is built in", p),
+            Error::NotUser(p) => write!(f, "principal {} is not a user", p),
+            Error::NotGroup(p) => write!(f, "principal {} is not a group", p),
+            Error::NotOwner { principal, group } => {
+                write!(f, "principal {} does not own group {}", principal, group)
+            }
+            Error::Disabled(p) => write!(f, "principal {} is disabled", p),
+            Error::NotAdmin(p) => write!(f, "principal {} is not a superuser", p),
+            Error::NotDelegator {
+                principal,
+                delegator,
+            } => write!(
+                f,
+                "principal {} may not manage delegations of {}",
+                principal, delegator
+            ),
+            Error::MissingRight {
+                principal,
+                variable,
+                right,
+            } => write!(
+                f,
+                "principal {} does not have {:?} on {}",
+                principal, right, variable
+            ),
+            Error::AdminRights => write!(f, "admin's rights cannot be revoked or denied"),
+            Error::UnknownVersion { variable, sequence } => write!(
+                f,
+                "no version of variable {} as of commit {} is kept",
+                variable, sequence
+            ),
+            Error::TypeMismatch {
+                variable,
+                expected,
+                found,
+            } => write!(
+                f,
+                "variable {} is a {}, expected a {}",
+                variable, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
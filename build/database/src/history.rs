@@ -0,0 +1,98 @@
+use crate::Value;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// A value a global variable held, and the program which wrote it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Version {
+    /// The commit sequence number of the program which wrote the value.
+    pub sequence: u64,
+    /// The principal the program ran as.
+    pub principal: String,
+    pub value: Value,
+}
+
+/// Who created a global variable and who last wrote it, and when, as told by the database's
+/// [clock](struct.Clock.html). Unlike the versions, this is never trimmed.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Metadata {
+    /// The principal which created the variable.
+    pub creator: String,
+    /// The principal which last wrote the variable.
+    pub writer: String,
+    /// When the variable was created, in seconds since the Unix epoch.
+    pub created: u64,
+    /// When the variable was last written, in seconds since the Unix epoch.
+    pub modified: u64,
+    /// The size of the current value, counted as its [history](struct.HistoryPolicy.html) bytes
+    /// are.
+    pub size: usize,
+}
+
+/// How many of each variable's versions are kept. The current value is always kept, even if it
+/// alone exceeds the bounds; older versions are dropped, oldest first, until the history fits.
+///
+/// A policy can also be read from a comma separated list of bounds, as the server does with the
+/// `BIBIFI_HISTORY` environment variable:
+///
+/// ```
+/// use bibifi_database::HistoryPolicy;
+///
+/// let policy: HistoryPolicy = "versions=4,bytes=65536".parse().unwrap();
+/// assert_eq!(4, policy.versions);
+/// assert_eq!(Some(65536), policy.bytes);
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HistoryPolicy {
+    /// The most versions kept per variable, including the current one.
+    pub versions: usize,
+    /// The most bytes of text kept per variable, across its versions, if bounded.
+    pub bytes: Option<usize>,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        HistoryPolicy {
+            versions: 16,
+            bytes: None,
+        }
+    }
+}
+
+impl HistoryPolicy {
+    /// Drops the oldest versions until the history fits the policy.
+    pub(crate) fn trim(&self, history: &mut Vec<Version>) {
+        let mut bytes: usize = history.iter().map(|v| v.value.size()).sum();
+        let mut keep = history.len();
+        while keep > 1 && (keep > self.versions || self.bytes.is_some_and(|bound| bytes > bound)) {
+            bytes -= history[history.len() - keep].value.size();
+            keep -= 1;
+        }
+        history.drain(..history.len() - keep);
+    }
+}
+
+impl FromStr for HistoryPolicy {
+    type Err = String;
+
+    fn from_str(bounds: &str) -> Result<Self, Self::Err> {
+        let mut policy = HistoryPolicy::default();
+        for bound in bounds
+            .split(',')
+            .map(str::trim)
+            .filter(|bound| !bound.is_empty())
+        {
+            let number = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("{} is not a number in bound {}", value, bound))
+            };
+            match bound.split_once('=') {
+                Some(("versions", n)) => policy.versions = number(n)?,
+                Some(("bytes", n)) => policy.bytes = Some(number(n)?),
+                _ => return Err(format!("unknown history bound {}", bound)),
+            }
+        }
+        Ok(policy)
+    }
+}
@@ -1,12 +1,71 @@
-use crate::Status::{DENIED, FAILED, SUCCESS};
+use bibifi_util::{debug, hash, info, warn};
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+mod clock;
+mod error;
+mod history;
+mod policy;
+
+pub use clock::Clock;
+pub use error::Error;
+pub use history::{HistoryPolicy, Metadata, Version};
+pub use policy::{CharClass, PasswordPolicy, Weakness};
+
+#[derive(Clone, Debug)]
 pub struct Database {
     principals: HashMap<String, VPrincipal>,
     variables: HashMap<String, Value>,
     def_delegator: String,
+    clock: Clock,
+    field_policy: FieldPolicy,
+    /// Open sessions, keyed by the hash of their token so that the tokens themselves are never
+    /// stored.
+    sessions: HashMap<[u8; 32], Session>,
+    password_policy: PasswordPolicy,
+    /// The hashes of each principal's previous passwords, most recent first, as far back as the
+    /// password policy remembers.
+    history: HashMap<String, Vec<[u8; 32]>>,
+    /// Rights taken away regardless of delegations, in the order they were set.
+    denials: Vec<Denial>,
+    /// The commit sequence number of the program running, or of the last one run.
+    sequence: u64,
+    history_policy: HistoryPolicy,
+    /// The versions of each global variable, oldest first. The last one is the current value.
+    versions: HashMap<String, Vec<Version>>,
+    /// The provenance of each global variable.
+    metadata: HashMap<String, Metadata>,
+    /// The use-limited delegations the operation running has relied on, with the principal
+    /// holding each. They are used once each when the operation ends.
+    relied: Vec<(String, Delegation)>,
+}
+
+/// Databases are equal when they hold the same state. The version history and metadata are left
+/// out: they record how the state came about, and which programs wrote it, rather than the state
+/// itself. So are the delegations relied on by an operation which has not ended yet.
+impl PartialEq for Database {
+    fn eq(&self, other: &Self) -> bool {
+        self.principals == other.principals
+            && self.variables == other.variables
+            && self.def_delegator == other.def_delegator
+            && self.clock == other.clock
+            && self.field_policy == other.field_policy
+            && self.sessions == other.sessions
+            && self.password_policy == other.password_policy
+            && self.history == other.history
+            && self.denials == other.denials
+            && self.history_policy == other.history_policy
+    }
+}
+
+impl Eq for Database {}
+
+/// A session opened by [open_session](struct.Database.html#method.open_session).
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Session {
+    principal: String,
+    /// The time from which the token is no longer accepted.
+    expires: u64,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -14,13 +73,28 @@ enum VPrincipal {
     Admin([u8; 32]),
     Anyone(Principal),
     User(Principal, [u8; 32]),
+    Group(Principal, Group),
 }
 
+impl VPrincipal {
+    /// Whether the principal may do everything admin may, which admin itself always can.
+    fn superuser(&self) -> bool {
+        match self {
+            VPrincipal::Admin(_) => true,
+            VPrincipal::User(p, _) => p.superuser,
+            VPrincipal::Anyone(_) | VPrincipal::Group(_, _) => false,
+        }
+    }
+}
+
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for VPrincipal {
     fn to_string(&self) -> String {
         match self {
             VPrincipal::Admin(_) => "admin".to_string(),
-            VPrincipal::Anyone(p) | VPrincipal::User(p, _) => p.to_string(),
+            VPrincipal::Anyone(p) | VPrincipal::User(p, _) | VPrincipal::Group(p, _) => {
+                p.to_string()
+            }
         }
     }
 }
@@ -29,14 +103,26 @@ impl ToString for VPrincipal {
 struct Principal {
     name: String,
     delegations: Vec<Delegation>,
+    disabled: bool,
+    /// Whether the principal has been granted everything admin may do.
+    superuser: bool,
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Principal {
     fn to_string(&self) -> String {
         self.name.clone()
     }
 }
 
+/// A group cannot log in, but holds and passes on delegations like any other principal. Every
+/// member has each right the group has.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Group {
+    owner: String,
+    members: Vec<String>,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize)]
 #[serde(untagged)]
 pub enum Value {
@@ -45,17 +131,142 @@ pub enum Value {
     FieldVals(HashMap<String, String>),
 }
 
+impl Value {
+    /// The name of this value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Immediate(_) => "string",
+            Value::List(_) => "list",
+            Value::FieldVals(_) => "record",
+        }
+    }
+
+    /// The number of bytes of text in the value, counting the names of fields as well as their
+    /// values.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::Immediate(s) => s.len(),
+            Value::List(items) => items.iter().map(Value::size).sum(),
+            Value::FieldVals(fv) => fv.iter().map(|(f, v)| f.len() + v.len()).sum(),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 struct Delegation {
     target: String,
     delegator: String,
     right: Right,
+    /// The time from which the delegation is ignored, if it is time-limited.
+    expires: Option<u64>,
+    /// How many more operations may rely on the delegation, if it is use-limited.
+    uses: Option<u64>,
+}
+
+impl Delegation {
+    fn new(target: &str, delegator: &str, right: &Right) -> Delegation {
+        Delegation {
+            target: target.to_string(),
+            delegator: delegator.to_string(),
+            right: right.clone(),
+            expires: None,
+            uses: None,
+        }
+    }
+
+    fn live(&self, now: u64) -> bool {
+        self.expires.is_none_or(|expires| now < expires) && self.uses.is_none_or(|uses| uses > 0)
+    }
+}
+
+/// How a principal was reached while checking a right: the index of the principal it was reached
+/// from, and the delegation followed (none for a group membership). The starting principal has no
+/// step.
+type Step<'a> = Option<(usize, Option<&'a Delegation>)>;
+
+/// Optional bounds on a delegation: it expires `seconds` after being made, or after it has been
+/// used `uses` times, whichever comes first. A delegation is used once by each operation whose
+/// right checks relied on it, however many there were, including checks for principals further
+/// down a delegation chain. An operation is whatever the caller marks the end of with
+/// [settle](struct.Database.html#method.settle); the runtime ends one after each command, each
+/// condition and the return value. Uses spent by a program which fails are discarded with it.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Limit {
+    pub seconds: Option<u64>,
+    pub uses: Option<u64>,
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub enum Target {
     All,
     Variable(String),
+    /// A single field of the record stored in a variable. A right on the whole variable is also a
+    /// right on each of its fields.
+    Field(String, String),
+    /// Every variable whose name starts with the prefix, including variables created later; an
+    /// empty prefix matches every variable. Unlike `All`, this is a single delegation which is
+    /// matched whenever a right is checked.
+    Pattern(String),
+}
+
+impl Target {
+    /// The target of the delegations made for this target, or none for `all`. A field is
+    /// targeted as `variable.field` and a pattern as `prefix*`, neither of which can clash with a
+    /// variable name.
+    fn key(&self) -> Option<String> {
+        match self {
+            Target::All => None,
+            Target::Variable(variable) => Some(variable.clone()),
+            Target::Field(variable, field) => Some(format!("{}.{}", variable, field)),
+            Target::Pattern(prefix) => Some(format!("{}*", prefix)),
+        }
+    }
+}
+
+/// Whether a delegation targeting `held` applies to `target`: either they are the same, or
+/// `target` is a field of the variable `held`, or `held` is a pattern and `target` starts with its
+/// prefix. A pattern thereby also covers the narrower patterns it matches.
+fn covers(held: &str, target: &str) -> bool {
+    held == target
+        || target
+            .strip_prefix(held)
+            .is_some_and(|field| field.starts_with('.'))
+        || held
+            .strip_suffix('*')
+            .is_some_and(|prefix| target.starts_with(prefix))
+}
+
+/// A negative assertion: `principal` does not hold `right` on `target`, whatever it has been
+/// delegated. A denial naming `anyone` applies to every principal, and one on `all` applies to
+/// every variable, including those created later. Superusers are never denied anything.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct Denial {
+    pub target: Target,
+    pub right: Right,
+    pub principal: String,
+}
+
+impl Denial {
+    /// Whether the denial restricts rights on `target`, a variable `x` or a field `x.f`. A
+    /// denial on a field also applies to the variable holding it, since the whole record includes
+    /// the field.
+    fn applies(&self, target: &str) -> bool {
+        self.target
+            .key()
+            .is_none_or(|key| covers(&key, target) || covers(target, &key))
+    }
+}
+
+/// What reading a whole record yields for a principal which only has read permission on some of
+/// its fields.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum FieldPolicy {
+    /// The read is a security violation, as if there were no field permissions at all.
+    #[default]
+    Deny,
+    /// The read yields the record with only the fields the principal may read. It is still a
+    /// security violation if there are none.
+    Project,
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
@@ -66,6 +277,8 @@ pub enum Right {
     Delegate,
 }
 
+/// The wire-level outcome of an operation. Operations themselves return an [Error](enum.Error.html)
+/// describing why they did not succeed, which maps onto one of these.
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub enum Status {
     SUCCESS,
@@ -75,38 +288,182 @@ pub enum Status {
 
 impl Database {
     pub fn new(admin_hash: [u8; 32]) -> Database {
+        Database::with_clock(admin_hash, Clock::system())
+    }
+
+    /// Creates a database whose time-limited delegations expire according to `clock`.
+    pub fn with_clock(admin_hash: [u8; 32], clock: Clock) -> Database {
         let mut principals = HashMap::new();
         principals.insert("admin".to_string(), VPrincipal::Admin(admin_hash));
         let anyone = VPrincipal::Anyone(Principal {
             name: "anyone".to_string(),
             delegations: Vec::new(),
+            disabled: false,
+            superuser: false,
         });
         principals.insert("anyone".to_string(), anyone.clone());
         Database {
             principals,
             variables: HashMap::new(),
             def_delegator: "anyone".to_string(),
+            clock,
+            field_policy: FieldPolicy::Deny,
+            sessions: HashMap::new(),
+            password_policy: PasswordPolicy::default(),
+            history: HashMap::new(),
+            denials: Vec::new(),
+            sequence: 0,
+            history_policy: HistoryPolicy::default(),
+            versions: HashMap::new(),
+            metadata: HashMap::new(),
+            relied: Vec::new(),
         }
     }
 
-    #[must_use]
-    pub fn check_pass(&self, principal: &String, hash: &[u8; 32]) -> Status {
-        self.principals
-            .get(principal)
-            .map(|principal| match *principal {
-                VPrincipal::Anyone(_) => DENIED,
-                VPrincipal::User(_, checked) | VPrincipal::Admin(checked) => {
-                    if &checked == hash {
-                        SUCCESS
-                    } else {
-                        DENIED
+    /// Sets the rules which new passwords must satisfy. Existing passwords are not checked.
+    pub fn set_password_policy(&mut self, policy: PasswordPolicy) {
+        self.password_policy = policy;
+    }
+
+    /// Sets how many versions of each variable are kept, trimming the histories kept so far.
+    pub fn set_history_policy(&mut self, policy: HistoryPolicy) {
+        self.history_policy = policy;
+        for history in self.versions.values_mut() {
+            policy.trim(history);
+        }
+    }
+
+    /// Starts the next program, whose writes are tagged with the next commit sequence number. The
+    /// runtime runs each program on a copy of the database, so a number is only used up by a
+    /// program which commits.
+    pub fn begin(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /// The commit sequence number of the program running, or of the last one run.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Ends an operation: each use-limited delegation it relied on is used once, so the next
+    /// operation no longer finds one which has been used up.
+    pub fn settle(&mut self) {
+        for (holder, relied) in std::mem::take(&mut self.relied) {
+            if let Some(VPrincipal::Anyone(p) | VPrincipal::User(p, _) | VPrincipal::Group(p, _)) =
+                self.principals.get_mut(&holder)
+            {
+                if let Some(uses) = p
+                    .delegations
+                    .iter_mut()
+                    .find(|d| **d == relied)
+                    .and_then(|d| d.uses.as_mut())
+                {
+                    *uses -= 1;
+                }
+            }
+        }
+    }
+
+    /// Puts principals, delegations, denials, variables and their histories all back as they are
+    /// in `checkpoint`, an earlier copy of this database. The configuration and clock are kept,
+    /// and numbering carries on from the current program. Sessions are kept too, apart from those
+    /// of principals which no longer exist, so a token closed since the checkpoint stays closed.
+    pub fn restore(&mut self, checkpoint: &Database) {
+        let mut restored = checkpoint.clone();
+        restored.sessions = std::mem::take(&mut self.sessions);
+        let principals = &restored.principals;
+        restored
+            .sessions
+            .retain(|_, s| principals.contains_key(&s.principal));
+        restored.clock = self.clock.clone();
+        restored.field_policy = self.field_policy;
+        restored.password_policy = self.password_policy.clone();
+        restored.set_history_policy(self.history_policy);
+        restored.sequence = self.sequence;
+        restored.relied = std::mem::take(&mut self.relied);
+        info!(
+            "database restored",
+            checkpoint = checkpoint.sequence,
+            sequence = self.sequence
+        );
+        *self = restored;
+    }
+
+    /// Sets how [read](#method.read) treats principals with read permission on only some fields.
+    pub fn set_field_policy(&mut self, policy: FieldPolicy) {
+        self.field_policy = policy;
+    }
+
+    pub fn check_pass(&self, principal: &String, hash: &[u8; 32]) -> Result<(), Error> {
+        let checked = match self.principals.get(principal) {
+            None => Err(Error::UnknownPrincipal(principal.clone())),
+            Some(VPrincipal::User(p, checked)) if checked == hash && p.disabled => {
+                Err(Error::Disabled(principal.clone()))
+            }
+            Some(VPrincipal::User(_, checked)) | Some(VPrincipal::Admin(checked))
+                if checked == hash =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(Error::BadPassword(principal.clone())),
+        };
+        if let Err(e) = &checked {
+            warn!("password refused", principal = principal, error = e);
+        }
+        checked
+    }
+
+    /// Authenticates the principal by a session token rather than a password. `token` is the hash
+    /// of the token, as for passwords.
+    pub fn check_token(&self, principal: &String, token: &[u8; 32]) -> Result<(), Error> {
+        let now = self.clock.now();
+        let checked = match self.principals.get(principal) {
+            None => Err(Error::UnknownPrincipal(principal.clone())),
+            Some(vprincipal) => match self.sessions.get(token) {
+                Some(session) if &session.principal == principal && now < session.expires => {
+                    match vprincipal {
+                        VPrincipal::User(p, _) if p.disabled => {
+                            Err(Error::Disabled(principal.clone()))
+                        }
+                        _ => Ok(()),
                     }
                 }
-            })
-            .unwrap_or(FAILED)
+                _ => Err(Error::BadToken(principal.clone())),
+            },
+        };
+        if let Err(e) = &checked {
+            warn!("token refused", principal = principal, error = e);
+        }
+        checked
+    }
+
+    /// Opens a session for the principal, which lasts `seconds` from now. `token` is the hash of
+    /// the token which will be accepted by [check_token](#method.check_token).
+    pub fn open_session(&mut self, user: &str, token: [u8; 32], seconds: u64) {
+        let expires = self.clock.now().saturating_add(seconds);
+        self.sessions.insert(
+            token,
+            Session {
+                principal: user.to_string(),
+                expires,
+            },
+        );
+    }
+
+    /// Closes every session of the principal. Principals may close their own sessions; only
+    /// superusers may close anyone else's.
+    pub fn revoke_sessions(&mut self, user: &String, principal: &String) -> Result<(), Error> {
+        if !self.is_superuser(user) && user != principal {
+            Err(Error::NotAdmin(user.clone()))
+        } else if !self.principals.contains_key(principal) {
+            Err(Error::UnknownPrincipal(principal.clone()))
+        } else {
+            self.sessions.retain(|_, s| &s.principal != principal);
+            Ok(())
+        }
     }
 
-    #[must_use]
     pub fn delegate(
         &mut self,
         user: &String,
@@ -114,71 +471,108 @@ impl Database {
         delegator: &String,
         right: &Right,
         delegated: &String,
-    ) -> Status {
-        if user == "admin" || user == delegator {
-            if let Some(pdelegator) = self.principals.get(delegator).cloned() {
-                if let Some(pdelegated) = self.principals.get(delegated).cloned() {
-                    let mut p = match pdelegated {
-                        VPrincipal::Admin(_) => {
-                            if let Target::Variable(variable) = target {
-                                if !(self.variables.contains_key(variable)) {
-                                    return FAILED;
-                                } else if !(self.direct_check_right(
-                                    variable,
-                                    &Right::Delegate,
-                                    &pdelegator,
-                                )) {
-                                    return DENIED;
-                                }
-                            }
-                            return SUCCESS;
-                        }
-                        VPrincipal::Anyone(ref p) | VPrincipal::User(ref p, _) => p.clone(),
-                    };
-                    if let Target::Variable(variable) = target {
-                        if user == "admin"
-                            || self.direct_check_right(variable, &Right::Delegate, &pdelegator)
-                        {
-                            let delegation = Delegation {
-                                target: variable.clone(),
-                                delegator: delegator.to_string(),
-                                right: right.clone(),
-                            };
-                            p.delegations.push(delegation);
-                        } else {
-                            return DENIED;
-                        }
-                    } else {
-                        for variable in self.variables.keys() {
-                            if user == "admin"
-                                || self.direct_check_right(variable, &Right::Delegate, &pdelegator)
-                            {
-                                let delegation = Delegation {
-                                    target: variable.clone(),
-                                    delegator: delegator.to_string(),
-                                    right: right.clone(),
-                                };
-                                p.delegations.push(delegation);
-                            }
-                        }
+    ) -> Result<(), Error> {
+        self.delegate_limited(user, target, delegator, right, delegated, &Limit::default())
+    }
+
+    /// Like [delegate](#method.delegate), but the delegations made are bounded by `limit`.
+    pub fn delegate_limited(
+        &mut self,
+        user: &String,
+        target: &Target,
+        delegator: &String,
+        right: &Right,
+        delegated: &String,
+        limit: &Limit,
+    ) -> Result<(), Error> {
+        let pdelegator = self
+            .principals
+            .get(delegator)
+            .cloned()
+            .ok_or_else(|| Error::UnknownPrincipal(delegator.clone()))?;
+        let pdelegated = self
+            .principals
+            .get(delegated)
+            .cloned()
+            .ok_or_else(|| Error::UnknownPrincipal(delegated.clone()))?;
+        if let Target::Field(variable, field) = target {
+            self.check_field(variable, field)?;
+        }
+        if !self.is_superuser(user) && user != delegator {
+            return Err(Error::NotDelegator {
+                principal: user.clone(),
+                delegator: delegator.clone(),
+            });
+        }
+        let key = target.key();
+        let mut p = match pdelegated {
+            VPrincipal::Admin(_) => {
+                if let Some(variable) = &key {
+                    // the variable holding a field has already been checked, and a pattern need
+                    // not match any variable yet
+                    if !(self.variables.contains_key(variable)
+                        || matches!(target, Target::Field(..) | Target::Pattern(_)))
+                    {
+                        return Err(Error::UnknownVariable(variable.clone()));
+                    } else if !(self.direct_check_right(variable, &Right::Delegate, &pdelegator)) {
+                        return Err(Error::MissingRight {
+                            principal: delegator.clone(),
+                            variable: variable.clone(),
+                            right: Right::Delegate,
+                        });
                     }
-                    match pdelegated {
-                        VPrincipal::Anyone(_) => self
-                            .principals
-                            .insert("anyone".to_string(), VPrincipal::Anyone(p)),
-                        VPrincipal::User(_, hash) => self
-                            .principals
-                            .insert(p.name.clone(), VPrincipal::User(p, hash.clone())),
-                        _ => panic!(),
-                    };
-                    return SUCCESS;
+                }
+                return Ok(());
+            }
+            VPrincipal::Anyone(ref p)
+            | VPrincipal::User(ref p, _)
+            | VPrincipal::Group(ref p, _) => p.clone(),
+        };
+        let expires = limit
+            .seconds
+            .map(|seconds| self.clock.now().saturating_add(seconds));
+        let delegation = |variable: &String| Delegation {
+            expires,
+            uses: limit.uses,
+            ..Delegation::new(variable, delegator, right)
+        };
+        if let Some(variable) = &key {
+            if self.is_superuser(user)
+                || self.direct_check_right(variable, &Right::Delegate, &pdelegator)
+            {
+                p.delegations.push(delegation(variable));
+            } else {
+                return Err(Error::MissingRight {
+                    principal: delegator.clone(),
+                    variable: variable.clone(),
+                    right: Right::Delegate,
+                });
+            }
+        } else {
+            let variables: Vec<String> = self.variables.keys().cloned().collect();
+            for variable in &variables {
+                if self.is_superuser(user)
+                    || self.direct_check_right(variable, &Right::Delegate, &pdelegator)
+                {
+                    p.delegations.push(delegation(variable));
                 }
             }
         }
-        FAILED
+        match pdelegated {
+            VPrincipal::Anyone(_) => self
+                .principals
+                .insert("anyone".to_string(), VPrincipal::Anyone(p)),
+            VPrincipal::User(_, hash) => self
+                .principals
+                .insert(p.name.clone(), VPrincipal::User(p, hash)),
+            VPrincipal::Group(_, group) => self
+                .principals
+                .insert(p.name.clone(), VPrincipal::Group(p, group)),
+            _ => panic!(),
+        };
+        Ok(())
     }
 
-    #[must_use]
     pub fn undelegate(
         &mut self,
         user: &String,
@@ -186,296 +580,844 @@ impl Database {
         delegator: &String,
         right: &Right,
         delegated: &String,
-    ) -> Status {
-        if user == "admin" || user == delegator || user == delegated {
-            if self.principals.contains_key(delegator) {
-                if let Some(pdelegated) = self.principals.get(delegated).cloned() {
-                    match pdelegated {
-                        VPrincipal::Admin(_) => {}
-                        VPrincipal::Anyone(ref p) | VPrincipal::User(ref p, _) => {
-                            let mut p = p.clone();
-                            let delegations = if let Target::Variable(variable) = target {
-                                if user == delegated
-                                    || self.check_right(variable, &Right::Delegate, user)
-                                {
-                                    let delegation = Delegation {
-                                        target: variable.clone(),
-                                        delegator: delegator.clone(),
-                                        right: right.clone(),
-                                    };
-                                    vec![delegation]
-                                } else {
-                                    return DENIED;
-                                }
-                            } else {
-                                self.variables
-                                    .keys()
-                                    .clone()
-                                    .filter_map(|variable| {
-                                        if user == delegated
-                                            || self.check_right(variable, &Right::Delegate, user)
-                                        {
-                                            Some(Delegation {
-                                                target: variable.clone(),
-                                                delegator: delegator.clone(),
-                                                right: right.clone(),
-                                            })
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect()
-                            };
-                            p.delegations.retain(|d| !delegations.contains(d));
-                            match pdelegated {
-                                VPrincipal::Anyone(_) => self
-                                    .principals
-                                    .insert("anyone".to_string(), VPrincipal::Anyone(p)),
-                                VPrincipal::User(_, hash) => self
-                                    .principals
-                                    .insert(p.name.clone(), VPrincipal::User(p, hash.clone())),
-                                _ => panic!(),
-                            };
-                            return SUCCESS;
-                        }
-                    }
-                }
-            }
+    ) -> Result<(), Error> {
+        if !self.principals.contains_key(delegator) {
+            return Err(Error::UnknownPrincipal(delegator.clone()));
+        }
+        let pdelegated = self
+            .principals
+            .get(delegated)
+            .cloned()
+            .ok_or_else(|| Error::UnknownPrincipal(delegated.clone()))?;
+        if let Target::Field(variable, field) = target {
+            self.check_field(variable, field)?;
         }
-        FAILED
+        if !self.is_superuser(user) && user != delegator && user != delegated {
+            return Err(Error::NotDelegator {
+                principal: user.clone(),
+                delegator: delegator.clone(),
+            });
+        }
+        let mut p = match pdelegated {
+            VPrincipal::Admin(_) => return Err(Error::AdminRights),
+            VPrincipal::Anyone(ref p)
+            | VPrincipal::User(ref p, _)
+            | VPrincipal::Group(ref p, _) => p.clone(),
+        };
+        let delegations = if let Some(variable) = &target.key() {
+            if user == delegated || self.check_right(variable, &Right::Delegate, user) {
+                vec![Delegation::new(variable, delegator, right)]
+            } else {
+                return Err(Error::MissingRight {
+                    principal: user.clone(),
+                    variable: variable.clone(),
+                    right: Right::Delegate,
+                });
+            }
+        } else {
+            let variables: Vec<String> = self.variables.keys().cloned().collect();
+            variables
+                .iter()
+                .filter_map(|variable| {
+                    if user == delegated || self.check_right(variable, &Right::Delegate, user) {
+                        Some(Delegation::new(variable, delegator, right))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        // limits are not part of what is revoked, so limited delegations are removed as well
+        p.delegations.retain(|d| {
+            !delegations
+                .iter()
+                .any(|r| r.target == d.target && r.delegator == d.delegator && r.right == d.right)
+        });
+        match pdelegated {
+            VPrincipal::Anyone(_) => self
+                .principals
+                .insert("anyone".to_string(), VPrincipal::Anyone(p)),
+            VPrincipal::User(_, hash) => self
+                .principals
+                .insert(p.name.clone(), VPrincipal::User(p, hash)),
+            VPrincipal::Group(_, group) => self
+                .principals
+                .insert(p.name.clone(), VPrincipal::Group(p, group)),
+            _ => panic!(),
+        };
+        Ok(())
     }
 
-    #[must_use]
-    pub fn set_default_delegator(&mut self, user: &String, delegator: &String) -> Status {
-        if user == "admin" {
+    #[allow(clippy::ptr_arg)]
+    pub fn set_default_delegator(
+        &mut self,
+        user: &String,
+        delegator: &String,
+    ) -> Result<(), Error> {
+        if self.is_superuser(user) {
             self.def_delegator = delegator.clone();
-            SUCCESS
+            Ok(())
         } else {
-            DENIED
+            Err(Error::NotAdmin(user.clone()))
         }
     }
 
-    #[must_use]
+    /// Denies `right` on the target to the principal, taking precedence over every delegation.
+    /// Only superusers may set denials. Setting a denial which is already in place has no effect.
+    pub fn deny(
+        &mut self,
+        user: &str,
+        target: &Target,
+        right: &Right,
+        principal: &String,
+    ) -> Result<(), Error> {
+        self.check_denial(user, target, principal)?;
+        let denial = Denial {
+            target: target.clone(),
+            right: right.clone(),
+            principal: principal.clone(),
+        };
+        if !self.denials.contains(&denial) {
+            self.denials.push(denial);
+        }
+        Ok(())
+    }
+
+    /// Lifts a denial set by [deny](#method.deny). Lifting a denial which is not in place has no
+    /// effect; in particular, a denial on `all` is not lifted for single variables.
+    pub fn undeny(
+        &mut self,
+        user: &str,
+        target: &Target,
+        right: &Right,
+        principal: &String,
+    ) -> Result<(), Error> {
+        self.check_denial(user, target, principal)?;
+        self.denials
+            .retain(|d| !(&d.target == target && &d.right == right && &d.principal == principal));
+        Ok(())
+    }
+
+    /// Every denial which applies to the target, in the order they were set: those on the target
+    /// itself, on the variable holding it or on its fields, and on `all`. For `all`, every denial
+    /// is listed. Only superusers may list denials.
+    pub fn denials(&self, user: &str, target: &Target) -> Result<Vec<&Denial>, Error> {
+        if !self.is_superuser(user) {
+            return Err(Error::NotAdmin(user.to_string()));
+        }
+        self.check_target(target)?;
+        Ok(self
+            .denials
+            .iter()
+            .filter(|d| target.key().is_none_or(|key| d.applies(&key)))
+            .collect())
+    }
+
+    /// Shared checks of [deny](#method.deny) and [undeny](#method.undeny).
+    fn check_denial(&self, user: &str, target: &Target, principal: &String) -> Result<(), Error> {
+        if !self.is_superuser(user) {
+            return Err(Error::NotAdmin(user.to_string()));
+        }
+        match self.principals.get(principal) {
+            None => return Err(Error::UnknownPrincipal(principal.clone())),
+            Some(VPrincipal::Admin(_)) => return Err(Error::AdminRights),
+            Some(_) => {}
+        }
+        self.check_target(target)
+    }
+
+    /// Checks that the variable or field targeted exists. Patterns need not match anything.
+    fn check_target(&self, target: &Target) -> Result<(), Error> {
+        match target {
+            Target::All | Target::Pattern(_) => Ok(()),
+            Target::Variable(variable) if self.variables.contains_key(variable) => Ok(()),
+            Target::Variable(variable) => Err(Error::UnknownVariable(variable.clone())),
+            Target::Field(variable, field) => self.check_field(variable, field).map(|_| ()),
+        }
+    }
+
+    /// Creates a user with the password, which must satisfy the password policy.
     pub fn create_principal(
         &mut self,
         user: &String,
         principal: &String,
-        hash: &[u8; 32],
-    ) -> Status {
-        if user != "admin" {
-            DENIED
+        password: &str,
+    ) -> Result<(), Error> {
+        if !self.is_superuser(user) {
+            Err(Error::NotAdmin(user.clone()))
         } else if self.principals.contains_key(principal) {
-            FAILED
+            Err(Error::PrincipalExists(principal.clone()))
         } else {
+            self.check_password(principal, password)?;
+            let hash = hash(password.to_string());
             let name = principal;
             let principal = Principal {
                 name: name.clone(),
                 delegations: Vec::new(),
+                disabled: false,
+                superuser: false,
             };
             if self.principals.contains_key(&self.def_delegator) {
-                self.principals.insert(
-                    principal.name.clone(),
-                    VPrincipal::User(principal, hash.clone()),
-                );
+                self.principals
+                    .insert(principal.name.clone(), VPrincipal::User(principal, hash));
                 for right in &[Right::Read, Right::Write, Right::Append, Right::Delegate] {
-                    match self.delegate(
-                        user,
-                        &Target::All,
-                        &self.def_delegator.clone(),
-                        right,
-                        name,
-                    ) {
-                        SUCCESS => {}
-                        _ => panic!(),
-                    }
+                    self.delegate(user, &Target::All, &self.def_delegator.clone(), right, name)
+                        .expect("Delegation from the default delegator failed.");
                 }
-                SUCCESS
+                Ok(())
             } else {
-                FAILED
+                Err(Error::UnknownPrincipal(self.def_delegator.clone()))
             }
         }
     }
 
-    #[must_use]
+    /// Changes the principal's password. The new password must satisfy the password policy, and
+    /// may not repeat as many of the principal's recent passwords as the policy remembers.
     pub fn change_password(
         &mut self,
         user: &String,
         principal: &String,
-        hash: &[u8; 32],
-    ) -> Status {
-        if user == "admin" || user == principal {
-            if let Some(principal) = self.principals.get_mut(principal) {
-                match principal {
-                    VPrincipal::User(_, ref mut existing) | VPrincipal::Admin(ref mut existing) => {
-                        *existing = hash.clone();
-                        SUCCESS
+        password: &str,
+    ) -> Result<(), Error> {
+        if !self.is_superuser(user) && user != principal {
+            return Err(Error::NotAdmin(user.clone()));
+        }
+        let current = match self.principals.get(principal) {
+            Some(VPrincipal::User(_, existing)) | Some(VPrincipal::Admin(existing)) => *existing,
+            Some(VPrincipal::Anyone(_)) | Some(VPrincipal::Group(_, _)) => {
+                return Err(Error::NoPassword(principal.clone()))
+            }
+            None => return Err(Error::UnknownPrincipal(principal.clone())),
+        };
+        self.check_password(principal, password)?;
+        let hash = hash(password.to_string());
+        let remembered = self.password_policy.history;
+        let mut history = self.history.get(principal).cloned().unwrap_or_default();
+        history.insert(0, current);
+        history.truncate(remembered);
+        if history.contains(&hash) {
+            return Err(Error::WeakPassword {
+                principal: principal.clone(),
+                weakness: Weakness::Reused,
+            });
+        }
+        history.truncate(remembered.saturating_sub(1));
+        if history.is_empty() {
+            self.history.remove(principal);
+        } else {
+            self.history.insert(principal.clone(), history);
+        }
+        match self.principals.get_mut(principal) {
+            Some(VPrincipal::User(_, existing)) | Some(VPrincipal::Admin(existing)) => {
+                *existing = hash
+            }
+            _ => unreachable!(),
+        }
+        // tokens handed out under the old password are no longer good
+        self.sessions.retain(|_, s| &s.principal != principal);
+        Ok(())
+    }
+
+    fn check_password(&self, principal: &str, password: &str) -> Result<(), Error> {
+        self.password_policy
+            .check(principal, password)
+            .map_err(|weakness| Error::WeakPassword {
+                principal: principal.to_string(),
+                weakness,
+            })
+    }
+
+    /// Removes the principal and every delegation it made or was given, and every denial naming
+    /// it, and takes it out of every group. Rights which others held only through the principal
+    /// are lost with it. Groups it owned are handed to admin, and if it was the default delegator,
+    /// the default delegator becomes `anyone` again. Its sessions are closed.
+    pub fn delete_principal(&mut self, user: &str, principal: &String) -> Result<(), Error> {
+        self.check_removable(user, principal)?;
+        self.principals.remove(principal);
+        for other in self.principals.values_mut() {
+            match other {
+                VPrincipal::Admin(_) => {}
+                VPrincipal::Anyone(p) | VPrincipal::User(p, _) => {
+                    p.delegations.retain(|d| &d.delegator != principal);
+                }
+                VPrincipal::Group(p, group) => {
+                    p.delegations.retain(|d| &d.delegator != principal);
+                    group.members.retain(|m| m != principal);
+                    if &group.owner == principal {
+                        group.owner = "admin".to_string();
                     }
-                    VPrincipal::Anyone(_) => FAILED,
                 }
-            } else {
-                FAILED
             }
+        }
+        if &self.def_delegator == principal {
+            self.def_delegator = "anyone".to_string();
+        }
+        self.sessions.retain(|_, s| &s.principal != principal);
+        self.history.remove(principal);
+        self.denials.retain(|d| &d.principal != principal);
+        Ok(())
+    }
+
+    /// Blocks or unblocks logins of the principal. Its delegations are kept, and rights still flow
+    /// through it to the principals it delegated to.
+    pub fn set_disabled(
+        &mut self,
+        user: &str,
+        principal: &String,
+        disabled: bool,
+    ) -> Result<(), Error> {
+        self.check_removable(user, principal)?;
+        if let Some(VPrincipal::User(p, _)) = self.principals.get_mut(principal) {
+            p.disabled = disabled;
+        }
+        Ok(())
+    }
+
+    /// Grants the principal every right and permission admin has, or takes them away again when
+    /// `superuser` is false. Only users may become superusers, and admin always is one.
+    pub fn set_superuser(
+        &mut self,
+        user: &str,
+        principal: &String,
+        superuser: bool,
+    ) -> Result<(), Error> {
+        self.check_removable(user, principal)?;
+        match self.principals.get_mut(principal) {
+            Some(VPrincipal::User(p, _)) => {
+                p.superuser = superuser;
+                Ok(())
+            }
+            _ => Err(Error::NotUser(principal.clone())),
+        }
+    }
+
+    /// Whether the principal exists and may do everything admin may. Every check on what the
+    /// acting principal may do asks this, rather than comparing its name with admin.
+    pub fn is_superuser(&self, principal: &str) -> bool {
+        self.principals
+            .get(principal)
+            .is_some_and(VPrincipal::superuser)
+    }
+
+    /// Creates a group, whose membership may be managed by superusers and by `owner`.
+    pub fn create_group(
+        &mut self,
+        user: &str,
+        group: &String,
+        owner: &String,
+    ) -> Result<(), Error> {
+        if !self.is_superuser(user) {
+            Err(Error::NotAdmin(user.to_string()))
+        } else if self.principals.contains_key(group) {
+            Err(Error::PrincipalExists(group.clone()))
+        } else {
+            match self.principals.get(owner) {
+                None => Err(Error::UnknownPrincipal(owner.clone())),
+                Some(VPrincipal::Admin(_)) | Some(VPrincipal::User(_, _)) => {
+                    let principal = Principal {
+                        name: group.clone(),
+                        delegations: Vec::new(),
+                        disabled: false,
+                        superuser: false,
+                    };
+                    let group = Group {
+                        owner: owner.clone(),
+                        members: Vec::new(),
+                    };
+                    self.principals
+                        .insert(principal.name.clone(), VPrincipal::Group(principal, group));
+                    Ok(())
+                }
+                Some(_) => Err(Error::NotUser(owner.clone())),
+            }
+        }
+    }
+
+    /// Adds `member` to the group, or removes it when `member_of` is false. Groups may be members
+    /// of other groups.
+    pub fn set_member_of(
+        &mut self,
+        user: &String,
+        group: &String,
+        member: &String,
+        member_of: bool,
+    ) -> Result<(), Error> {
+        match self.principals.get(member) {
+            None => return Err(Error::UnknownPrincipal(member.clone())),
+            Some(VPrincipal::Admin(_)) | Some(VPrincipal::Anyone(_)) => {
+                return Err(Error::Builtin(member.clone()))
+            }
+            Some(_) => {}
+        }
+        let superuser = self.is_superuser(user);
+        match self.principals.get_mut(group) {
+            None => Err(Error::UnknownPrincipal(group.clone())),
+            Some(VPrincipal::Group(_, g)) => {
+                if !superuser && user != &g.owner {
+                    Err(Error::NotOwner {
+                        principal: user.clone(),
+                        group: group.clone(),
+                    })
+                } else {
+                    g.members.retain(|m| m != member);
+                    if member_of {
+                        g.members.push(member.clone());
+                    }
+                    Ok(())
+                }
+            }
+            Some(_) => Err(Error::NotGroup(group.clone())),
+        }
+    }
+
+    fn check_removable(&self, user: &str, principal: &String) -> Result<(), Error> {
+        if !self.is_superuser(user) {
+            Err(Error::NotAdmin(user.to_string()))
         } else {
-            DENIED
+            match self.principals.get(principal) {
+                None => Err(Error::UnknownPrincipal(principal.clone())),
+                Some(VPrincipal::User(_, _)) | Some(VPrincipal::Group(_, _)) => Ok(()),
+                Some(_) => Err(Error::Builtin(principal.clone())),
+            }
         }
     }
 
+    // takes the same argument types as the public methods, which is what the tests pass
+    #[allow(clippy::ptr_arg)]
     #[must_use]
-    fn check_right(&self, target: &String, right: &Right, principal: &String) -> bool {
+    fn check_right(&mut self, target: &String, right: &Right, principal: &String) -> bool {
         let principal = self
             .principals
             .get(principal)
             .expect("Precondition of principal existence not met.");
-        self.direct_check_right(target, right, principal)
+        let relied = self.reliance(target, right, principal);
+        self.rely(relied)
     }
 
     #[must_use]
-    fn direct_check_right(&self, target: &String, right: &Right, principal: &VPrincipal) -> bool {
-        match principal {
-            VPrincipal::Admin(_) => true,
-            VPrincipal::Anyone(p) | VPrincipal::User(p, _) => {
-                let mut searched: Vec<&Delegation> = Vec::new();
-                let mut searching: VecDeque<&Delegation> = p
-                    .delegations
-                    .iter()
-                    .filter(|d| &d.target == target && &d.right == right)
-                    .collect();
-                while !searching.is_empty() {
-                    let curr = searching.pop_front().unwrap(); // guaranteed by while conditionx
-                    if searched.contains(&curr) {
-                        continue;
+    fn direct_check_right(&mut self, target: &str, right: &Right, principal: &VPrincipal) -> bool {
+        let relied = self.reliance(target, right, principal);
+        self.rely(relied)
+    }
+
+    /// Notes the use-limited delegations a right check relied on, for the operation to use once
+    /// each when it ends, and returns whether the right is held.
+    fn rely(&mut self, relied: Option<Vec<(String, Delegation)>>) -> bool {
+        match relied {
+            Some(relied) => {
+                for relied in relied {
+                    if !self.relied.contains(&relied) {
+                        self.relied.push(relied);
                     }
-                    searched.push(curr);
-                    match self.principals.get(&curr.delegator).unwrap() {
-                        VPrincipal::Admin(_) => return true,
-                        VPrincipal::Anyone(p) | VPrincipal::User(p, _) => p
-                            .delegations
-                            .iter()
-                            .filter(|d| &d.target == target && &d.right == right)
-                            .for_each(|d| searching.push_back(d)),
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Searches breadth-first from the principal towards admin, following each live delegation of
+    /// the right on the target to its delegator, and each membership to the group. If admin or
+    /// another superuser is reached, the use-limited delegations followed on the way are returned
+    /// with the principal holding each, and if not the principal does not hold the right.
+    /// Principals denied the right are not followed, so a denial cuts off every principal whose
+    /// rights flow through the denied one.
+    fn reliance(
+        &self,
+        target: &str,
+        right: &Right,
+        principal: &VPrincipal,
+    ) -> Option<Vec<(String, Delegation)>> {
+        let now = self.clock.now();
+        let mut reached: Vec<(&VPrincipal, Step)> = vec![(principal, None)];
+        let mut next = 0;
+        while next < reached.len() {
+            if reached[next].0.superuser() {
+                let mut relied = Vec::new();
+                let mut step = reached[next].1;
+                while let Some((from, delegation)) = step {
+                    if let Some(delegation) = delegation.filter(|d| d.uses.is_some()) {
+                        relied.push((reached[from].0.to_string(), delegation.clone()));
                     }
+                    step = reached[from].1;
+                }
+                return Some(relied);
+            }
+            let p = match reached[next].0 {
+                VPrincipal::Admin(_) => unreachable!("admin is a superuser"),
+                VPrincipal::Anyone(p) | VPrincipal::User(p, _) | VPrincipal::Group(p, _) => p,
+            };
+            if self.denied(target, right, &p.name) {
+                next += 1;
+                continue;
+            }
+            let delegators = p
+                .delegations
+                .iter()
+                .filter(|d| covers(&d.target, target) && &d.right == right && d.live(now))
+                .map(|d| (self.principals.get(&d.delegator).unwrap(), Some(d)));
+            let groups = self
+                .principals
+                .values()
+                .filter(|g| match g {
+                    VPrincipal::Group(_, group) => group.members.contains(&p.name),
+                    _ => false,
+                })
+                .map(|g| (g, None));
+            for (principal, delegation) in delegators.chain(groups).collect::<Vec<_>>() {
+                if !reached.iter().any(|(r, _)| std::ptr::eq(*r, principal)) {
+                    reached.push((principal, Some((next, delegation))));
                 }
-                false
             }
+            next += 1;
         }
+        None
     }
 
-    #[must_use]
-    pub fn set(&mut self, user: &String, variable: &String, value: &Value) -> Status {
+    fn denied(&self, target: &str, right: &Right, principal: &str) -> bool {
+        self.denials.iter().any(|d| {
+            &d.right == right
+                && (d.principal == principal || d.principal == "anyone")
+                && d.applies(target)
+        })
+    }
+
+    /// Removes every delegation which has expired or been used up, and every expired session. These
+    /// are already ignored, so this only reclaims their space.
+    pub fn sweep(&mut self) {
+        let now = self.clock.now();
+        let mut delegations = 0;
+        for principal in self.principals.values_mut() {
+            if let VPrincipal::Anyone(p) | VPrincipal::User(p, _) | VPrincipal::Group(p, _) =
+                principal
+            {
+                let before = p.delegations.len();
+                p.delegations.retain(|d| d.live(now));
+                delegations += before - p.delegations.len();
+            }
+        }
+        let sessions = self.sessions.len();
+        self.sessions.retain(|_, s| now < s.expires);
+        debug!(
+            "swept",
+            delegations = delegations,
+            sessions = sessions - self.sessions.len()
+        );
+    }
+
+    pub fn set(&mut self, user: &String, variable: &String, value: &Value) -> Result<(), Error> {
         if !self.variables.contains_key(variable) {
             self.variables.insert(variable.clone(), value.clone());
+            self.record(user, variable);
             for right in &[Right::Read, Right::Write, Right::Append, Right::Delegate] {
-                match self.delegate(
+                self.delegate(
                     &"admin".to_string(),
                     &Target::All,
                     &"admin".to_string(),
                     right,
                     user,
-                ) {
-                    SUCCESS => {}
-                    _ => panic!(),
-                }
+                )
+                .expect("Delegation to the creator of a variable failed.");
             }
-            SUCCESS
+            Ok(())
         } else if self.check_right(variable, &Right::Write, user) {
             self.variables.insert(variable.clone(), value.clone());
-            SUCCESS
+            self.record(user, variable);
+            Ok(())
         } else {
-            DENIED
+            Err(Error::MissingRight {
+                principal: user.clone(),
+                variable: variable.clone(),
+                right: Right::Write,
+            })
         }
     }
 
-    #[must_use]
+    #[allow(clippy::ptr_arg)]
     pub fn set_member(
         &mut self,
         user: &String,
         variable: &String,
         member: &String,
         value: &String,
-    ) -> Status {
-        if let Some(existing) = self.variables.get(variable).cloned() {
-            match existing {
-                Value::FieldVals(mut fv) => {
-                    if let Some(existing) = fv.get_mut(member) {
-                        if self.check_right(variable, &Right::Write, user) {
-                            *existing = value.clone();
-                            self.variables
-                                .insert(variable.clone(), Value::FieldVals(fv));
-                            SUCCESS
-                        } else {
-                            DENIED
-                        }
+    ) -> Result<(), Error> {
+        let existing = self
+            .variables
+            .get(variable)
+            .cloned()
+            .ok_or_else(|| Error::UnknownVariable(variable.clone()))?;
+        match existing {
+            Value::FieldVals(mut fv) => {
+                let key = Target::Field(variable.clone(), member.clone())
+                    .key()
+                    .unwrap();
+                if let Some(existing) = fv.get_mut(member) {
+                    if self.check_right(&key, &Right::Write, user) {
+                        *existing = value.clone();
+                        self.variables
+                            .insert(variable.clone(), Value::FieldVals(fv));
+                        self.record(user, variable);
+                        Ok(())
                     } else {
-                        FAILED
+                        Err(Error::MissingRight {
+                            principal: user.clone(),
+                            variable: key,
+                            right: Right::Write,
+                        })
                     }
+                } else {
+                    Err(Error::UnknownField {
+                        variable: variable.clone(),
+                        field: member.clone(),
+                    })
                 }
-                _ => FAILED,
             }
-        } else {
-            FAILED
+            other => Err(Error::TypeMismatch {
+                variable: variable.clone(),
+                expected: "record",
+                found: other.type_name(),
+            }),
         }
     }
 
-    #[must_use]
-    pub fn append(&mut self, user: &String, variable: &String, value: &Value) -> Status {
-        match value {
-            Value::Immediate(_) | Value::FieldVals(_) => {
-                if let Some(existing) = self.variables.get(variable).cloned() {
-                    if self.check_right(variable, &Right::Write, user)
-                        || self.check_right(variable, &Right::Append, user)
-                    {
-                        if let Value::List(mut list) = existing {
-                            list.push(value.clone());
-                            self.variables.insert(variable.clone(), Value::List(list));
-                            SUCCESS
-                        } else {
-                            FAILED
+    pub fn append(&mut self, user: &String, variable: &String, value: &Value) -> Result<(), Error> {
+        let existing = self
+            .variables
+            .get(variable)
+            .cloned()
+            .ok_or_else(|| Error::UnknownVariable(variable.clone()))?;
+        if !(self.check_right(variable, &Right::Write, user)
+            || self.check_right(variable, &Right::Append, user))
+        {
+            return Err(Error::MissingRight {
+                principal: user.clone(),
+                variable: variable.clone(),
+                right: Right::Append,
+            });
+        }
+        match existing {
+            Value::List(mut elist) => {
+                match value {
+                    Value::Immediate(_) | Value::FieldVals(_) => elist.push(value.clone()),
+                    Value::List(list) => {
+                        for item in list {
+                            elist.push(item.clone());
                         }
-                    } else {
-                        DENIED
                     }
-                } else {
-                    FAILED
                 }
+                self.variables.insert(variable.clone(), Value::List(elist));
+                self.record(user, variable);
+                Ok(())
             }
-            Value::List(list) => {
-                if let Some(existing) = self.variables.get(variable).cloned() {
-                    if self.check_right(variable, &Right::Write, user)
-                        || self.check_right(variable, &Right::Append, user)
-                    {
-                        if let Value::List(mut elist) = existing {
-                            for item in list {
-                                elist.push(item.clone());
-                            }
-                            self.variables.insert(variable.clone(), Value::List(elist));
-                            SUCCESS
-                        } else {
-                            FAILED
-                        }
-                    } else {
-                        DENIED
-                    }
-                } else {
-                    FAILED
-                }
+            other => Err(Error::TypeMismatch {
+                variable: variable.clone(),
+                expected: "list",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// Removes the variable and its history, along with every delegation and denial whose target
+    /// it or one of its fields is.
+    pub fn delete(&mut self, user: &String, variable: &String) -> Result<(), Error> {
+        if !self.variables.contains_key(variable) {
+            return Err(Error::UnknownVariable(variable.clone()));
+        } else if !self.check_right(variable, &Right::Write, user) {
+            return Err(Error::MissingRight {
+                principal: user.clone(),
+                variable: variable.clone(),
+                right: Right::Write,
+            });
+        }
+        self.variables.remove(variable);
+        self.versions.remove(variable);
+        self.metadata.remove(variable);
+        for principal in self.principals.values_mut() {
+            if let VPrincipal::Anyone(p) | VPrincipal::User(p, _) | VPrincipal::Group(p, _) =
+                principal
+            {
+                p.delegations.retain(|d| !covers(variable, &d.target));
             }
         }
+        self.denials
+            .retain(|d| d.target.key().is_none_or(|key| !covers(variable, &key)));
+        Ok(())
     }
 
-    #[must_use]
-    pub fn get(&self, user: &String, variable: &String) -> Result<&Value, Status> {
+    /// Adds the current value of the variable to its history, as written by the principal, and
+    /// updates its metadata. A program which writes a variable several times leaves only its last
+    /// value.
+    fn record(&mut self, user: &str, variable: &str) {
+        let (now, size) = (self.clock.now(), self.variables[variable].size());
+        self.metadata
+            .entry(variable.to_string())
+            .and_modify(|m| {
+                m.writer = user.to_string();
+                m.modified = now;
+                m.size = size;
+            })
+            .or_insert_with(|| Metadata {
+                creator: user.to_string(),
+                writer: user.to_string(),
+                created: now,
+                modified: now,
+                size,
+            });
+        let version = Version {
+            sequence: self.sequence,
+            principal: user.to_string(),
+            value: self.variables[variable].clone(),
+        };
+        let history = self.versions.entry(variable.to_string()).or_default();
+        if history
+            .last()
+            .is_some_and(|v| v.sequence == version.sequence)
+        {
+            history.pop();
+        }
+        history.push(version);
+        self.history_policy.trim(history);
+    }
+
+    /// The versions of the variable still kept, oldest first, which needs read permission on the
+    /// variable as it is now.
+    pub fn history(&mut self, user: &String, variable: &String) -> Result<&[Version], Error> {
+        self.get(user, variable)?;
+        Ok(self.versions.get(variable).map_or(&[], Vec::as_slice))
+    }
+
+    /// Like [history](#method.history), but without using up any use-limited delegation the read
+    /// relies on, for looking at the committed state from outside a program, as when telling
+    /// watchers of changes.
+    pub fn peek_history(&self, user: &str, variable: &str) -> Result<&[Version], Error> {
+        let principal = self
+            .principals
+            .get(user)
+            .ok_or_else(|| Error::UnknownPrincipal(user.to_string()))?;
         if !self.variables.contains_key(variable) {
-            Err(FAILED)
+            Err(Error::UnknownVariable(variable.to_string()))
+        } else if self.reliance(variable, &Right::Read, principal).is_none() {
+            Err(Error::MissingRight {
+                principal: user.to_string(),
+                variable: variable.to_string(),
+                right: Right::Read,
+            })
+        } else {
+            Ok(self.versions.get(variable).map_or(&[], Vec::as_slice))
+        }
+    }
+
+    /// Who created the variable and who last wrote it, and when, which needs read permission on
+    /// the variable.
+    pub fn metadata(&mut self, user: &String, variable: &String) -> Result<&Metadata, Error> {
+        self.get(user, variable)?;
+        Ok(&self.metadata[variable])
+    }
+
+    /// The value the variable held once the program with commit sequence number `sequence` had
+    /// run, which needs read permission on the variable as it is now.
+    pub fn get_version(
+        &mut self,
+        user: &String,
+        variable: &String,
+        sequence: u64,
+    ) -> Result<&Value, Error> {
+        self.history(user, variable)?
+            .iter()
+            .rev()
+            .find(|v| v.sequence <= sequence)
+            .map(|v| &v.value)
+            .ok_or_else(|| Error::UnknownVersion {
+                variable: variable.clone(),
+                sequence,
+            })
+    }
+
+    pub fn get(&mut self, user: &String, variable: &String) -> Result<&Value, Error> {
+        if !self.variables.contains_key(variable) {
+            Err(Error::UnknownVariable(variable.clone()))
         } else if self.check_right(variable, &Right::Read, user) {
             Ok(self.variables.get(variable).unwrap())
         } else {
-            Err(DENIED)
+            Err(Error::MissingRight {
+                principal: user.clone(),
+                variable: variable.clone(),
+                right: Right::Read,
+            })
+        }
+    }
+
+    /// Reads a single field of a record, which needs read permission on either the field or the
+    /// whole variable.
+    pub fn get_member(
+        &mut self,
+        user: &String,
+        variable: &String,
+        field: &String,
+    ) -> Result<&String, Error> {
+        if !self.variables.contains_key(variable) {
+            return Err(Error::UnknownVariable(variable.clone()));
+        }
+        let key = Target::Field(variable.clone(), field.clone())
+            .key()
+            .unwrap();
+        if !self.check_right(&key, &Right::Read, user) {
+            return Err(Error::MissingRight {
+                principal: user.clone(),
+                variable: key,
+                right: Right::Read,
+            });
+        }
+        self.check_field(variable, field)
+    }
+
+    /// Reads a whole variable. If the principal may only read some fields of a record, the
+    /// [field policy](enum.FieldPolicy.html) decides the outcome.
+    pub fn read(&mut self, user: &String, variable: &String) -> Result<Value, Error> {
+        match self.get(user, variable).cloned() {
+            Err(error @ Error::MissingRight { .. })
+                if self.field_policy == FieldPolicy::Project =>
+            {
+                if let Some(Value::FieldVals(fv)) = self.variables.get(variable).cloned() {
+                    let mut readable = HashMap::new();
+                    for (field, value) in fv {
+                        let key = Target::Field(variable.clone(), field.clone());
+                        if self.check_right(&key.key().unwrap(), &Right::Read, user) {
+                            readable.insert(field, value);
+                        }
+                    }
+                    if !readable.is_empty() {
+                        return Ok(Value::FieldVals(readable));
+                    }
+                }
+                Err(error)
+            }
+            result => result,
+        }
+    }
+
+    fn check_field(&self, variable: &String, field: &String) -> Result<&String, Error> {
+        match self.variables.get(variable) {
+            None => Err(Error::UnknownVariable(variable.clone())),
+            Some(Value::FieldVals(fv)) => fv.get(field).ok_or_else(|| Error::UnknownField {
+                variable: variable.clone(),
+                field: field.clone(),
+            }),
+            Some(other) => Err(Error::TypeMismatch {
+                variable: variable.clone(),
+                expected: "record",
+                found: other.type_name(),
+            }),
         }
     }
 
     pub fn contains(&self, variable: &String) -> bool {
         self.variables.contains_key(variable)
     }
+
+    pub fn contains_principal(&self, principal: &str) -> bool {
+        self.principals.contains_key(principal)
+    }
 }
 
 #[cfg(test)]
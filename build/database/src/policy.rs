@@ -0,0 +1,143 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A kind of character a password may be required to contain.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    /// Anything other than a letter or digit, such as a space or punctuation.
+    Symbol,
+}
+
+impl CharClass {
+    fn matches(self, c: char) -> bool {
+        match self {
+            CharClass::Lowercase => c.is_ascii_lowercase(),
+            CharClass::Uppercase => c.is_ascii_uppercase(),
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Symbol => !c.is_ascii_alphanumeric(),
+        }
+    }
+}
+
+/// The rules new passwords are held to by `create principal` and `change password`. The default
+/// policy accepts every password, as the specification requires; the admin password given on the
+/// command line is never checked.
+///
+/// A policy can also be read from a comma separated list of rules, as the server does with the
+/// `BIBIFI_PASSWORD_POLICY` environment variable:
+///
+/// ```
+/// use bibifi_database::{CharClass, PasswordPolicy};
+///
+/// let policy: PasswordPolicy = "min_length=12,digit,symbol,deny_name,deny=admin,history=3"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(12, policy.min_length);
+/// assert_eq!(vec![CharClass::Digit, CharClass::Symbol], policy.classes);
+/// assert!(policy.check("bob", "correct horse 9").is_ok());
+/// assert!(policy.check("bob", "Admin").is_err());
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct PasswordPolicy {
+    /// The fewest characters a password may have.
+    pub min_length: usize,
+    /// The kinds of character a password must contain at least one of.
+    pub classes: Vec<CharClass>,
+    /// Passwords which are refused outright, ignoring case.
+    pub denied: Vec<String>,
+    /// Whether a principal's password may not be its own name, ignoring case.
+    pub deny_name: bool,
+    /// How many of a principal's most recent passwords, including the current one, a new
+    /// password may not repeat. Only this many are remembered.
+    pub history: usize,
+}
+
+impl PasswordPolicy {
+    /// Checks a new password for the principal against every rule except the history, which
+    /// only the database knows.
+    pub fn check(&self, principal: &str, password: &str) -> Result<(), Weakness> {
+        if password.len() < self.min_length {
+            return Err(Weakness::TooShort(self.min_length));
+        }
+        if let Some(class) = self
+            .classes
+            .iter()
+            .find(|class| !password.chars().any(|c| class.matches(c)))
+        {
+            return Err(Weakness::Missing(*class));
+        }
+        if (self.deny_name && password.eq_ignore_ascii_case(principal))
+            || self
+                .denied
+                .iter()
+                .any(|denied| password.eq_ignore_ascii_case(denied))
+        {
+            return Err(Weakness::Denied);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PasswordPolicy {
+    type Err = String;
+
+    fn from_str(rules: &str) -> Result<Self, Self::Err> {
+        let mut policy = PasswordPolicy::default();
+        let number = |rule: &str, value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("{} is not a number in rule {}", value, rule))
+        };
+        for rule in rules
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            match rule.split_once('=') {
+                Some(("min_length", n)) => policy.min_length = number(rule, n)?,
+                Some(("history", n)) => policy.history = number(rule, n)?,
+                Some(("deny", word)) => policy.denied.push(word.to_string()),
+                None if rule == "lowercase" => policy.classes.push(CharClass::Lowercase),
+                None if rule == "uppercase" => policy.classes.push(CharClass::Uppercase),
+                None if rule == "digit" => policy.classes.push(CharClass::Digit),
+                None if rule == "symbol" => policy.classes.push(CharClass::Symbol),
+                None if rule == "deny_name" => policy.deny_name = true,
+                _ => return Err(format!("unknown password rule {}", rule)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// The reason a password does not satisfy the [policy](struct.PasswordPolicy.html).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Weakness {
+    /// The password has fewer characters than the minimum given.
+    TooShort(usize),
+    /// The password has no character of the class.
+    Missing(CharClass),
+    /// The password is on the denylist, or is the principal's name.
+    Denied,
+    /// The password is one of the principal's recent passwords.
+    Reused,
+}
+
+impl fmt::Display for Weakness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Weakness::TooShort(min) => write!(f, "is shorter than {} characters", min),
+            Weakness::Missing(class) => {
+                write!(
+                    f,
+                    "has no {} character",
+                    format!("{:?}", class).to_lowercase()
+                )
+            }
+            Weakness::Denied => write!(f, "is not allowed"),
+            Weakness::Reused => write!(f, "was used recently"),
+        }
+    }
+}
@@ -1,71 +1,73 @@
+#![allow(clippy::bool_assert_comparison)]
+
 use super::*;
-use std::error::Error;
+use std::error::Error as StdError;
 
 use bibifi_util::hash;
 
 #[test]
 // test all basic stuff
-fn basic_full_1() -> Result<(), Box<dyn Error>> {
+fn basic_full_1() -> Result<(), Box<dyn StdError>> {
     let mut my_database = Database::new(hash("wolla".to_string()));
 
     //admin with correct password checks true
     assert_eq!(
         my_database.check_pass(&"admin".to_string(), &hash("wolla".to_string())),
-        SUCCESS
+        Ok(())
     );
 
     //admin with wrong password checks false
     assert_eq!(
         my_database.check_pass(&"admin".to_string(), &hash("wollabig".to_string())),
-        DENIED
+        Err(Error::BadPassword("admin".to_string()))
     );
 
     //anyone principal rejected with any password
     assert_eq!(
         my_database.check_pass(&"anyone".to_string(), &hash("".to_string())),
-        DENIED
+        Err(Error::BadPassword("anyone".to_string()))
     );
 
     //check non admin principal
     assert_eq!(
         my_database.check_pass(&"not_admin".to_string(), &hash("wolla".to_string())),
-        FAILED
+        Err(Error::UnknownPrincipal("not_admin".to_string()))
     );
 
     //add principals to database
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"bob".to_string(),
-        &hash("".to_string()),
-    ), SUCCESS); // empty string password
+        "",
+    ), Ok(())); // empty string password
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"tom".to_string(),
-        &hash("tom_pass".to_string()),
-    ), SUCCESS);
+        "tom_pass",
+    ), Ok(()));
 
     //principal with correct password checks true
     assert_eq!(
         my_database.check_pass(&"bob".to_string(), &hash("".to_string())),
-        SUCCESS
+        Ok(())
     );
     assert_eq!(
         my_database.check_pass(&"tom".to_string(), &hash("tom_pass".to_string())),
-        SUCCESS
+        Ok(())
     );
 
     //principal with wrong password checks false
     assert_eq!(
         my_database.check_pass(&"bob".to_string(), &hash("wolla".to_string())),
-        DENIED
+        Err(Error::BadPassword("bob".to_string()))
     );
     assert_eq!(
         my_database.check_pass(&"tom".to_string(), &hash("".to_string())),
-        DENIED
+        Err(Error::BadPassword("tom".to_string()))
     );
     assert_eq!(
         my_database.check_pass(&"tom".to_string(), &hash("tom".to_string())),
-        DENIED
+        Err(Error::BadPassword("tom".to_string()))
     );
 
     // lets say, bob created my_var and delegated all permissions to everyone
@@ -73,7 +75,7 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
         &"bob".to_string(),
         &"my_var1".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
     // rights delegated automatically, hopefully
 
     assert_eq!(my_database.delegate(
@@ -82,28 +84,28 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
         &"bob".to_string(),
         &Right::Read,
         &"anyone".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
     assert_eq!(my_database.delegate(
         &"admin".to_string(),
         &Target::Variable("my_var1".to_string()),
         &"bob".to_string(),
         &Right::Write,
         &"anyone".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
     assert_eq!(my_database.delegate(
         &"admin".to_string(),
         &Target::Variable("my_var1".to_string()),
         &"bob".to_string(),
         &Right::Append,
         &"anyone".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
     assert_eq!(my_database.delegate(
         &"admin".to_string(),
         &Target::Variable("my_var1".to_string()),
         &"bob".to_string(),
         &Right::Delegate,
         &"anyone".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     // check for correct permissions
     assert_eq!(
@@ -171,8 +173,8 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"alice".to_string(),
-        &hash("alice_pass".to_string()),
-    ), SUCCESS);
+        "alice_pass",
+    ), Ok(()));
 
     // check for correct permissions
     assert_eq!(
@@ -201,24 +203,24 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
         &"bob".to_string(),
         &"my_var2".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //change the default
-    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), SUCCESS);
+    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), Ok(()));
 
     //change bob's password
     assert_eq!(my_database.change_password(
         &"bob".to_string(),
         &"bob".to_string(),
-        &hash("bob_new_pass".to_string()),
-    ), SUCCESS);
+        "bob_new_pass",
+    ), Ok(()));
     assert_eq!(
         my_database.check_pass(&"bob".to_string(), &hash("bob_new_pass".to_string())),
-        SUCCESS
+        Ok(())
     );
     assert_eq!(
         my_database.check_pass(&"bob".to_string(), &hash("bob_pass".to_string())),
-        DENIED
+        Err(Error::BadPassword("bob".to_string()))
     );
 
     Ok(())
@@ -226,58 +228,58 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
 
 #[test]
 // test all basic stuff
-fn basic_full_2() -> Result<(), Box<dyn Error>> {
+fn basic_full_2() -> Result<(), Box<dyn StdError>> {
     let mut my_database = Database::new(hash("wolla".to_string()));
 
     //add principals to database
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"bob".to_string(),
-        &hash("".to_string()),
-    ), SUCCESS); // empty string password
+        "",
+    ), Ok(())); // empty string password
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"tom".to_string(),
-        &hash("tom_pass".to_string()),
-    ), SUCCESS);
+        "tom_pass",
+    ), Ok(()));
 
     // lets say, bob created my_var and delegated all permissions to everyone
     assert_eq!(my_database.set(
         &"bob".to_string(),
         &"my_var1".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //add principals to database after anyone has some permissions
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"alice".to_string(),
-        &hash("alice_pass".to_string()),
-    ), SUCCESS);
+        "alice_pass",
+    ), Ok(()));
 
     //alice created my_var2
     assert_eq!(my_database.set(
         &"alice".to_string(),
         &"my_var2".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //change the default
-    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), SUCCESS);
+    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), Ok(()));
 
     //change bob's password
     assert_eq!(my_database.change_password(
         &"bob".to_string(),
         &"bob".to_string(),
-        &hash("bob_new_pass".to_string()),
-    ), SUCCESS);
+        "bob_new_pass",
+    ), Ok(()));
 
     //add principals to database after new default alice has some permissions other than prev default anyone
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"john".to_string(),
-        &hash("john_pass".to_string()),
-    ), SUCCESS);
+        "john_pass",
+    ), Ok(()));
 
     // check for correct permissions
     assert_eq!(
@@ -306,14 +308,14 @@ fn basic_full_2() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &"my_var3".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //add principals to database after new default alice has some permissions other than prev default anyone
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"git".to_string(),
-        &hash("git_pass".to_string()),
-    ), SUCCESS);
+        "git_pass",
+    ), Ok(()));
     // check for in-correct permissions
     assert_eq!(
         my_database.check_right(&"my_var3".to_string(), &Right::Read, &"git".to_string()),
@@ -335,15 +337,15 @@ fn basic_full_2() -> Result<(), Box<dyn Error>> {
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"git1".to_string(),
-        &hash("git_pass".to_string()),
-    ), SUCCESS);
+        "git_pass",
+    ), Ok(()));
 
     //alice created my_var4
     assert_eq!(my_database.set(
         &"alice".to_string(),
         &"my_var4".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     // check for in-correct permissions
     assert_eq!(
@@ -372,51 +374,51 @@ fn basic_full_2() -> Result<(), Box<dyn Error>> {
 
 #[test]
 // test all basic stuff
-fn basic_full_3() -> Result<(), Box<dyn Error>> {
+fn basic_full_3() -> Result<(), Box<dyn StdError>> {
     let mut my_database = Database::new(hash("wolla".to_string()));
 
     //add principals to database
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"bob".to_string(),
-        &hash("".to_string()),
-    ), SUCCESS); // empty string password
+        "",
+    ), Ok(())); // empty string password
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"tom".to_string(),
-        &hash("tom_pass".to_string()),
-    ), SUCCESS);
+        "tom_pass",
+    ), Ok(()));
 
     // lets say, bob created my_var and delegated all permissions to everyone
     assert_eq!(my_database.set(
         &"bob".to_string(),
         &"my_var1".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //add principals to database after anyone has some permissions
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"alice".to_string(),
-        &hash("alice_pass".to_string()),
-    ), SUCCESS);
+        "alice_pass",
+    ), Ok(()));
 
     //alice created my_var2
     assert_eq!(my_database.set(
         &"alice".to_string(),
         &"my_var2".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //change the default
-    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), SUCCESS);
+    assert_eq!(my_database.set_default_delegator(&"admin".to_string(), &"alice".to_string()), Ok(()));
 
     //add principals to database after new default alice has some permissions other than prev default anyone
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"john".to_string(),
-        &hash("john_pass".to_string()),
-    ), SUCCESS);
+        "john_pass",
+    ), Ok(()));
 
     // check for correct permissions
     assert_eq!(
@@ -445,14 +447,14 @@ fn basic_full_3() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &"my_var3".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     //add principals to database after new default alice has some permissions other than prev default anyone
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"git".to_string(),
-        &hash("git_pass".to_string()),
-    ), SUCCESS);
+        "git_pass",
+    ), Ok(()));
     // check for in-correct permissions
     assert_eq!(
         my_database.check_right(&"my_var3".to_string(), &Right::Read, &"git".to_string()),
@@ -474,15 +476,15 @@ fn basic_full_3() -> Result<(), Box<dyn Error>> {
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"git1".to_string(),
-        &hash("git_pass".to_string()),
-    ), SUCCESS);
+        "git_pass",
+    ), Ok(()));
 
     //alice created my_var4
     assert_eq!(my_database.set(
         &"alice".to_string(),
         &"my_var4".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     // check for in-correct permissions
     assert_eq!(
@@ -513,7 +515,7 @@ fn basic_full_3() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &Right::Read,
         &"git".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     // delete permissions to git
     assert_eq!(my_database.undelegate(
@@ -522,7 +524,7 @@ fn basic_full_3() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &Right::Read,
         &"git".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     assert_eq!(
         my_database.check_right(&"my_var3".to_string(), &Right::Read, &"git".to_string()),
@@ -534,32 +536,32 @@ fn basic_full_3() -> Result<(), Box<dyn Error>> {
 
 #[test]
 // test all basic stuff
-fn basic_full_4() -> Result<(), Box<dyn Error>> {
+fn basic_full_4() -> Result<(), Box<dyn StdError>> {
     let mut my_database = Database::new(hash("wolla".to_string()));
 
     //add principals to database
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"bob".to_string(),
-        &hash("".to_string()),
-    ), SUCCESS); // empty string password
+        "",
+    ), Ok(())); // empty string password
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"alice".to_string(),
-        &hash("".to_string()),
-    ), SUCCESS); // empty string password
+        "",
+    ), Ok(())); // empty string password
     assert_eq!(my_database.create_principal(
         &"admin".to_string(),
         &"tom".to_string(),
-        &hash("tom_pass".to_string()),
-    ), SUCCESS);
+        "tom_pass",
+    ), Ok(()));
 
     // lets say, bob created my_var and delegated all permissions to everyone
     assert_eq!(my_database.set(
         &"bob".to_string(),
         &"my_var1".to_string(),
         &Value::Immediate("lmao".to_string()),
-    ), SUCCESS);
+    ), Ok(()));
 
     // give the permission of my_var1 to alice
     assert_eq!(my_database.delegate(
@@ -568,7 +570,7 @@ fn basic_full_4() -> Result<(), Box<dyn Error>> {
         &"bob".to_string(),
         &Right::Read,
         &"alice".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     // give the permission of my_var1 to tom
     assert_eq!(my_database.delegate(
@@ -577,7 +579,7 @@ fn basic_full_4() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &Right::Read,
         &"tom".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     assert_eq!(
         my_database.check_right(&"my_var1".to_string(), &Right::Read, &"tom".to_string()),
@@ -591,7 +593,7 @@ fn basic_full_4() -> Result<(), Box<dyn Error>> {
         &"alice".to_string(),
         &Right::Read,
         &"tom".to_string(),
-    ), SUCCESS);
+    ), Ok(()));
 
     assert_eq!(
         my_database.check_right(&"my_var1".to_string(), &Right::Read, &"tom".to_string()),
@@ -600,3 +602,1175 @@ fn basic_full_4() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+// errors say why an operation failed, and still map onto the wire-level codes
+fn error_reasons() {
+    let mut my_database = Database::new(hash("admin".to_string()));
+    assert_eq!(
+        my_database.create_principal(&"admin".to_string(), &"bob".to_string(), "bob"),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.set(
+            &"admin".to_string(),
+            &"x".to_string(),
+            &Value::Immediate("x".to_string())
+        ),
+        Ok(())
+    );
+
+    let err = my_database
+        .get(&"bob".to_string(), &"x".to_string())
+        .unwrap_err();
+    assert_eq!(
+        err,
+        Error::MissingRight {
+            principal: "bob".to_string(),
+            variable: "x".to_string(),
+            right: Right::Read,
+        }
+    );
+    assert_eq!(err.status(), Status::DENIED);
+
+    let err = my_database
+        .append(
+            &"admin".to_string(),
+            &"x".to_string(),
+            &Value::Immediate("y".to_string()),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err,
+        Error::TypeMismatch {
+            variable: "x".to_string(),
+            expected: "list",
+            found: "string",
+        }
+    );
+    assert_eq!(err.status(), Status::FAILED);
+
+    assert_eq!(
+        my_database.create_principal(&"bob".to_string(), &"tom".to_string(), "tom"),
+        Err(Error::NotAdmin("bob".to_string()))
+    );
+    assert_eq!(
+        my_database.change_password(&"admin".to_string(), &"anyone".to_string(), "anyone"),
+        Err(Error::NoPassword("anyone".to_string()))
+    );
+    assert_eq!(
+        my_database.get(&"bob".to_string(), &"y".to_string()),
+        Err(Error::UnknownVariable("y".to_string()))
+    );
+}
+
+#[test]
+// deleting a variable frees its name and forgets every delegation on it
+fn delete_variable() {
+    let admin = "admin".to_string();
+    let bob = "bob".to_string();
+    let tom = "tom".to_string();
+    let x = "x".to_string();
+    let y = "y".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&bob, &tom] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    for v in &[&x, &y] {
+        assert_eq!(
+            my_database.set(&admin, v, &Value::Immediate(v.to_string())),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Variable(x.clone()),
+            &admin,
+            &Right::Read,
+            &bob
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &Target::All, &admin, &Right::Write, &tom),
+        Ok(())
+    );
+
+    // bob may only read x, so may not delete it
+    assert_eq!(
+        my_database.delete(&bob, &x),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: x.clone(),
+            right: Right::Write,
+        })
+    );
+    assert_eq!(my_database.delete(&tom, &x), Ok(()));
+    assert!(!my_database.contains(&x));
+    assert_eq!(
+        my_database.delete(&tom, &x),
+        Err(Error::UnknownVariable(x.clone()))
+    );
+    assert_eq!(
+        my_database.get(&bob, &x),
+        Err(Error::UnknownVariable(x.clone()))
+    );
+
+    // no delegation survives on x, including those made through all
+    for p in &[&bob, &tom] {
+        if let Some(VPrincipal::User(p, _)) = my_database.principals.get(*p) {
+            assert!(p.delegations.iter().all(|d| d.target != x));
+        }
+    }
+    assert!(my_database.check_right(&y, &Right::Write, &tom));
+
+    // a recreated x starts with no rights from earlier delegations
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("new".to_string())),
+        Ok(())
+    );
+    assert!(!my_database.check_right(&x, &Right::Read, &bob));
+    assert!(!my_database.check_right(&x, &Right::Write, &tom));
+}
+
+#[test]
+// deleting a principal breaks every delegation chain through it
+fn delete_principal() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let carol = "carol".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob, &carol] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("x".to_string())),
+        Ok(())
+    );
+    let target = Target::Variable(x.clone());
+    // admin -> alice -> bob -> carol, and admin -> carol directly for write
+    assert_eq!(
+        my_database.delegate(&admin, &target, &admin, &Right::Delegate, &alice),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &target, &admin, &Right::Read, &alice),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&alice, &target, &alice, &Right::Read, &bob),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &target, &bob, &Right::Read, &carol),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &target, &admin, &Right::Write, &carol),
+        Ok(())
+    );
+    assert_eq!(my_database.set_default_delegator(&admin, &bob), Ok(()));
+    assert!(my_database.check_right(&x, &Right::Read, &carol));
+
+    // only admin may delete, and never admin or anyone
+    assert_eq!(
+        my_database.delete_principal(&alice, &bob),
+        Err(Error::NotAdmin(alice.clone()))
+    );
+    for p in &["admin", "anyone"] {
+        assert_eq!(
+            my_database.delete_principal(&admin, &p.to_string()),
+            Err(Error::Builtin(p.to_string()))
+        );
+    }
+    assert_eq!(
+        my_database.delete_principal(&admin, &"dave".to_string()),
+        Err(Error::UnknownPrincipal("dave".to_string()))
+    );
+
+    assert_eq!(my_database.delete_principal(&admin, &bob), Ok(()));
+    assert_eq!(
+        my_database.check_pass(&bob, &hash("bob".to_string())),
+        Err(Error::UnknownPrincipal(bob.clone()))
+    );
+    assert_eq!(my_database.def_delegator, "anyone");
+    assert!(!my_database.check_right(&x, &Right::Read, &carol));
+    assert!(my_database.check_right(&x, &Right::Write, &carol));
+    assert!(my_database.check_right(&x, &Right::Read, &alice));
+    if let Some(VPrincipal::User(p, _)) = my_database.principals.get(&carol) {
+        assert!(p.delegations.iter().all(|d| d.delegator != bob));
+    }
+
+    // a new bob gets nothing from the old one
+    assert_eq!(my_database.create_principal(&admin, &bob, "new"), Ok(()));
+    assert!(!my_database.check_right(&x, &Right::Read, &bob));
+    assert!(!my_database.check_right(&x, &Right::Read, &carol));
+}
+
+#[test]
+// a disabled principal cannot log in, but rights still flow through it
+fn disable_principal() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("x".to_string())),
+        Ok(())
+    );
+    let target = Target::Variable(x.clone());
+    assert_eq!(
+        my_database.delegate(&admin, &target, &admin, &Right::Read, &alice),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &target, &alice, &Right::Read, &bob),
+        Ok(())
+    );
+
+    assert_eq!(
+        my_database.set_disabled(&bob, &alice, true),
+        Err(Error::NotAdmin(bob.clone()))
+    );
+    assert_eq!(
+        my_database.set_disabled(&admin, &admin, true),
+        Err(Error::Builtin(admin.clone()))
+    );
+    assert_eq!(my_database.set_disabled(&admin, &alice, true), Ok(()));
+    assert_eq!(my_database.set_disabled(&admin, &alice, true), Ok(()));
+
+    let err = my_database
+        .check_pass(&alice, &hash("alice".to_string()))
+        .unwrap_err();
+    assert_eq!(err, Error::Disabled(alice.clone()));
+    assert_eq!(err.status(), Status::DENIED);
+    // a wrong password is still reported as such
+    assert_eq!(
+        my_database.check_pass(&alice, &hash("bob".to_string())),
+        Err(Error::BadPassword(alice.clone()))
+    );
+    assert!(my_database.check_right(&x, &Right::Read, &alice));
+    assert!(my_database.check_right(&x, &Right::Read, &bob));
+
+    assert_eq!(my_database.set_disabled(&admin, &alice, false), Ok(()));
+    assert_eq!(
+        my_database.check_pass(&alice, &hash("alice".to_string())),
+        Ok(())
+    );
+}
+
+#[test]
+// members have every right their groups have, through delegations and nested groups
+fn groups() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let carol = "carol".to_string();
+    let team = "team".to_string();
+    let staff = "staff".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob, &carol] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("x".to_string())),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.create_group(&alice, &team, &alice),
+        Err(Error::NotAdmin(alice.clone()))
+    );
+    assert_eq!(
+        my_database.create_group(&admin, &team, &"anyone".to_string()),
+        Err(Error::NotUser("anyone".to_string()))
+    );
+    assert_eq!(my_database.create_group(&admin, &team, &alice), Ok(()));
+    assert_eq!(my_database.create_group(&admin, &staff, &admin), Ok(()));
+    assert_eq!(
+        my_database.create_group(&admin, &bob, &admin),
+        Err(Error::PrincipalExists(bob.clone()))
+    );
+
+    // a group cannot log in or have a password
+    assert_eq!(
+        my_database.check_pass(&team, &hash("".to_string())),
+        Err(Error::BadPassword(team.clone()))
+    );
+    assert_eq!(
+        my_database.change_password(&admin, &team, ""),
+        Err(Error::NoPassword(team.clone()))
+    );
+
+    // admin -> staff, team is a member of staff, bob is a member of team
+    let target = Target::Variable(x.clone());
+    for right in &[Right::Read, Right::Delegate] {
+        assert_eq!(
+            my_database.delegate(&admin, &target, &admin, right, &staff),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        my_database.set_member_of(&bob, &team, &bob, true),
+        Err(Error::NotOwner {
+            principal: bob.clone(),
+            group: team.clone(),
+        })
+    );
+    assert_eq!(
+        my_database.set_member_of(&alice, &staff, &team, true),
+        Err(Error::NotOwner {
+            principal: alice.clone(),
+            group: staff.clone(),
+        })
+    );
+    assert_eq!(
+        my_database.set_member_of(&alice, &bob, &carol, true),
+        Err(Error::NotGroup(bob.clone()))
+    );
+    assert_eq!(
+        my_database.set_member_of(&alice, &team, &admin, true),
+        Err(Error::Builtin(admin.clone()))
+    );
+    assert_eq!(
+        my_database.set_member_of(&admin, &staff, &team, true),
+        Ok(())
+    );
+    assert!(!my_database.check_right(&x, &Right::Read, &bob));
+    assert_eq!(my_database.set_member_of(&alice, &team, &bob, true), Ok(()));
+    assert!(my_database.check_right(&x, &Right::Read, &bob));
+    assert!(!my_database.check_right(&x, &Right::Write, &bob));
+    assert!(!my_database.check_right(&x, &Right::Read, &alice));
+
+    // bob passes on the delegate right he holds through his groups
+    assert_eq!(
+        my_database.delegate(&bob, &target, &bob, &Right::Read, &carol),
+        Ok(())
+    );
+    assert!(my_database.check_right(&x, &Right::Read, &carol));
+    // a group may also pass rights on directly
+    assert_eq!(
+        my_database.delegate(&admin, &target, &team, &Right::Read, &alice),
+        Ok(())
+    );
+    assert!(my_database.check_right(&x, &Right::Read, &alice));
+
+    // leaving the group takes the rights away, down the whole chain
+    assert_eq!(
+        my_database.set_member_of(&alice, &team, &bob, false),
+        Ok(())
+    );
+    assert!(!my_database.check_right(&x, &Right::Read, &bob));
+    assert!(!my_database.check_right(&x, &Right::Read, &carol));
+    assert!(my_database.check_right(&x, &Right::Read, &alice));
+
+    // deleting the group cuts everyone off, and deleting an owner hands the group to admin
+    assert_eq!(my_database.set_member_of(&alice, &team, &bob, true), Ok(()));
+    assert_eq!(my_database.delete_principal(&admin, &alice), Ok(()));
+    assert_eq!(
+        my_database.set_member_of(&alice, &team, &carol, true),
+        Err(Error::NotOwner {
+            principal: alice.clone(),
+            group: team.clone(),
+        })
+    );
+    assert!(my_database.check_right(&x, &Right::Read, &bob));
+    assert_eq!(my_database.delete_principal(&admin, &team), Ok(()));
+    assert!(!my_database.check_right(&x, &Right::Read, &bob));
+    if let Some(VPrincipal::Group(_, group)) = my_database.principals.get(&staff) {
+        assert!(group.members.is_empty());
+    }
+}
+
+#[test]
+// limited delegations are ignored once expired or used up, along the whole chain
+fn limited_delegations() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let time = Arc::new(AtomicU64::new(1000));
+    let clock = {
+        let time = time.clone();
+        Clock::new(move || time.load(Ordering::SeqCst))
+    };
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let x = "x".to_string();
+    let target = Target::Variable(x.clone());
+    let mut my_database = Database::with_clock(hash("admin".to_string()), clock);
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("x".to_string())),
+        Ok(())
+    );
+
+    // alice may read x for a minute
+    let minute = Limit {
+        seconds: Some(60),
+        uses: None,
+    };
+    assert_eq!(
+        my_database.delegate_limited(&admin, &target, &admin, &Right::Read, &alice, &minute),
+        Ok(())
+    );
+    time.store(1059, Ordering::SeqCst);
+    assert!(my_database.get(&alice, &x).is_ok());
+    time.store(1060, Ordering::SeqCst);
+    assert!(my_database.get(&alice, &x).is_err());
+
+    // alice may write x twice, and bob may write it through her
+    let twice = Limit {
+        seconds: None,
+        uses: Some(2),
+    };
+    assert_eq!(
+        my_database.delegate_limited(&admin, &target, &admin, &Right::Write, &alice, &twice),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &target, &alice, &Right::Write, &bob),
+        Ok(())
+    );
+    let value = Value::Immediate("y".to_string());
+    // an operation uses the delegation once when it ends, however often it relies on it
+    assert_eq!(my_database.set(&bob, &x, &value), Ok(()));
+    assert_eq!(my_database.set(&bob, &x, &value), Ok(()));
+    my_database.settle();
+    let committed = my_database.clone();
+    assert_eq!(my_database.set(&alice, &x, &value), Ok(()));
+    my_database.settle();
+    assert_eq!(
+        my_database.set(&bob, &x, &value),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: x.clone(),
+            right: Right::Write,
+        })
+    );
+    // uses are part of the state, so a discarded copy does not spend them
+    assert_eq!(committed.clone().set(&alice, &x, &value), Ok(()));
+
+    // a fresh delegation is not affected by the spent one, and revoking removes both
+    assert_eq!(
+        my_database.delegate(&admin, &target, &admin, &Right::Write, &alice),
+        Ok(())
+    );
+    assert!(my_database.check_right(&x, &Right::Write, &bob));
+    assert_eq!(
+        my_database.undelegate(&admin, &target, &admin, &Right::Write, &alice),
+        Ok(())
+    );
+    assert!(!my_database.check_right(&x, &Right::Write, &alice));
+
+    // sweeping only drops what is already ignored
+    let swept = |database: &Database| match database.principals.get(&alice) {
+        Some(VPrincipal::User(p, _)) => p.delegations.len(),
+        _ => panic!(),
+    };
+    let before = swept(&committed);
+    let mut sweeping = committed.clone();
+    sweeping.sweep();
+    assert_eq!(before - 1, swept(&sweeping));
+    assert!(sweeping.check_right(&x, &Right::Write, &bob));
+    assert!(!sweeping.check_right(&x, &Right::Read, &alice));
+}
+
+#[test]
+// a field delegation grants access to that field alone
+fn field_delegations() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let y = "y".to_string();
+    let f1 = "f1".to_string();
+    let f2 = "f2".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    let record: HashMap<String, String> = vec![
+        (f1.clone(), "one".to_string()),
+        (f2.clone(), "two".to_string()),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(
+        my_database.set(&admin, &y, &Value::FieldVals(record)),
+        Ok(())
+    );
+
+    // the field must exist on a record
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Field(y.clone(), "f3".to_string()),
+            &admin,
+            &Right::Read,
+            &bob
+        ),
+        Err(Error::UnknownField {
+            variable: y.clone(),
+            field: "f3".to_string(),
+        })
+    );
+
+    // alice may pass on reading y.f1 because she may read and delegate all of y
+    let field = Target::Field(y.clone(), f1.clone());
+    for right in &[Right::Read, Right::Delegate] {
+        assert_eq!(
+            my_database.delegate(&admin, &Target::Variable(y.clone()), &admin, right, &alice),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        my_database.delegate(&alice, &field, &alice, &Right::Read, &bob),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.get_member(&bob, &y, &f1),
+        Ok(&"one".to_string())
+    );
+    assert_eq!(
+        my_database.get_member(&bob, &y, &f2),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: "y.f2".to_string(),
+            right: Right::Read,
+        })
+    );
+    assert_eq!(
+        my_database.set_member(&bob, &y, &f1, &"uno".to_string()),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: "y.f1".to_string(),
+            right: Right::Write,
+        })
+    );
+
+    // reading all of y is denied, unless the policy projects the readable fields
+    assert!(my_database.read(&bob, &y).is_err());
+    my_database.set_field_policy(FieldPolicy::Project);
+    let projected: HashMap<String, String> =
+        vec![(f1.clone(), "one".to_string())].into_iter().collect();
+    assert_eq!(my_database.read(&bob, &y), Ok(Value::FieldVals(projected)));
+    assert!(my_database.read(&admin, &y).is_ok());
+
+    // deleting y removes the field delegation too
+    assert_eq!(my_database.delete(&admin, &y), Ok(()));
+    assert!(!my_database.check_right(&"y.f1".to_string(), &Right::Read, &bob));
+}
+
+#[test]
+// session tokens expire, and are revoked by their principal, admin, or a password change
+fn sessions() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let time = Arc::new(AtomicU64::new(1000));
+    let clock = {
+        let time = time.clone();
+        Clock::new(move || time.load(Ordering::SeqCst))
+    };
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let mut my_database = Database::with_clock(hash("admin".to_string()), clock);
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    let token = hash("token".to_string());
+    my_database.open_session(&bob, token, 60);
+    assert_eq!(my_database.check_token(&bob, &token), Ok(()));
+    assert_eq!(
+        my_database.check_token(&alice, &token),
+        Err(Error::BadToken(alice.clone()))
+    );
+    assert_eq!(
+        my_database.check_token(&"carol".to_string(), &token),
+        Err(Error::UnknownPrincipal("carol".to_string()))
+    );
+
+    // a disabled principal cannot use its token
+    assert_eq!(my_database.set_disabled(&admin, &bob, true), Ok(()));
+    assert_eq!(
+        my_database.check_token(&bob, &token),
+        Err(Error::Disabled(bob.clone()))
+    );
+    assert_eq!(my_database.set_disabled(&admin, &bob, false), Ok(()));
+
+    // the token expires, and sweeping drops it
+    time.store(1060, Ordering::SeqCst);
+    assert_eq!(
+        my_database.check_token(&bob, &token),
+        Err(Error::BadToken(bob.clone()))
+    );
+    let mut swept = my_database.clone();
+    swept.sweep();
+    assert_ne!(swept, my_database);
+
+    // only admin and bob may revoke bob's tokens
+    my_database.open_session(&bob, token, 60);
+    assert_eq!(
+        my_database.revoke_sessions(&alice, &bob),
+        Err(Error::NotAdmin(alice.clone()))
+    );
+    assert_eq!(my_database.revoke_sessions(&bob, &bob), Ok(()));
+    assert!(my_database.check_token(&bob, &token).is_err());
+
+    // a password change revokes the principal's tokens, and only its own
+    let other = hash("other".to_string());
+    my_database.open_session(&bob, token, 60);
+    my_database.open_session(&alice, other, 60);
+    assert_eq!(my_database.change_password(&bob, &bob, "new"), Ok(()));
+    assert!(my_database.check_token(&bob, &token).is_err());
+    assert_eq!(my_database.check_token(&alice, &other), Ok(()));
+}
+
+#[test]
+// new passwords are checked against the policy, including recent passwords
+fn password_policy() {
+    let admin = "admin".to_string();
+    let bob = "bob".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    my_database.set_password_policy(PasswordPolicy {
+        min_length: 4,
+        classes: vec![CharClass::Digit],
+        denied: vec!["admin1".to_string()],
+        deny_name: true,
+        history: 2,
+    });
+    let weak = |weakness| {
+        Err(Error::WeakPassword {
+            principal: bob.clone(),
+            weakness,
+        })
+    };
+    assert_eq!(
+        my_database.create_principal(&admin, &bob, "b0b"),
+        weak(Weakness::TooShort(4))
+    );
+    assert_eq!(
+        my_database.create_principal(&admin, &bob, "bobby"),
+        weak(Weakness::Missing(CharClass::Digit))
+    );
+    assert_eq!(
+        my_database.create_principal(&admin, &bob, "ADMIN1"),
+        weak(Weakness::Denied)
+    );
+    assert_eq!(my_database.create_principal(&admin, &bob, "bobby1"), Ok(()));
+    assert_eq!(
+        my_database.change_password(&bob, &bob, "Bob"),
+        weak(Weakness::TooShort(4))
+    );
+
+    // the current and the previous password may not be reused, but older ones may
+    assert_eq!(
+        my_database.change_password(&bob, &bob, "bobby1"),
+        weak(Weakness::Reused)
+    );
+    assert_eq!(my_database.change_password(&bob, &bob, "bobby2"), Ok(()));
+    assert_eq!(
+        my_database.change_password(&bob, &bob, "bobby1"),
+        weak(Weakness::Reused)
+    );
+    assert_eq!(my_database.change_password(&bob, &bob, "bobby3"), Ok(()));
+    assert_eq!(my_database.change_password(&bob, &bob, "bobby1"), Ok(()));
+    assert_eq!(
+        my_database.check_pass(&bob, &hash("bobby1".to_string())),
+        Ok(())
+    );
+}
+
+#[test]
+// a superuser may do everything admin may, until the role is revoked
+fn superusers() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let carol = "carol".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("secret".to_string())),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.set_superuser(&bob, &alice, true),
+        Err(Error::NotAdmin(bob.clone()))
+    );
+    assert_eq!(my_database.set_superuser(&admin, &alice, true), Ok(()));
+    assert!(my_database.is_superuser(&alice));
+    assert!(my_database.is_superuser(&admin));
+    assert!(!my_database.is_superuser(&bob));
+
+    // alice holds every right and may grant them, and may manage principals
+    assert!(my_database.get(&alice, &x).is_ok());
+    assert_eq!(
+        my_database.delegate(
+            &alice,
+            &Target::Variable(x.clone()),
+            &alice,
+            &Right::Read,
+            &bob
+        ),
+        Ok(())
+    );
+    assert!(my_database.get(&bob, &x).is_ok());
+    assert_eq!(
+        my_database.create_principal(&alice, &carol, "carol"),
+        Ok(())
+    );
+    assert_eq!(my_database.change_password(&alice, &bob, "new"), Ok(()));
+    assert_eq!(my_database.set_default_delegator(&alice, &bob), Ok(()));
+    let team = "team".to_string();
+    assert_eq!(my_database.create_group(&alice, &team, &admin), Ok(()));
+    assert_eq!(my_database.set_member_of(&alice, &team, &bob, true), Ok(()));
+    assert_eq!(my_database.set_superuser(&alice, &carol, true), Ok(()));
+
+    // only users may be superusers
+    for p in &[&admin, &"anyone".to_string()] {
+        assert_eq!(
+            my_database.set_superuser(&alice, p, true),
+            Err(Error::Builtin((*p).clone()))
+        );
+    }
+    assert_eq!(
+        my_database.set_superuser(&alice, &team, true),
+        Err(Error::NotUser(team.clone()))
+    );
+
+    // revoking the role takes the rights away, and those delegated through it
+    assert_eq!(my_database.set_superuser(&carol, &alice, false), Ok(()));
+    assert!(!my_database.is_superuser(&alice));
+    assert!(my_database.get(&alice, &x).is_err());
+    assert!(my_database.get(&bob, &x).is_err());
+    assert_eq!(
+        my_database.create_principal(&alice, &"dave".to_string(), "dave"),
+        Err(Error::NotAdmin(alice.clone()))
+    );
+}
+
+#[test]
+// denials take precedence over delegations, for single variables, all variables and anyone
+fn denials() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let anyone = "anyone".to_string();
+    let x = "x".to_string();
+    let y = "y".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    for v in &[&x, &y] {
+        assert_eq!(
+            my_database.set(&admin, v, &Value::Immediate("secret".to_string())),
+            Ok(())
+        );
+    }
+    for p in &[&alice, &bob] {
+        assert_eq!(
+            my_database.delegate(&admin, &Target::All, &admin, &Right::Read, p),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Variable(x.clone()),
+            &admin,
+            &Right::Delegate,
+            &alice
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(
+            &alice,
+            &Target::Variable(x.clone()),
+            &alice,
+            &Right::Read,
+            &bob
+        ),
+        Ok(())
+    );
+
+    // denying alice cuts off bob's chain through her, but not his own delegation from admin
+    let read_x = Target::Variable(x.clone());
+    assert_eq!(
+        my_database.deny(&alice, &read_x, &Right::Read, &bob),
+        Err(Error::NotAdmin(alice.clone()))
+    );
+    assert_eq!(
+        my_database.deny(&admin, &read_x, &Right::Read, &alice),
+        Ok(())
+    );
+    assert!(my_database.get(&alice, &x).is_err());
+    assert!(my_database.get(&alice, &y).is_ok());
+    assert!(my_database.get(&bob, &x).is_ok());
+    assert_eq!(
+        my_database.deny(&admin, &read_x, &Right::Read, &bob),
+        Ok(())
+    );
+    assert!(my_database.get(&bob, &x).is_err());
+    assert_eq!(
+        my_database.undeny(&admin, &read_x, &Right::Read, &bob),
+        Ok(())
+    );
+    assert!(my_database.get(&bob, &x).is_ok());
+
+    // a denial on all applies to variables created later, and one on anyone to everyone
+    assert_eq!(
+        my_database.deny(&admin, &Target::All, &Right::Read, &bob),
+        Ok(())
+    );
+    let z = "z".to_string();
+    assert_eq!(
+        my_database.set(&bob, &z, &Value::Immediate("mine".to_string())),
+        Ok(())
+    );
+    assert!(my_database.get(&bob, &z).is_err());
+    assert_eq!(
+        my_database.deny(&admin, &Target::Variable(y.clone()), &Right::Read, &anyone),
+        Ok(())
+    );
+    assert!(my_database.get(&alice, &y).is_err());
+    assert!(my_database.get(&admin, &y).is_ok());
+
+    // denials are listed per target, and go away with the variable
+    let listed = |database: &Database, target: &Target| {
+        database
+            .denials(&admin, target)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.principal.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        listed(&my_database, &Target::All),
+        vec![alice.clone(), bob.clone(), anyone.clone()]
+    );
+    assert_eq!(
+        listed(&my_database, &Target::Variable(y.clone())),
+        vec![bob.clone(), anyone.clone()]
+    );
+    assert_eq!(my_database.delete(&admin, &y), Ok(()));
+    assert_eq!(
+        listed(&my_database, &Target::All),
+        vec![alice.clone(), bob.clone()]
+    );
+    assert_eq!(
+        my_database.denials(&bob, &Target::All),
+        Err(Error::NotAdmin(bob.clone()))
+    );
+    assert_eq!(
+        my_database.deny(&admin, &read_x, &Right::Read, &admin),
+        Err(Error::AdminRights)
+    );
+    assert_eq!(
+        my_database.deny(&admin, &Target::Variable(y.clone()), &Right::Read, &bob),
+        Err(Error::UnknownVariable(y.clone()))
+    );
+}
+
+#[test]
+// pattern delegations cover variables created later, unlike delegations on all
+fn pattern_delegations() {
+    let admin = "admin".to_string();
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let value = Value::Immediate("report".to_string());
+    let mut my_database = Database::new(hash("admin".to_string()));
+    for p in &[&alice, &bob] {
+        assert_eq!(my_database.create_principal(&admin, p, p), Ok(()));
+    }
+    let reports = Target::Pattern("reports_".to_string());
+    assert_eq!(
+        my_database.delegate(&admin, &reports, &admin, &Right::Read, &alice),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&admin, &Target::All, &admin, &Right::Read, &bob),
+        Ok(())
+    );
+    let q1 = "reports_q1".to_string();
+    assert_eq!(my_database.set(&admin, &q1, &value), Ok(()));
+    assert!(my_database.get(&alice, &q1).is_ok());
+    assert!(my_database.get(&bob, &q1).is_err());
+    let other = "other".to_string();
+    assert_eq!(my_database.set(&admin, &other, &value), Ok(()));
+    assert!(my_database.get(&alice, &other).is_err());
+
+    // the pattern survives deleting a variable it matched
+    assert_eq!(my_database.delete(&admin, &q1), Ok(()));
+    assert_eq!(my_database.set(&admin, &q1, &value), Ok(()));
+    assert!(my_database.get(&alice, &q1).is_ok());
+
+    // delegating a pattern needs delegate permission on the same or a broader pattern
+    let narrower = Target::Pattern("reports_q".to_string());
+    assert_eq!(
+        my_database.delegate(&alice, &narrower, &alice, &Right::Read, &bob),
+        Err(Error::MissingRight {
+            principal: alice.clone(),
+            variable: "reports_q*".to_string(),
+            right: Right::Delegate,
+        })
+    );
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Pattern(String::new()),
+            &admin,
+            &Right::Delegate,
+            &alice
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(&alice, &narrower, &alice, &Right::Read, &bob),
+        Ok(())
+    );
+    let q2 = "reports_q2".to_string();
+    assert_eq!(my_database.set(&admin, &q2, &value), Ok(()));
+    assert!(my_database.get(&bob, &q2).is_ok());
+
+    // revoking all leaves the pattern in place; revoking the pattern removes it
+    assert_eq!(
+        my_database.undelegate(&admin, &Target::All, &admin, &Right::Read, &alice),
+        Ok(())
+    );
+    assert!(my_database.get(&alice, &q2).is_ok());
+    assert_eq!(
+        my_database.undelegate(&admin, &reports, &admin, &Right::Read, &alice),
+        Ok(())
+    );
+    assert!(my_database.get(&alice, &q2).is_err());
+}
+
+#[test]
+fn version_history() {
+    let admin = "admin".to_string();
+    let bob = "bob".to_string();
+    let x = "x".to_string();
+    let text = |s: &str| Value::Immediate(s.to_string());
+    let mut my_database = Database::new(hash("admin".to_string()));
+    assert_eq!(my_database.begin(), 1);
+    assert_eq!(my_database.create_principal(&admin, &bob, "bob"), Ok(()));
+    assert_eq!(my_database.set(&admin, &x, &text("one")), Ok(()));
+    assert_eq!(my_database.begin(), 2);
+    assert_eq!(my_database.set(&admin, &x, &text("two")), Ok(()));
+    // only the last write of a program is kept
+    assert_eq!(my_database.set(&admin, &x, &text("three")), Ok(()));
+    assert_eq!(my_database.begin(), 3);
+    assert_eq!(my_database.begin(), 4);
+    assert_eq!(my_database.set(&admin, &x, &text("four")), Ok(()));
+
+    let sequences = |db: &mut Database| -> Vec<u64> {
+        db.history(&admin, &x)
+            .unwrap()
+            .iter()
+            .map(|v| v.sequence)
+            .collect()
+    };
+    assert_eq!(sequences(&mut my_database), vec![1, 2, 4]);
+    assert_eq!(my_database.get_version(&admin, &x, 1), Ok(&text("one")));
+    assert_eq!(my_database.get_version(&admin, &x, 3), Ok(&text("three")));
+    assert_eq!(my_database.get_version(&admin, &x, 9), Ok(&text("four")));
+    assert_eq!(
+        my_database.get_version(&admin, &x, 0),
+        Err(Error::UnknownVersion {
+            variable: x.clone(),
+            sequence: 0
+        })
+    );
+
+    // reading old versions needs the read right on the variable as it is now
+    assert_eq!(
+        my_database.get_version(&bob, &x, 1),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: x.clone(),
+            right: Right::Read
+        })
+    );
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Variable(x.clone()),
+            &admin,
+            &Right::Read,
+            &bob
+        ),
+        Ok(())
+    );
+    assert_eq!(my_database.get_version(&bob, &x, 1), Ok(&text("one")));
+
+    // histories are bounded by count and size, but the current value is always kept
+    my_database.set_history_policy(HistoryPolicy {
+        versions: 2,
+        bytes: None,
+    });
+    assert_eq!(sequences(&mut my_database), vec![2, 4]);
+    my_database.set_history_policy(HistoryPolicy {
+        versions: 2,
+        bytes: Some(3),
+    });
+    assert_eq!(sequences(&mut my_database), vec![4]);
+    assert_eq!(
+        my_database.get_version(&admin, &x, 2),
+        Err(Error::UnknownVersion {
+            variable: x.clone(),
+            sequence: 2
+        })
+    );
+
+    // deleting a variable deletes its history
+    assert_eq!(my_database.delete(&admin, &x), Ok(()));
+    assert_eq!(my_database.begin(), 5);
+    assert_eq!(my_database.set(&admin, &x, &text("five")), Ok(()));
+    assert_eq!(sequences(&mut my_database), vec![5]);
+}
+
+#[test]
+fn restore() {
+    let admin = "admin".to_string();
+    let bob = "bob".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::new(hash("admin".to_string()));
+    my_database.begin();
+    assert_eq!(my_database.create_principal(&admin, &bob, "bob"), Ok(()));
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("one".to_string())),
+        Ok(())
+    );
+    my_database.open_session(&bob, hash("token".to_string()), 60);
+    let checkpoint = my_database.clone();
+    assert_eq!(checkpoint.sequence(), 1);
+
+    my_database.begin();
+    assert_eq!(my_database.revoke_sessions(&bob, &bob), Ok(()));
+    assert_eq!(my_database.delete_principal(&admin, &bob), Ok(()));
+    assert_eq!(my_database.delete(&admin, &x), Ok(()));
+    my_database.set_history_policy(HistoryPolicy {
+        versions: 1,
+        bytes: None,
+    });
+
+    // the state comes back, but the numbering and configuration carry on
+    my_database.restore(&checkpoint);
+    assert_eq!(my_database.sequence(), 2);
+    assert_eq!(
+        my_database.check_pass(&bob, &hash("bob".to_string())),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.get(&admin, &x),
+        Ok(&Value::Immediate("one".to_string()))
+    );
+    assert_eq!(
+        my_database.get_version(&admin, &x, 1),
+        Ok(&Value::Immediate("one".to_string()))
+    );
+    // but a token revoked since stays revoked
+    assert_eq!(
+        my_database.check_token(&bob, &hash("token".to_string())),
+        Err(Error::BadToken(bob.clone()))
+    );
+    assert_ne!(my_database, checkpoint);
+    my_database.set_history_policy(HistoryPolicy::default());
+    my_database.open_session(&bob, hash("token".to_string()), 60);
+    assert_eq!(my_database, checkpoint);
+
+    // and the sessions of principals the checkpoint does not have are closed
+    let carol = "carol".to_string();
+    assert_eq!(my_database.create_principal(&admin, &carol, "carol"), Ok(()));
+    my_database.open_session(&carol, hash("carol's".to_string()), 60);
+    my_database.restore(&checkpoint);
+    assert_eq!(my_database, checkpoint);
+}
+
+#[test]
+// each variable remembers who created and last wrote it, and when
+fn metadata() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let time = Arc::new(AtomicU64::new(1000));
+    let clock = {
+        let time = time.clone();
+        Clock::new(move || time.load(Ordering::SeqCst))
+    };
+    let admin = "admin".to_string();
+    let bob = "bob".to_string();
+    let x = "x".to_string();
+    let mut my_database = Database::with_clock(hash("admin".to_string()), clock);
+    assert_eq!(my_database.create_principal(&admin, &bob, "bob"), Ok(()));
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::List(Vec::new())),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.delegate(
+            &admin,
+            &Target::Variable(x.clone()),
+            &admin,
+            &Right::Append,
+            &bob
+        ),
+        Ok(())
+    );
+    time.store(1500, Ordering::SeqCst);
+    assert_eq!(
+        my_database.append(&bob, &x, &Value::Immediate("hello".to_string())),
+        Ok(())
+    );
+    assert_eq!(
+        my_database.metadata(&admin, &x),
+        Ok(&Metadata {
+            creator: admin.clone(),
+            writer: bob.clone(),
+            created: 1000,
+            modified: 1500,
+            size: 5,
+        })
+    );
+
+    // reading the metadata needs the read right
+    assert_eq!(
+        my_database.metadata(&bob, &x),
+        Err(Error::MissingRight {
+            principal: bob.clone(),
+            variable: x.clone(),
+            right: Right::Read
+        })
+    );
+    assert_eq!(
+        my_database.metadata(&admin, &"y".to_string()),
+        Err(Error::UnknownVariable("y".to_string()))
+    );
+
+    // a variable created again starts afresh
+    assert_eq!(my_database.delete(&admin, &x), Ok(()));
+    time.store(2000, Ordering::SeqCst);
+    assert_eq!(
+        my_database.set(&admin, &x, &Value::Immediate("hi".to_string())),
+        Ok(())
+    );
+    let metadata = my_database.metadata(&admin, &x).unwrap();
+    assert_eq!((metadata.created, metadata.modified), (2000, 2000));
+    assert_eq!(metadata.writer, admin);
+}
@@ -10,19 +10,23 @@ use types::*;
 peg::parser! {
     grammar program_parser() for str {
         pub rule program<'a>() -> Program
-            = (comment() "\n")* _ "as" __ "principal" __ !keyword() p:principal() __ "password" __ s:string() __ "do" _ comment()? "\n"
+            = (comment() "\n")* _ "as" __ "principal" __ !keyword() p:principal() __ c:credential() __ "do" _ comment()? "\n"
                     (comment() "\n")*
                     cmd:(a:line() "\n" { a })*
                     term:terminator_command() "\n"
                     _ "***" _ (comment() _) ** "\n" {
                 Program {
                     principal: p,
-                    password: hash(s),
+                    credential: c,
                     commands: cmd,
                     terminator: term
                 }
             }
 
+        rule credential() -> Credential
+            = "password" __ s:string() { Credential::Password(hash(s)) }
+            / "token" __ s:string() { Credential::Token(hash(s)) }
+
         rule principal() -> Principal
             = s:identifier() { Principal { ident: s } }
 
@@ -56,28 +60,68 @@ peg::parser! {
                  | '_']*<0,254>) { Identifier { name: s.to_string() } }
 
         rule expr() -> Expr
-            = !keyword() v:value() { Expr::Value(v) }
+            = "let" __ !keyword() i:identifier() _ "=" _ v:expr() __ "in" __ b:expr()
+                { Expr::Let(Let { variable: i, value: Box::new(v), body: Box::new(b) }) }
+            / f:function() { Expr::Function(f) }
+            / !keyword() v:value() { Expr::Value(v) }
             / "[" _ "]" { Expr::EmptyList }
             / "{" a:( _ () a:root_value_assignment() _ {a}) ** "," _ "}" { Expr::FieldVals(a) }
 
+        rule function() -> Function
+            = "concat" _ "(" _ a:argument() _ "," _ b:argument() _ ")" { Function::Concat(Box::new(a), Box::new(b)) }
+            / "tolower" _ "(" _ a:argument() _ ")" { Function::ToLower(Box::new(a)) }
+            / "equal" _ "(" _ a:argument() _ "," _ b:argument() _ ")" { Function::Equal(Box::new(a), Box::new(b)) }
+            / "notequal" _ "(" _ a:argument() _ "," _ b:argument() _ ")" { Function::NotEqual(Box::new(a), Box::new(b)) }
+            / "split" _ "(" _ a:argument() _ "," _ b:argument() _ ")" { Function::Split(Box::new(a), Box::new(b)) }
+            / "denials" _ "(" _ t:target() _ ")" { Function::Denials(t) }
+            / "history" _ "(" _ !keyword() i:identifier() _ ")" { Function::History(i) }
+            / "metadata" _ "(" _ !keyword() i:identifier() _ ")" { Function::Metadata(i) }
+
+        rule argument() -> Expr
+            = f:function() { Expr::Function(f) }
+            / !keyword() v:value() { Expr::Value(v) }
+
         rule primitive_command() -> PrimitiveCommand
             = c:create_principal() { PrimitiveCommand::CreatePrincipal(c) }
             / c:change_password() { PrimitiveCommand::ChangePassword(c) }
+            / "delete" __ "principal" __ p:principal() { PrimitiveCommand::DeletePrincipal(p) }
+            / c:create_group() { PrimitiveCommand::CreateGroup(c) }
+            / "add" __ "principal" __ !keyword() p:principal() __ "to" __ "group" __ !keyword() g:principal()
+                { PrimitiveCommand::AddMember(Membership { member: p, group: g }) }
+            / "remove" __ "principal" __ !keyword() p:principal() __ "from" __ "group" __ !keyword() g:principal()
+                { PrimitiveCommand::RemoveMember(Membership { member: p, group: g }) }
+            / "disable" __ "principal" __ p:principal() { PrimitiveCommand::DisablePrincipal(p) }
+            / "enable" __ "principal" __ p:principal() { PrimitiveCommand::EnablePrincipal(p) }
+            / "login" n:(__ "for" __ n:number() __ "second" "s"? { n })? { PrimitiveCommand::Login(n) }
+            / "revoke" __ "tokens" __ p:principal() { PrimitiveCommand::RevokeTokens(p) }
+            / "grant" __ "superuser" __ p:principal() { PrimitiveCommand::GrantSuperuser(p) }
+            / "revoke" __ "superuser" __ p:principal() { PrimitiveCommand::RevokeSuperuser(p) }
             / "set" __ a:assignment() { PrimitiveCommand::Assignment(a) }
             / c:append() { PrimitiveCommand::Append(c) }
             / "local" __ a:root_assignment() { PrimitiveCommand::LocalAssignment(a) }
             / c:for_each() { PrimitiveCommand::ForEach(c) }
-            / "set" __ d:delegation() { PrimitiveCommand::SetDelegation(d) }
+            / c:filter_each() { PrimitiveCommand::FilterEach(c) }
+            / c:conditional() { PrimitiveCommand::Conditional(c) }
+            / "set" __ d:delegation() l:limit()? { PrimitiveCommand::SetDelegation(d, l.unwrap_or_default()) }
             / "delete" __ d:delegation() { PrimitiveCommand::DeleteDelegation(d) }
+            / "set" __ d:denial() { PrimitiveCommand::SetDenial(d) }
+            / "delete" __ d:denial() { PrimitiveCommand::DeleteDenial(d) }
+            / "delete" __ !keyword() i:identifier() { PrimitiveCommand::DeleteVariable(i) }
             / "default" __ "delegator" _ "=" _ p:principal() { PrimitiveCommand::DefaultDelegator(p) }
+            / "restore" __ "to" __ n:number() { PrimitiveCommand::Restore(n) }
+            / "watch" __ !keyword() i:identifier() { PrimitiveCommand::Watch(i) }
 
         rule create_principal() -> CreatePrincipal
             = "create" __ "principal" __ p:principal() __ s:string()
-            { CreatePrincipal { principal: p, password: hash(s) } }
+            { CreatePrincipal { principal: p, password: s } }
+
+        rule create_group() -> CreateGroup
+            = "create" __ "group" __ !keyword() g:principal() o:(__ "owner" __ !keyword() p:principal() { p })?
+            { CreateGroup { group: g, owner: o } }
 
         rule change_password() -> ChangePassword
             = "change" __ "password" __ p:principal() __ s:string()
-            { ChangePassword { principal: p, password: hash(s) } }
+            { ChangePassword { principal: p, password: s } }
 
         rule assignment() -> Assignment
             = a:root_assignment() { a }
@@ -103,6 +147,28 @@ peg::parser! {
             = "foreach" __ y:variable() __ "in" __ x:variable() __ "replacewith" __ e:expr()
                 { ForEach { value: y, list: x, expr: e } }
 
+        rule filter_each() -> FilterEach
+            = "filtereach" __ y:variable() __ "in" __ x:variable() __ "with" __ c:condition()
+                { FilterEach { value: y, list: x, condition: c } }
+
+        rule conditional() -> Conditional
+            = "if" __ c:condition() __ "then" block_start()
+                t:(a:line() "\n" { a })*
+                e:(_ "else" block_start() e:(a:line() "\n" { a })* { e })?
+              _ "end"
+                { Conditional { condition: c, then: t, otherwise: e.unwrap_or_default() } }
+
+        rule block_start() = _ (comment() _) ** "\n" "\n"
+
+        rule condition() -> Condition
+            = "defined" __ v:defined() { Condition::Defined(v) }
+            / a:expr() _ "==" _ b:expr() { Condition::Equal(a, b) }
+            / a:expr() _ "!=" _ b:expr() { Condition::NotEqual(a, b) }
+
+        rule defined() -> Variable
+            = !keyword() i:identifier() _ "." _ !keyword() f:identifier() { Variable::Member(i, Box::new(Variable::Variable(f))) }
+            / v:variable() { v }
+
         rule delegation() -> Delegation
             = "delegation" __ t:target() __ !keyword() q:principal() __ r:right() _ "->" _ !keyword() p:principal()
                 {
@@ -114,13 +180,28 @@ peg::parser! {
                     }
                 }
 
+        rule denial() -> Denial
+            = "denial" __ t:target() __ r:right() _ "->" _ !keyword() p:principal()
+                { Denial { target: t, right: r, denied: p } }
+
+        rule limit() -> Limit
+            = __ "for" __ s:number() __ "second" "s"? u:(__ "and" __ u:number() __ "use" "s"? { u })?
+                { Limit { seconds: Some(s), uses: u } }
+            / __ "for" __ u:number() __ "use" "s"? { Limit { seconds: None, uses: Some(u) } }
+
+        rule number() -> u64
+            = n:$(['0'..='9']+) {? match n.parse() { Ok(n) if n > 0 => Ok(n), _ => Err("positive number") } }
+
         rule value() -> Value
-            = !keyword() i:identifier() _ "." _ !keyword() f:identifier() { Value::Variable(Variable::Member(i, Box::new(Variable::Variable(f)))) }
+            = !keyword() i:identifier() "@" n:number() { Value::Version(i, n) }
+            / !keyword() i:identifier() _ "." _ !keyword() f:identifier() { Value::Variable(Variable::Member(i, Box::new(Variable::Variable(f)))) }
             / v:variable() { Value::Variable(v) }
             / s:string() { Value::String(s) }
 
         rule target() -> Target
             = "all" { Target::All }
+            / !keyword() p:$(identifier()? "*") { Target::Pattern(p.trim_end_matches('*').to_string()) }
+            / !keyword() i:identifier() _ "." _ !keyword() f:identifier() { Target::Field(i, f) }
             / !keyword() i:identifier() { Target::Variable(i) }
 
         rule right() -> Right
@@ -148,10 +229,16 @@ peg::parser! {
                  | '!'
                  | '-']*}
 
+        // the words of the original grammar, which are never names. Words added with later
+        // commands and functions (`if`, `watch`, `group`, `login`, ...) are not reserved: they are
+        // only matched where their syntax expects them, so older programs may still use them as
+        // names. A keyword only counts as a whole word, so `setting` or `allx` are names too.
         rule keyword() = quiet!{
-            "all" / "append" / "as" / "change" / "create" / "default" / "delegation" / "delegator"
+            ("all" / "append" / "as" / "change" / "create" / "default" / "delegation" / "delegator"
                   / "delete" / "do" / "exit" / "foreach" / "in" / "local" / "password" / "principal"
-                  / "read" / "replacewith" / "return" / "set" / "to" / "write" / "***"
+                  / "read" / "replacewith" / "return" / "set" / "to" / "write")
+                  !['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
+            / "***"
         }
     }
 }
@@ -33,7 +33,7 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::CreatePrincipal(CreatePrincipal {
                     principal: Principal {
@@ -41,7 +41,7 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
                             name: "jack".to_string()
                         }
                     },
-                    password: hash("hammer".to_string())
+                    password: "hammer".to_string()
                 }),
                 PrimitiveCommand::ChangePassword(ChangePassword {
                     principal: Principal {
@@ -49,7 +49,7 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
                             name: "bob".to_string()
                         }
                     },
-                    password: hash("bits".to_string())
+                    password: "bits".to_string()
                 }),
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -152,20 +152,23 @@ fn basic_full_1() -> Result<(), Box<dyn Error>> {
                     }),
                     expr: Expr::Value(Value::String("temp_i".to_string()))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "jack".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "jack".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "bob".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "bob".to_string()
-                        }
-                    }
-                }),
+                    Limit::default()
+                ),
                 PrimitiveCommand::DeleteDelegation(Delegation {
                     target: Target::Variable(Identifier {
                         name: "x".to_string()
@@ -219,7 +222,7 @@ fn pg4_max_1m_char_prog_eq_1() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("l".to_string()),
+            credential: Credential::Password(hash("l".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -251,7 +254,7 @@ fn pg4_max_1m_char_prog_eq_2() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("l".to_string()),
+            credential: Credential::Password(hash("l".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -311,7 +314,7 @@ fn pg5_tkn_s_max_65k_char_eq() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("l".repeat(65535).to_string()),
+            credential: Credential::Password(hash("l".repeat(65535).to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -363,7 +366,7 @@ fn pg5_tkn_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash(r#"AJGFsdkljg543_ , ;\.?!-"#.to_string()),
+            credential: Credential::Password(hash(r#"AJGFsdkljg543_ , ;\.?!-"#.to_string())),
             commands: vec![
                 PrimitiveCommand::ChangePassword(ChangePassword {
                     principal: Principal {
@@ -371,7 +374,7 @@ fn pg5_tkn_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                             name: "tom1".to_string()
                         }
                     },
-                    password: hash(r#"\\"#.to_string())
+                    password: r#"\\"#.to_string()
                 }),
                 PrimitiveCommand::ChangePassword(ChangePassword {
                     principal: Principal {
@@ -379,7 +382,7 @@ fn pg5_tkn_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                             name: "tom2".to_string()
                         }
                     },
-                    password: hash(r#"__\.."#.to_string())
+                    password: r#"__\.."#.to_string()
                 }),
                 PrimitiveCommand::ChangePassword(ChangePassword {
                     principal: Principal {
@@ -387,7 +390,7 @@ fn pg5_tkn_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                             name: "tom3".to_string()
                         }
                     },
-                    password: hash(r#"  rgf\  egfvd.?"#.to_string())
+                    password: r#"  rgf\  egfvd.?"#.to_string()
                 }),
                 PrimitiveCommand::ChangePassword(ChangePassword {
                     principal: Principal {
@@ -395,7 +398,7 @@ fn pg5_tkn_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                             name: "tom4".to_string()
                         }
                     },
-                    password: hash(r#".\ __ ---\"#.to_string())
+                    password: r#".\ __ ---\"#.to_string()
                 })
             ],
             terminator: TerminatorCommand::Exit
@@ -453,7 +456,7 @@ fn pg5_tkn_s_empty() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("".to_string()),
+            credential: Credential::Password(hash("".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -489,7 +492,7 @@ fn pg5_tkn_non_s_max_255_eq() -> Result<(), Box<dyn Error>> {
                     name: "b".repeat(255).to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -504,20 +507,23 @@ fn pg5_tkn_non_s_max_255_eq() -> Result<(), Box<dyn Error>> {
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q".repeat(255).to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q".repeat(255).to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "b".repeat(255).to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "b".repeat(255).to_string()
-                        }
-                    }
-                })
+                    Limit::default()
+                )
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -667,7 +673,7 @@ fn pg5_tkn_non_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                     name: "a9_".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -682,20 +688,23 @@ fn pg5_tkn_non_s_accepted_char_types() -> Result<(), Box<dyn Error>> {
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q_9_aA".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q_9_aA".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "a9_".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "a9_".to_string()
-                        }
-                    }
-                })
+                    Limit::default()
+                )
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -756,7 +765,7 @@ fn pg5_n_newline() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("".to_string()),
+            credential: Credential::Password(hash("".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -805,7 +814,7 @@ fn pg5_spaces_in_bw() -> Result<(), Box<dyn Error>> {
                     name: "a9_".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -820,20 +829,23 @@ fn pg5_spaces_in_bw() -> Result<(), Box<dyn Error>> {
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q_9_aA".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q_9_aA".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "a9_".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "a9_".to_string()
-                        }
-                    }
-                }),
+                    Limit::default()
+                ),
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -905,7 +917,7 @@ fn pg5_min_whitespace() -> Result<(), Box<dyn Error>> {
                     name: "a9_".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -920,20 +932,23 @@ fn pg5_min_whitespace() -> Result<(), Box<dyn Error>> {
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q_9_aA".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q_9_aA".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "a9_".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "a9_".to_string()
-                        }
-                    }
-                }),
+                    Limit::default()
+                ),
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -978,7 +993,7 @@ as principal {} password "{}" do //ignore_me
                     name: "a9_".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -993,20 +1008,23 @@ as principal {} password "{}" do //ignore_me
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q_9_aA".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q_9_aA".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "a9_".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "a9_".to_string()
-                        }
-                    }
-                }),
+                    Limit::default()
+                ),
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -1064,7 +1082,7 @@ as principal {} password "{}" do
                     name: "a9_".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -1079,20 +1097,23 @@ as principal {} password "{}" do
                         }))
                     )))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::All,
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "q_9_aA".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::All,
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "q_9_aA".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "a9_".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "a9_".to_string()
-                        }
-                    }
-                }),
+                    Limit::default()
+                ),
             ],
             terminator: TerminatorCommand::Exit
         }
@@ -1180,7 +1201,7 @@ fn basic() -> Result<(), Box<dyn Error>> {
                     name: "bob".to_string()
                 }
             },
-            password: hash("lmao".to_string()),
+            credential: Credential::Password(hash("lmao".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Exit
         }
@@ -1207,7 +1228,7 @@ fn basic_1() -> Result<(), Box<dyn Error>> {
                     name: "alice".to_string()
                 }
             },
-            password: hash("alices_password".to_string()),
+            credential: Credential::Password(hash("alices_password".to_string())),
             commands: vec![],
             terminator: TerminatorCommand::Return(Expr::Value(Value::Variable(
                 Variable::Variable(Identifier {
@@ -1264,7 +1285,7 @@ return "success"
                     name: "admin".to_string()
                 }
             },
-            password: hash("admin".to_string()),
+            credential: Credential::Password(hash("admin".to_string())),
             commands: vec![
                 PrimitiveCommand::CreatePrincipal(CreatePrincipal {
                     principal: Principal {
@@ -1272,7 +1293,7 @@ return "success"
                             name: "alice".to_string()
                         },
                     },
-                    password: hash("alices_password".to_string())
+                    password: "alices_password".to_string()
                 }),
                 PrimitiveCommand::Assignment(Assignment {
                     variable: Variable::Variable(Identifier {
@@ -1282,22 +1303,25 @@ return "success"
                         "Hi Alice. Good luck in Build-it, Break-it, Fix-it!".to_string()
                     ))
                 }),
-                PrimitiveCommand::SetDelegation(Delegation {
-                    target: Target::Variable(Identifier {
-                        name: "msg".to_string()
-                    }),
-                    delegator: Principal {
-                        ident: Identifier {
-                            name: "admin".to_string()
+                PrimitiveCommand::SetDelegation(
+                    Delegation {
+                        target: Target::Variable(Identifier {
+                            name: "msg".to_string()
+                        }),
+                        delegator: Principal {
+                            ident: Identifier {
+                                name: "admin".to_string()
+                            }
+                        },
+                        right: Right::Read,
+                        delegated: Principal {
+                            ident: Identifier {
+                                name: "alice".to_string()
+                            }
                         }
                     },
-                    right: Right::Read,
-                    delegated: Principal {
-                        ident: Identifier {
-                            name: "alice".to_string()
-                        }
-                    }
-                })
+                    Limit::default()
+                )
             ],
             terminator: TerminatorCommand::Return(Expr::Value(Value::String(
                 "success".to_string()
@@ -1307,3 +1331,973 @@ return "success"
 
     Ok(())
 }
+
+#[test]
+// Conditionals nest, and the else block is optional
+fn conditional_blocks() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "lmao" do
+              if x == "yes" then // comment
+                 set y = "agreed"
+                 if defined z.f then
+                    set z.f = y
+                 end
+              else
+                 set y = "refused"
+              end
+              if defined y then
+              end
+              return y
+       ***"#
+            .to_string(),
+    )?;
+
+    let identifier = |name: &str| Identifier {
+        name: name.to_string(),
+    };
+    let set = |variable: Variable, value: Value| {
+        PrimitiveCommand::Assignment(Assignment {
+            variable,
+            expr: Expr::Value(value),
+        })
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::Conditional(Conditional {
+                condition: Condition::Equal(
+                    Expr::Value(Value::Variable(Variable::Variable(identifier("x")))),
+                    Expr::Value(Value::String("yes".to_string()))
+                ),
+                then: vec![
+                    set(
+                        Variable::Variable(identifier("y")),
+                        Value::String("agreed".to_string())
+                    ),
+                    PrimitiveCommand::Conditional(Conditional {
+                        condition: Condition::Defined(Variable::Member(
+                            identifier("z"),
+                            Box::new(Variable::Variable(identifier("f")))
+                        )),
+                        then: vec![set(
+                            Variable::Member(
+                                identifier("z"),
+                                Box::new(Variable::Variable(identifier("f")))
+                            ),
+                            Value::Variable(Variable::Variable(identifier("y")))
+                        )],
+                        otherwise: vec![]
+                    })
+                ],
+                otherwise: vec![set(
+                    Variable::Variable(identifier("y")),
+                    Value::String("refused".to_string())
+                )]
+            }),
+            PrimitiveCommand::Conditional(Conditional {
+                condition: Condition::Defined(Variable::Variable(identifier("y"))),
+                then: vec![],
+                otherwise: vec![]
+            })
+        ]
+    );
+
+    // a block must be closed
+    assert!(parse(
+        r#"as principal bob password "lmao" do
+              if x != "yes" then
+                 set y = "agreed"
+              return y
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn functions() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "lmao" do
+              return split(concat(tolower(x.name), "-"), equal(y, "z"))
+       ***"#
+            .to_string(),
+    )?;
+
+    let variable = |name: &str| {
+        Box::new(Expr::Value(Value::Variable(Variable::Variable(
+            Identifier {
+                name: name.to_string(),
+            },
+        ))))
+    };
+    let lowered = Function::ToLower(Box::new(Expr::Value(Value::Variable(Variable::Member(
+        Identifier {
+            name: "x".to_string(),
+        },
+        Box::new(Variable::Variable(Identifier {
+            name: "name".to_string(),
+        })),
+    )))));
+    assert_eq!(
+        program.terminator,
+        TerminatorCommand::Return(Expr::Function(Function::Split(
+            Box::new(Expr::Function(Function::Concat(
+                Box::new(Expr::Function(lowered)),
+                Box::new(Expr::Value(Value::String("-".to_string())))
+            ))),
+            Box::new(Expr::Function(Function::Equal(
+                variable("y"),
+                Box::new(Expr::Value(Value::String("z".to_string())))
+            )))
+        )))
+    );
+
+    // records and lists are not function arguments
+    assert!(parse(
+        r#"as principal bob password "lmao" do
+              return concat([], "a")
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn let_expressions() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "lmao" do
+              foreach y in x replacewith let z = y.name in let w = concat(z, "s") in { a = z, b = w }
+              return let z = [] in z
+       ***"#
+            .to_string(),
+    )?;
+
+    let identifier = |name: &str| Identifier {
+        name: name.to_string(),
+    };
+    let variable = |name: &str| {
+        Box::new(Expr::Value(Value::Variable(Variable::Variable(
+            identifier(name),
+        ))))
+    };
+    let field = |name: &str, value: &str| Assignment {
+        variable: Variable::Variable(identifier(name)),
+        expr: Expr::Value(Value::Variable(Variable::Variable(identifier(value)))),
+    };
+    assert_eq!(
+        program.commands,
+        vec![PrimitiveCommand::ForEach(ForEach {
+            value: Variable::Variable(identifier("y")),
+            list: Variable::Variable(identifier("x")),
+            expr: Expr::Let(Let {
+                variable: identifier("z"),
+                value: Box::new(Expr::Value(Value::Variable(Variable::Member(
+                    identifier("y"),
+                    Box::new(Variable::Variable(identifier("name")))
+                )))),
+                body: Box::new(Expr::Let(Let {
+                    variable: identifier("w"),
+                    value: Box::new(Expr::Function(Function::Concat(
+                        variable("z"),
+                        Box::new(Expr::Value(Value::String("s".to_string())))
+                    ))),
+                    body: Box::new(Expr::FieldVals(vec![field("a", "z"), field("b", "w")]))
+                }))
+            })
+        })]
+    );
+    assert_eq!(
+        program.terminator,
+        TerminatorCommand::Return(Expr::Let(Let {
+            variable: identifier("z"),
+            value: Box::new(Expr::EmptyList),
+            body: variable("z")
+        }))
+    );
+
+    // the bound name cannot be a keyword, and the body is required
+    for source in &["return let in = \"a\" in in", "return let z = \"a\" in"] {
+        assert!(parse(format!(
+            "as principal bob password \"lmao\" do\n{}\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn filter_each() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "lmao" do
+              filtereach rec in records with rec.name != "mike"
+              filtereach rec in records with defined rec.date
+              return records
+       ***"#
+            .to_string(),
+    )?;
+
+    let identifier = |name: &str| Identifier {
+        name: name.to_string(),
+    };
+    let filter = |condition: Condition| {
+        PrimitiveCommand::FilterEach(FilterEach {
+            value: Variable::Variable(identifier("rec")),
+            list: Variable::Variable(identifier("records")),
+            condition,
+        })
+    };
+    let member = |field: &str| {
+        Variable::Member(
+            identifier("rec"),
+            Box::new(Variable::Variable(identifier(field))),
+        )
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            filter(Condition::NotEqual(
+                Expr::Value(Value::Variable(member("name"))),
+                Expr::Value(Value::String("mike".to_string()))
+            )),
+            filter(Condition::Defined(member("date")))
+        ]
+    );
+
+    // the condition is required
+    assert!(parse(
+        r#"as principal bob password "lmao" do
+              filtereach rec in records with
+              return records
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn delete_variable() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "lmao" do
+              delete x
+              delete delegation x admin read -> bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::DeleteVariable(Identifier {
+                name: "x".to_string()
+            }),
+            PrimitiveCommand::DeleteDelegation(Delegation {
+                target: Target::Variable(Identifier {
+                    name: "x".to_string()
+                }),
+                delegator: Principal {
+                    ident: Identifier {
+                        name: "admin".to_string()
+                    }
+                },
+                right: Right::Read,
+                delegated: Principal {
+                    ident: Identifier {
+                        name: "bob".to_string()
+                    }
+                }
+            })
+        ]
+    );
+
+    // only whole variables can be deleted
+    assert!(parse(
+        r#"as principal bob password "lmao" do
+              delete x.f
+              return ""
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn principal_lifecycle() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              disable principal bob
+              enable principal bob
+              delete principal bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    let bob = || Principal {
+        ident: Identifier {
+            name: "bob".to_string(),
+        },
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::DisablePrincipal(bob()),
+            PrimitiveCommand::EnablePrincipal(bob()),
+            PrimitiveCommand::DeletePrincipal(bob())
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn groups() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              create group team
+              create group staff owner alice
+              add principal bob to group team
+              remove principal bob from group team
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    let principal = |name: &str| Principal {
+        ident: Identifier {
+            name: name.to_string(),
+        },
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::CreateGroup(CreateGroup {
+                group: principal("team"),
+                owner: None
+            }),
+            PrimitiveCommand::CreateGroup(CreateGroup {
+                group: principal("staff"),
+                owner: Some(principal("alice"))
+            }),
+            PrimitiveCommand::AddMember(Membership {
+                member: principal("bob"),
+                group: principal("team")
+            }),
+            PrimitiveCommand::RemoveMember(Membership {
+                member: principal("bob"),
+                group: principal("team")
+            })
+        ]
+    );
+
+    // a group is not named like a keyword
+    assert!(parse(
+        r#"as principal admin password "admin" do
+              create group delete
+              return ""
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn limited_delegations() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              set delegation x admin read -> bob for 86400 seconds
+              set delegation x admin write -> bob for 1 use
+              set delegation all admin append -> bob for 60 seconds and 3 uses
+              set delegation x admin read -> bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    let limits = program
+        .commands
+        .iter()
+        .map(|c| match c {
+            PrimitiveCommand::SetDelegation(_, limit) => *limit,
+            _ => panic!(),
+        })
+        .collect::<Vec<Limit>>();
+    assert_eq!(
+        limits,
+        vec![
+            Limit {
+                seconds: Some(86400),
+                uses: None
+            },
+            Limit {
+                seconds: None,
+                uses: Some(1)
+            },
+            Limit {
+                seconds: Some(60),
+                uses: Some(3)
+            },
+            Limit::default()
+        ]
+    );
+
+    // limits are positive, and only apply when setting a delegation
+    for source in &[
+        "set delegation x admin read -> bob for 0 seconds",
+        "set delegation x admin read -> bob for seconds",
+        "set delegation x admin read -> bob for 1 use and 5 seconds",
+        "delete delegation x admin read -> bob for 1 use",
+    ] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// a delegation may target a single field of a record
+#[test]
+fn field_delegations() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              set delegation y.f admin read -> bob
+              delete delegation y . f admin read -> bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    let targets = program
+        .commands
+        .iter()
+        .map(|c| match c {
+            PrimitiveCommand::SetDelegation(d, _) | PrimitiveCommand::DeleteDelegation(d) => {
+                d.target.clone()
+            }
+            _ => panic!(),
+        })
+        .collect::<Vec<Target>>();
+    let field = Target::Field(
+        Identifier {
+            name: "y".to_string(),
+        },
+        Identifier {
+            name: "f".to_string(),
+        },
+    );
+    assert_eq!(targets, vec![field.clone(), field]);
+
+    // a field of all is not a target
+    assert!(parse(
+        r#"as principal admin password "admin" do
+              set delegation all.f admin read -> bob
+              return ""
+       ***"#
+            .to_string()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+// a program may authenticate with a session token, and open or revoke sessions
+#[test]
+fn sessions() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob token "0f1e2d" do
+              login
+              login for 60 seconds
+              revoke tokens bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    assert_eq!(
+        program.credential,
+        Credential::Token(hash("0f1e2d".to_string()))
+    );
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::Login(None),
+            PrimitiveCommand::Login(Some(60)),
+            PrimitiveCommand::RevokeTokens(Principal {
+                ident: Identifier {
+                    name: "bob".to_string()
+                }
+            }),
+        ]
+    );
+
+    for source in &["login for 0 seconds", "login for 1 use", "revoke tokens"] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// admin may grant and revoke the superuser role
+#[test]
+fn superusers() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              grant superuser bob
+              revoke superuser bob
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    let bob = Principal {
+        ident: Identifier {
+            name: "bob".to_string(),
+        },
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::GrantSuperuser(bob.clone()),
+            PrimitiveCommand::RevokeSuperuser(bob),
+        ]
+    );
+
+    for source in &["grant superuser", "grant bob"] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// denials are set and deleted like delegations, without a delegator, and listed by a function
+#[test]
+fn denials() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              set denial x read -> bob
+              delete denial all write -> anyone
+              set denial = denials(x.f)
+              return denials
+       ***"#
+            .to_string(),
+    )?;
+    let principal = |name: &str| Principal {
+        ident: Identifier {
+            name: name.to_string(),
+        },
+    };
+    let identifier = |name: &str| Identifier {
+        name: name.to_string(),
+    };
+    assert_eq!(
+        program.commands,
+        vec![
+            PrimitiveCommand::SetDenial(Denial {
+                target: Target::Variable(identifier("x")),
+                right: Right::Read,
+                denied: principal("bob"),
+            }),
+            PrimitiveCommand::DeleteDenial(Denial {
+                target: Target::All,
+                right: Right::Write,
+                denied: principal("anyone"),
+            }),
+            PrimitiveCommand::Assignment(Assignment {
+                variable: Variable::Variable(identifier("denial")),
+                expr: Expr::Function(Function::Denials(Target::Field(
+                    identifier("x"),
+                    identifier("f")
+                ))),
+            }),
+        ]
+    );
+    assert_eq!(
+        program.terminator,
+        TerminatorCommand::Return(Expr::Value(Value::Variable(Variable::Variable(
+            identifier("denials")
+        ))))
+    );
+
+    for source in &[
+        "set denial x admin read -> bob",
+        "delete denial x read",
+        "local d = denials()",
+    ] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// a delegation target may be a pattern, matching every variable with its prefix
+#[test]
+fn pattern_delegations() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              set delegation reports_* admin read -> bob
+              delete delegation * admin write -> bob
+              return denials(all)
+       ***"#
+            .to_string(),
+    )?;
+    let targets: Vec<Target> = program
+        .commands
+        .into_iter()
+        .map(|command| match command {
+            PrimitiveCommand::SetDelegation(d, _) | PrimitiveCommand::DeleteDelegation(d) => {
+                d.target
+            }
+            other => panic!("{:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        targets,
+        vec![
+            Target::Pattern("reports_".to_string()),
+            Target::Pattern(String::new())
+        ]
+    );
+
+    for source in &[
+        "set delegation reports_*.f admin read -> bob",
+        "set delegation *x admin read -> bob",
+        "set delegation 1* admin read -> bob",
+    ] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// x@n reads an old version of a global variable, and history(x) lists the versions kept
+#[test]
+fn version_history() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              set y = x@3
+              return history(x)
+       ***"#
+            .to_string(),
+    )?;
+    let x = Identifier {
+        name: "x".to_string(),
+    };
+    assert_eq!(
+        program.commands,
+        vec![PrimitiveCommand::Assignment(Assignment {
+            variable: Variable::Variable(Identifier {
+                name: "y".to_string()
+            }),
+            expr: Expr::Value(Value::Version(x.clone(), 3))
+        })]
+    );
+    assert_eq!(
+        program.terminator,
+        TerminatorCommand::Return(Expr::Function(Function::History(x)))
+    );
+
+    for source in &[
+        "return x@0",
+        "return x@",
+        "return x.f@1",
+        "return x @1",
+        "return history(x.f)",
+        "return history(\"x\")",
+    ] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// restore goes back to the checkpoint of a numbered commit
+#[test]
+fn restore() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              restore to 12
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    assert_eq!(program.commands, vec![PrimitiveCommand::Restore(12)]);
+
+    for source in &["restore to 0", "restore 12", "restore to x"] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// metadata(x) describes a global variable
+#[test]
+fn metadata() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal admin password "admin" do
+              return metadata( x )
+       ***"#
+            .to_string(),
+    )?;
+    assert_eq!(
+        program.terminator,
+        TerminatorCommand::Return(Expr::Function(Function::Metadata(Identifier {
+            name: "x".to_string()
+        })))
+    );
+
+    for source in &[
+        "return metadata(x.f)",
+        "return metadata()",
+        "return metadata(all)",
+    ] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+// watch subscribes to changes of a global variable
+#[test]
+fn watch() -> Result<(), Box<dyn Error>> {
+    let program = parse(
+        r#"as principal bob password "bob" do
+              watch x
+              return ""
+       ***"#
+            .to_string(),
+    )?;
+    assert_eq!(
+        program.commands,
+        vec![PrimitiveCommand::Watch(Identifier {
+            name: "x".to_string()
+        })]
+    );
+
+    for source in &["watch x.f", "watch", "watch all"] {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\n{}\nreturn \"\"\n***",
+            source
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn keyword_prefixes() -> Result<(), Box<dyn Error>> {
+    // identifiers which only start with a keyword
+    let identifiers = [
+        "endpoint",
+        "ifx",
+        "thence",
+        "elsewhere",
+        "defined_x",
+        "splitter",
+        "equals",
+        "concatenated",
+        "notequals",
+        "letter",
+        "filtereach2",
+        "enabled",
+        "disabled",
+        "address",
+        "owner",
+        "group1",
+        "fromage",
+        "removed",
+        "login_count",
+        "revoked",
+        "grantee",
+        "restored",
+        "watchlist",
+        "total",
+        "setting",
+        "reader",
+    ];
+    // and every keyword followed by another identifier character
+    let keywords = [
+        "all",
+        "append",
+        "as",
+        "change",
+        "create",
+        "default",
+        "delegation",
+        "delegator",
+        "delete",
+        "do",
+        "exit",
+        "foreach",
+        "in",
+        "local",
+        "password",
+        "principal",
+        "read",
+        "replacewith",
+        "return",
+        "set",
+        "to",
+        "write",
+    ];
+    let extended = keywords.iter().flat_map(|keyword| {
+        vec![
+            format!("{}s", keyword),
+            format!("{}_", keyword),
+            format!("{}1", keyword),
+        ]
+    });
+    for name in identifiers
+        .iter()
+        .map(|name| name.to_string())
+        .chain(extended)
+    {
+        let program = parse(format!(
+            "as principal {} password \"p\" do\nset {} = \"v\"\nreturn {}\n***",
+            name, name, name
+        ))?;
+        let identifier = Identifier { name };
+        assert_eq!(program.principal.ident, identifier);
+        assert_eq!(
+            program.commands,
+            vec![PrimitiveCommand::Assignment(Assignment {
+                variable: Variable::Variable(identifier.clone()),
+                expr: Expr::Value(Value::String("v".to_string()))
+            })]
+        );
+        assert_eq!(
+            program.terminator,
+            TerminatorCommand::Return(Expr::Value(Value::Variable(Variable::Variable(identifier))))
+        );
+    }
+
+    // the keywords themselves are still not identifiers
+    for keyword in &keywords {
+        assert!(parse(format!(
+            "as principal admin password \"admin\" do\nset {} = \"v\"\nreturn \"\"\n***",
+            keyword
+        ))
+        .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unreserved_words() -> Result<(), Box<dyn Error>> {
+    // words which only have a meaning in the syntax added since the original grammar are still
+    // names everywhere else, so programs which used them as names keep parsing
+    let words = [
+        "add",
+        "and",
+        "concat",
+        "defined",
+        "denial",
+        "denials",
+        "disable",
+        "else",
+        "enable",
+        "end",
+        "equal",
+        "filtereach",
+        "for",
+        "from",
+        "grant",
+        "group",
+        "history",
+        "if",
+        "let",
+        "login",
+        "metadata",
+        "notequal",
+        "owner",
+        "remove",
+        "restore",
+        "revoke",
+        "seconds",
+        "split",
+        "superuser",
+        "then",
+        "token",
+        "tokens",
+        "tolower",
+        "uses",
+        "watch",
+        "with",
+    ];
+    for word in &words {
+        let program = parse(format!(
+            r#"as principal {w} password "p" do
+              create principal {w} "p"
+              set {w} = {{ {w} = "v" }}
+              append to {w} with {w}.{w}
+              set delegation {w} admin read -> {w}
+              delete delegation {w}.{w} {w} write -> anyone
+              foreach {w} in {w} replacewith {w}
+              if {w} == {w} then
+                delete {w}
+              end
+              return {w}.{w}
+       ***"#,
+            w = word
+        ))?;
+        let identifier = Identifier {
+            name: word.to_string(),
+        };
+        assert_eq!(program.principal.ident, identifier);
+        assert_eq!(
+            program.commands[1],
+            PrimitiveCommand::Assignment(Assignment {
+                variable: Variable::Variable(identifier.clone()),
+                expr: Expr::FieldVals(vec![Assignment {
+                    variable: Variable::Variable(identifier.clone()),
+                    expr: Expr::Value(Value::String("v".to_string()))
+                }])
+            })
+        );
+        assert_eq!(
+            program.terminator,
+            TerminatorCommand::Return(Expr::Value(Value::Variable(Variable::Member(
+                identifier.clone(),
+                Box::new(Variable::Variable(identifier))
+            ))))
+        );
+    }
+
+    Ok(())
+}
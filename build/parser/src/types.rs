@@ -86,14 +86,23 @@ use std::ops::Not;
 pub struct Program {
     /// The principal executing this program.
     pub principal: Principal,
-    /// The password used to authenticate the principal.
-    pub password: [u8; 32],
+    /// How the principal is authenticated.
+    pub credential: Credential,
     /// The commands for the program.
     pub commands: Vec<PrimitiveCommand>,
     /// The termination command for the program
     pub terminator: TerminatorCommand,
 }
 
+/// The secret a program's header authenticates its principal with, either `password "s"` or
+/// `token "t"`, where t was returned by [Login](enum.PrimitiveCommand.html#variant.Login). Both are
+/// hashed, so neither is kept in the parsed program.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Credential {
+    Password([u8; 32]),
+    Token([u8; 32]),
+}
+
 /// Each program is run as a different user, referred to as a principal. Whichever principal runs
 /// the program determines what data the program can access. The program in this example is being
 /// run by a principal called admin, which is the superuser of the system; we will return to admin’s
@@ -136,17 +145,135 @@ pub enum PrimitiveCommand {
     /// Failure conditions:
     ///  - Fails if p already exists as a principal.
     ///  - Security violation if the current principal is not admin.
+    ///  - Fails if s does not satisfy the password policy, which by default accepts every
+    ///    password.
     ///
     /// Successful status code: CREATE_PRINCIPAL
     CreatePrincipal(CreatePrincipal),
-    /// Changes the principal p’s password to s.
+    /// Changes the principal p’s password to s. Every session token p was given is revoked.
     ///
     /// Failure conditions:
     ///  - Fails if p does not exist
     ///  - Security violation if the current principal is neither admin nor p itself.
+    ///  - Fails if s does not satisfy the password policy, or repeats one of p's recent
+    ///    passwords when the policy remembers them.
     ///
     /// Successful status code: CHANGE_PASSWORD
     ChangePassword(ChangePassword),
+    /// Deletes the principal p. Every delegation assertion naming p, as delegator or as delegated
+    /// principal, is removed with it, so principals who held a right only through p lose it. If p
+    /// is the default delegator, the default delegator becomes anyone again. A principal created
+    /// later under the same name starts afresh.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin.
+    ///  - Fails if p does not exist
+    ///  - Fails if p is admin or anyone.
+    ///
+    /// Successful status code: DELETE_PRINCIPAL
+    DeletePrincipal(Principal),
+    /// Creates a group g, owned by p if an owner is given and by admin otherwise. A group is a
+    /// principal which cannot log in, but which may be given rights by `set delegation` and pass
+    /// them on, just like any other principal. Each member of g has every right g has; this
+    /// applies through any number of groups and delegations, so a member of g may in turn pass
+    /// those rights on if g has delegate permission. Unlike create principal, the default
+    /// delegator delegates nothing to a new group. A group is deleted with delete principal.
+    ///
+    /// ```text
+    /// create group team owner alice
+    /// set delegation x admin read -> team
+    /// add principal bob to group team
+    /// ```
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin.
+    ///  - Fails if g already exists as a principal.
+    ///  - Fails if p does not exist, or is anyone or a group.
+    ///
+    /// Successful status code: CREATE_GROUP
+    CreateGroup(CreateGroup),
+    /// Adds p to the group g. Adding a principal which is already a member has no effect.
+    ///
+    /// Failure conditions:
+    ///  - Fails if p or g does not exist
+    ///  - Fails if p is admin or anyone.
+    ///  - Fails if g is not a group.
+    ///  - Security violation if the current principal is neither admin nor the owner of g.
+    ///
+    /// Successful status code: ADD_MEMBER
+    AddMember(Membership),
+    /// Removes p from the group g, so that p no longer has the rights it held through g. Removing
+    /// a principal which is not a member has no effect. The failure conditions are those of
+    /// [AddMember](#variant.AddMember).
+    ///
+    /// Successful status code: REMOVE_MEMBER
+    RemoveMember(Membership),
+    /// Disables the principal p: programs run as p are security violations until p is enabled
+    /// again. Disabling p does not affect its delegations, so rights still flow through p to the
+    /// principals it delegated to. Disabling a disabled principal has no effect.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin.
+    ///  - Fails if p does not exist
+    ///  - Fails if p is admin or anyone.
+    ///
+    /// Successful status code: DISABLE_PRINCIPAL
+    DisablePrincipal(Principal),
+    /// Enables the principal p again after `disable principal p`. Enabling a principal which is
+    /// not disabled has no effect.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin.
+    ///  - Fails if p does not exist
+    ///  - Fails if p is admin or anyone.
+    ///
+    /// Successful status code: ENABLE_PRINCIPAL
+    EnablePrincipal(Principal),
+    /// Opens a session for the current principal, and outputs its token:
+    ///
+    /// ```javascript
+    /// {"status":"LOGIN","output":"3f9c...e1"}
+    /// ```
+    ///
+    /// Later programs may then start with `as principal p token "3f9c...e1"` in place of the
+    /// password. The token expires after <n> seconds if the command is `login for <n> seconds`,
+    /// or after an hour otherwise. It stops working early if it is revoked, or if p's password is
+    /// changed or p is deleted. As with every other command, the session is only opened if the
+    /// program succeeds.
+    ///
+    /// Failure conditions:
+    ///  - Fails if <n> is more than 3600, an hour.
+    ///  - Security violation if the program authenticated with a token rather than the password,
+    ///    so that a token cannot be renewed.
+    ///
+    /// Successful status code: LOGIN
+    Login(Option<u64>),
+    /// Revokes every session token of the principal p, so that p must supply its password again.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is neither admin nor p itself.
+    ///  - Fails if p does not exist
+    ///
+    /// Successful status code: REVOKE_TOKENS
+    RevokeTokens(Principal),
+    /// Makes the user p a superuser. A superuser may do everything admin may: wherever a command
+    /// requires the current principal to be admin, a superuser is accepted too, and a superuser
+    /// holds every right on every variable, which it can delegate like admin does. Unlike admin,
+    /// a superuser can be deleted or disabled, and its role can be revoked.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin or a superuser.
+    ///  - Fails if p does not exist
+    ///  - Fails if p is admin, anyone, or a group.
+    ///
+    /// Successful status code: GRANT_SUPERUSER
+    GrantSuperuser(Principal),
+    /// Takes the superuser role away from p again, leaving it with only the rights delegated to
+    /// it. Revoking the role of a principal which does not have it has no effect. The failure
+    /// conditions are those of [GrantSuperuser](#variant.GrantSuperuser).
+    ///
+    /// Successful status code: REVOKE_SUPERUSER
+    RevokeSuperuser(Principal),
     /// Sets x’s value to the result of evaluating <expr>, where x is a global or local variable.
     /// If x does not exist this command creates it as a global.  If x is created by this command,
     /// and the current principal is not admin, then the current principal is delegated read, write,
@@ -228,23 +355,100 @@ pub enum PrimitiveCommand {
     ///
     /// Successful status code: FOREACH
     ForEach(ForEach),
+    /// For each element y in list x, keeps y in x if <cond> holds, and removes it otherwise. <cond>
+    /// is evaluated once per element, in order, with y bound to the current element, and takes
+    /// the same forms as in a [Conditional](#variant.Conditional). For example, given the records
+    /// list from the foreach example above,
+    ///
+    /// ```text
+    /// filtereach rec in records with rec.name != "mike"
+    /// ```
+    ///
+    /// leaves records holding only dave's record. The elements that are kept are not copied or
+    /// changed, and keep their relative order.
+    ///
+    /// Failure conditions:
+    ///  - Fails if x is not defined
+    ///  - Security violation if the current principal does not have read and write permission on x.
+    ///  - Fails if y is already defined as a local or global variable.
+    ///  - Fails if x is not a list.
+    ///  - If any evaluation of <cond> fails or has a security violation, then entire filtereach
+    ///    does.
+    ///
+    /// Successful status code: FILTEREACH
+    FilterEach(FilterEach),
+    /// Runs one of two blocks of commands, depending on whether <cond> holds. Each block is a
+    /// sequence of commands, one per line, and may itself contain conditionals:
+    ///
+    /// ```text
+    /// if x == "yes" then
+    ///    set y = "agreed"
+    /// else
+    ///    set y = "refused"
+    /// end
+    /// ```
+    ///
+    /// The else block may be omitted. <cond> is one of:
+    ///  - `<expr> == <expr>` or `<expr> != <expr>`, comparing two strings;
+    ///  - `defined x`, which holds if x is a local or global variable; or
+    ///  - `defined x.y`, which holds if x is defined and is a record with a field y.
+    ///
+    /// The commands of the block which is not taken are not run, and cannot fail. Local variables
+    /// created within a block remain defined until the end of the program. As with every other
+    /// command, a failure or security violation in the taken block rolls back the entire program.
+    ///
+    /// Failure conditions:
+    ///  - May fail or have a security violation due to evaluating either <expr>
+    ///  - Fails if either <expr> does not evaluate to a string
+    ///  - Security violation if <cond> is `defined x.y`, x is a global variable, and the current
+    ///    principal has read permission on neither x nor x.y.
+    ///  - Fails or has a security violation if any command in the taken block does.
+    ///
+    /// Successful status code: IF_THEN or IF_ELSE, depending on the block taken, followed by the
+    /// status codes of the commands in that block.
+    Conditional(Conditional),
     /// When <tgt> is a variable x, Indicates that q delegates <right> to p on x, so that p is given
     /// <right> whenever q is. If p is anyone, then effectively all principals are given <right> on
     /// x (for more detail, see here). When <tgt> is the keyword all then q delegates <right> to p
-    /// for all variables on which q (currently) has delegate permission.
+    /// for all variables on which q (currently) has delegate permission. When <tgt> is a field
+    /// x.y, q delegates <right> to p on that field alone: p may then read (or write) x.y without
+    /// holding any right on the rest of x. A right on x is also a right on each of its fields.
+    ///
+    /// When <tgt> is a pattern such as `reports_*`, q delegates <right> to p on every variable
+    /// whose name starts with `reports_`, including variables created later; the pattern `*`
+    /// covers every variable in the same way. This is a single delegation, checked whenever a
+    /// right is, rather than one delegation per variable as for all.
     ///
     /// Failure conditions:
     ///  - Fails if either p or q does not exist
     ///  - Fails if x does not exist or if it is a local variable, if <tgt> is a variable x.
+    ///  - Fails if x does not exist, is a local variable, is not a record, or has no field y, if
+    ///    <tgt> is a field x.y.
     ///  - Security violation unless the current principal is admin or q; if the principal is q and
-    ///    <tgt> is the variable x, then q must have delegate permission on x.
+    ///    <tgt> is the variable x (or field x.y, or a pattern), then q must have delegate
+    ///    permission on it. Only a delegation on the same pattern or a broader one gives delegate
+    ///    permission on a pattern.
+    ///
+    /// The delegation may be bounded by a trailing clause: `for <n> seconds` makes it expire <n>
+    /// seconds after it is made, `for <n> uses` makes it expire after it has been relied on by <n>
+    /// operations (by p, or by anyone p passes the right on to), and `for <n> seconds and <m>
+    /// uses` does both, expiring at whichever comes first. Each command, each condition and the
+    /// return value is an operation, which uses the delegation once however often it relies on it.
+    /// An expired delegation is ignored, as if it had been deleted. A use only counts if the
+    /// program which made it succeeds.
+    ///
+    /// ```text
+    /// set delegation x admin read -> contractor for 86400 seconds
+    /// set delegation y admin write -> bob for 1 use
+    /// ```
     ///
     /// Successful status code: SET_DELEGATION
-    SetDelegation(Delegation),
+    SetDelegation(Delegation, Limit),
     /// When <tgt> is a variable x, indicates that q revokes a delegation assertion of <right> to p
     /// on x. In effect, this command revokes a previous command set delegation x q <right> -> p;
     /// see below for the precise semantics of what this means. If <tgt> is the keyword all then q
     /// revokes delegation of <right> to p for all variables on which q has delegate permission.
+    /// If <tgt> is a pattern, only the delegation made on that same pattern is revoked.
     ///
     /// Failure conditions:
     ///  - Fails if either p or q does not exist
@@ -256,6 +460,39 @@ pub enum PrimitiveCommand {
     ///
     /// Successful status code: DELETE_DELEGATION
     DeleteDelegation(Delegation),
+    /// Deletes the variable x. If x is a local variable it is simply removed; if it is a global
+    /// variable, it is removed together with every delegation on x, so that its name can be
+    /// reused. A delegation made with <tgt> all only ever covered the variables which existed at
+    /// the time, so if x is later created again, nobody (other than admin and its creator) has any
+    /// rights on it until they are delegated anew.
+    ///
+    /// Failure conditions:
+    ///  - Fails if x does not exist
+    ///  - Security violation if x is a global variable and the current principal does not have
+    ///    write permission on x.
+    ///
+    /// Successful status code: DELETE_VARIABLE
+    DeleteVariable(Identifier),
+    /// Denies <right> on <tgt> to p: p does not hold <right> there, whatever has been delegated to
+    /// it, and neither does any principal whose right would flow through p (by delegation from p,
+    /// or membership of the group p). If p is anyone, no principal holds <right> on <tgt>. When
+    /// <tgt> is the keyword all, the denial applies to every variable, including those created
+    /// later. A denial on a field x.y also applies to x as a whole. Superusers are never denied
+    /// anything. The denial is removed if x or p is deleted.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin or a superuser.
+    ///  - Fails if p does not exist or is admin.
+    ///  - Fails if x does not exist, if <tgt> is a variable x, or if x has no field y, if <tgt> is
+    ///    a field x.y.
+    ///
+    /// Successful status code: SET_DENIAL
+    SetDenial(Denial),
+    /// Removes a denial made by [SetDenial](#variant.SetDenial) with the same <tgt>, <right> and p,
+    /// if there is one. The failure conditions are those of SetDenial.
+    ///
+    /// Successful status code: DELETE_DENIAL
+    DeleteDenial(Denial),
     /// Sets the “default delegator” to p. This means that when a principal q is created, the system
     /// automatically delegates all from p to q. Changing the default delegator does not affect the
     /// permissions of existing principals. The initial default delegator is anyone.
@@ -266,6 +503,44 @@ pub enum PrimitiveCommand {
     ///
     /// Successful status code: DEFAULT_DELEGATOR
     DefaultDelegator(Principal),
+    /// Puts the whole database back as it was once the program with commit sequence number <n>
+    /// had committed: principals, passwords, groups, delegations, denials, variables and their
+    /// histories all roll back together. Sessions do not: those closed since stay closed, and
+    /// those of principals which no longer exist are closed. The server keeps a checkpoint for
+    /// each of the most recent programs to commit, 16 unless configured otherwise. The rest of the
+    /// program runs against the restored state, and numbering carries on from this program, so a
+    /// restore can itself be undone by restoring to a later checkpoint.
+    ///
+    /// Failure conditions:
+    ///  - Security violation if the current principal is not admin or a superuser.
+    ///  - Fails if no checkpoint of program <n> is kept.
+    ///
+    /// Successful status code: RESTORE
+    Restore(u64),
+    /// Subscribes the connection to changes of the global variable x, and outputs x:
+    ///
+    /// ```javascript
+    /// {"status":"WATCH","output":"x"}
+    /// ```
+    ///
+    /// Once the program commits, the server keeps the connection open after the program's output,
+    /// and sends an event each time a later program which changes x commits:
+    ///
+    /// ```javascript
+    /// {"status":"CHANGED","variable":"x","output":"new value"}
+    /// {"status":"DELETED","variable":"x"}
+    /// ```
+    ///
+    /// Programs which are rolled back send no events. Read permission on x is checked again each
+    /// time a program commits; once the current principal loses it, or x is deleted, x is no
+    /// longer watched, and the connection is closed once nothing is.
+    ///
+    /// Failure conditions:
+    ///  - Fails if x does not exist as a global.
+    ///  - Security violation if the current principal does not have read permission on x.
+    ///
+    /// Successful status code: WATCH
+    Watch(Identifier),
 }
 
 /// The struct containing the data required to represent the
@@ -274,8 +549,30 @@ pub enum PrimitiveCommand {
 pub struct CreatePrincipal {
     /// The principal to be created.
     pub principal: Principal,
-    /// The password to be used for the principal.
-    pub password: [u8; 32],
+    /// The password to be used for the principal. It is left unhashed so that the database can
+    /// check it against the password policy.
+    pub password: String,
+}
+
+/// The struct containing the data required to represent the
+/// [CreateGroup](enum.PrimitiveCommand.html#variant.CreateGroup) primitive command.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct CreateGroup {
+    /// The group to be created.
+    pub group: Principal,
+    /// The principal who may manage its members, if not admin.
+    pub owner: Option<Principal>,
+}
+
+/// The struct containing the data required to represent the
+/// [AddMember](enum.PrimitiveCommand.html#variant.AddMember) and
+/// [RemoveMember](enum.PrimitiveCommand.html#variant.RemoveMember) primitive commands.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct Membership {
+    /// The principal joining or leaving the group.
+    pub member: Principal,
+    /// The group.
+    pub group: Principal,
 }
 
 /// The struct containing the data required to represent the
@@ -284,8 +581,9 @@ pub struct CreatePrincipal {
 pub struct ChangePassword {
     /// The principal who's password will be changed.
     pub principal: Principal,
-    /// The password to set it to (hashed).
-    pub password: [u8; 32],
+    /// The password to set it to. It is left unhashed so that the database can check it against
+    /// the password policy.
+    pub password: String,
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
@@ -307,6 +605,38 @@ pub struct ForEach {
     pub expr: Expr,
 }
 
+/// The struct containing the data required to represent the
+/// [FilterEach](enum.PrimitiveCommand.html#variant.FilterEach) primitive command.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct FilterEach {
+    /// The name each element is bound to while the condition is evaluated.
+    pub value: Variable,
+    /// The list being filtered.
+    pub list: Variable,
+    /// The condition an element must satisfy to be kept.
+    pub condition: Condition,
+}
+
+/// The struct containing the data required to represent the
+/// [Conditional](enum.PrimitiveCommand.html#variant.Conditional) primitive command.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct Conditional {
+    /// The condition deciding which block is run.
+    pub condition: Condition,
+    /// The commands run if the condition holds.
+    pub then: Vec<PrimitiveCommand>,
+    /// The commands run otherwise; empty if there was no else block.
+    pub otherwise: Vec<PrimitiveCommand>,
+}
+
+/// The condition of a [Conditional](struct.Conditional.html).
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub enum Condition {
+    Equal(Expr, Expr),
+    NotEqual(Expr, Expr),
+    Defined(Variable),
+}
+
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub struct Delegation {
     pub target: Target,
@@ -315,17 +645,95 @@ pub struct Delegation {
     pub delegated: Principal,
 }
 
+/// A negative assertion, written `denial <tgt> <right> -> p`.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct Denial {
+    pub target: Target,
+    pub right: Right,
+    pub denied: Principal,
+}
+
+/// The bounds on a delegation made by [SetDelegation](enum.PrimitiveCommand.html#variant.SetDelegation);
+/// by default there are none.
+#[derive(Hash, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Limit {
+    /// How long the delegation lasts, in seconds.
+    pub seconds: Option<u64>,
+    /// How many times the delegation may be used.
+    pub uses: Option<u64>,
+}
+
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub enum Expr {
     Value(Value),
     EmptyList,
     FieldVals(Vec<Assignment>),
+    Function(Function),
+    Let(Let),
+}
+
+/// `let x = <expr> in <expr'>`: evaluates <expr>, then evaluates <expr'> with x bound to the
+/// result. x is only visible in <expr'>, and disappears once the expression has been evaluated.
+///
+/// Failure conditions:
+///  - Fails if x is already defined as a local or global variable (including by an enclosing
+///    `let` or `foreach`).
+///  - May fail or have a security violation due to evaluating <expr> or <expr'>
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub struct Let {
+    /// The name bound in the body.
+    pub variable: Identifier,
+    /// The expression whose result x is bound to.
+    pub value: Box<Expr>,
+    /// The expression evaluated with x in scope; its result is the result of the whole `let`.
+    pub body: Box<Expr>,
+}
+
+/// A built-in function, applied to values or to the results of other functions. Unless stated
+/// otherwise, every argument must evaluate to a string, or the expression fails. Arguments are
+/// evaluated from left to right, and may fail or have a security violation like any other value.
+#[derive(Hash, Clone, PartialEq, Eq, Debug)]
+pub enum Function {
+    /// `concat(a, b)`: the concatenation of a and b, truncated to 65,535 characters.
+    Concat(Box<Expr>, Box<Expr>),
+    /// `tolower(a)`: a with every uppercase letter replaced by its lowercase letter.
+    ToLower(Box<Expr>),
+    /// `equal(a, b)`: the empty string if a and b are equal, and "0" otherwise. a and b may both
+    /// be strings or both be records; comparing anything else fails.
+    Equal(Box<Expr>, Box<Expr>),
+    /// `notequal(a, b)`: the opposite of `equal(a, b)`, with the same failure conditions.
+    NotEqual(Box<Expr>, Box<Expr>),
+    /// `split(a, b)`: a record `{fst = ..., snd = ...}` holding the parts of a before and after
+    /// the first occurrence of b. If b does not occur in a (or is empty), fst is a and snd is empty.
+    Split(Box<Expr>, Box<Expr>),
+    /// `denials(<tgt>)`: a list of records `{target = ..., right = ..., principal = ...}`, one for
+    /// each [denial](enum.PrimitiveCommand.html#variant.SetDenial) applying to <tgt>, in the order
+    /// they were set. <tgt> is not evaluated. Fails if <tgt> does not exist; security violation if
+    /// the current principal is not admin or a superuser.
+    Denials(Target),
+    /// `history(x)`: a list of records `{sequence = ..., principal = ...}`, one for each version
+    /// of the global variable x still kept, oldest first: the commit sequence number of the program
+    /// which wrote it, and the principal it ran as. The last one is the current value. Fails if x
+    /// does not exist; security violation if the current principal does not have read permission
+    /// on x.
+    History(Identifier),
+    /// `metadata(x)`: a record `{creator = ..., writer = ..., created = ..., modified = ...,
+    /// size = ...}` describing the global variable x: the principals which created it and last
+    /// wrote it, when they did so in seconds since the Unix epoch, and the number of bytes of
+    /// text its value holds. Fails if x does not exist; security violation if the current
+    /// principal does not have read permission on x.
+    Metadata(Identifier),
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub enum Value {
     Variable(Variable),
     String(String),
+    /// `x@n`: the value the global variable x held once the program with commit sequence number
+    /// n had run. Every program which commits is numbered, counting from 1. Fails if x does not
+    /// exist now, or if no version of x from program n or earlier is kept; security violation if
+    /// the current principal does not have read permission on x as it is now.
+    Version(Identifier, u64),
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
@@ -345,6 +753,11 @@ pub enum Type {
 pub enum Target {
     All,
     Variable(Identifier),
+    /// A field of a record, written `x.y`.
+    Field(Identifier, Identifier),
+    /// Every variable whose name starts with the prefix, written `prefix*`, or every variable,
+    /// written `*`. Unlike all, a pattern also covers variables created later.
+    Pattern(String),
 }
 
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
@@ -0,0 +1,73 @@
+//! Differential checking of the runtime against the reference model. Both are fed the same
+//! sequence of programs, starting from the same admin password, and their outputs are compared
+//! program by program.
+
+use crate::Reference;
+use bibifi_database::Clock;
+use bibifi_runtime::status::{Entry, Status};
+use bibifi_runtime::Engine;
+use std::fmt;
+
+/// The first program on which the runtime and the reference model disagree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Divergence {
+    /// The position of the program in the submitted sequence.
+    pub index: usize,
+    /// The program itself.
+    pub program: String,
+    /// The output of the reference model.
+    pub expected: Vec<Entry>,
+    /// The output of the runtime.
+    pub actual: Vec<Entry>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "divergence on program {}:", self.index)?;
+        writeln!(f, "{}", self.program)?;
+        writeln!(f, "reference: {:?}", self.expected)?;
+        write!(f, "runtime:   {:?}", self.actual)
+    }
+}
+
+/// The entries with the tokens output by `login` removed, since each side issues its own random
+/// tokens.
+fn normalized(entries: &[Entry]) -> Vec<Entry> {
+    entries
+        .iter()
+        .map(|entry| match entry.status {
+            Status::LOGIN => Entry {
+                status: Status::LOGIN,
+                output: None,
+            },
+            _ => entry.clone(),
+        })
+        .collect()
+}
+
+/// Runs `programs` in order through both the runtime and the reference model, returning the first
+/// divergence, if any. Checking stops early once both agree that the server has exited. The
+/// runtime's clock is stopped at 0, since the model has none.
+pub fn check(admin_hash: [u8; 32], programs: &[String]) -> Option<Divergence> {
+    let mut engine = Engine::with_clock(admin_hash, Clock::new(|| 0));
+    let mut reference = Reference::new(admin_hash);
+    for (index, program) in programs.iter().enumerate() {
+        let expected = reference.run_program(program.clone());
+        let actual = match engine.execute(program) {
+            Ok(entries) => entries,
+            Err(error) => vec![Entry::from(error)],
+        };
+        if normalized(&expected) != normalized(&actual) {
+            return Some(Divergence {
+                index,
+                program: program.clone(),
+                expected,
+                actual,
+            });
+        }
+        if actual.iter().any(|entry| entry.status == Status::EXITING) {
+            break;
+        }
+    }
+    None
+}
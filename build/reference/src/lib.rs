@@ -0,0 +1,1082 @@
+//! The bibifi-reference module is an executable model of the specification, intended to be run
+//! side by side with the real runtime (see [diff](diff/index.html)). It is deliberately simple and
+//! slow: the whole state is cloned for every program, and delegation is modelled as an explicit
+//! set of assertions which is searched from scratch on every right check. Nothing here is meant to
+//! be served to clients.
+//!
+//! The model reads programs with the same parser as the runtime, and answers with the same entry
+//! and status types, so it checks what is done with a parsed program but not the parsing itself.
+//!
+//! Where the specification lists several failure conditions for a command, they are checked in
+//! the order they are listed, except that the expressions of a command are always evaluated before
+//! the command's own checks.
+//!
+//! Limits on delegations (`for <n> seconds`, `for <n> uses`) are not modelled: the model has no
+//! clock, and which delegations a right check uses depends on the runtime's search order. Limited
+//! delegations are treated as unlimited, so programs relying on a limit being reached will diverge.
+//!
+//! Session tokens never expire in the model, for the same reason, though a `login` asking for
+//! more than an hour still fails. Tokens are random, so the [diff](diff/index.html) only compares
+//! that a token was issued, not its value.
+//!
+//! A delegation on a record field `x.f` is an assertion whose target is `x.f`, and an assertion on
+//! `x` covers each of its fields. Only the default field policy, which denies reading a whole
+//! record to a principal with rights on just some of its fields, is modelled. Likewise only the
+//! default password policy, which accepts every password, is modelled.
+//!
+//! Each committed program is numbered, and every write of a global variable is kept as a version
+//! tagged with the program's number and principal. Only the default history policy, which keeps
+//! the last 16 versions of each variable however large they are, is modelled.
+//!
+//! A delegation on a pattern such as `reports_*` is a single assertion whose target is the
+//! pattern itself, which covers every variable, field and narrower pattern starting with
+//! `reports_`.
+//!
+//! Variable metadata is modelled by the principals which created and last wrote each variable;
+//! since the model has no clock, its times are always 0, and the [diff](diff/index.html) runs the
+//! runtime on a clock stopped at 0 to match.
+//!
+//! The state after each of the last 16 programs to commit is kept as a checkpoint, which
+//! `restore` copies back whole apart from the program numbering and the sessions. Sessions are
+//! kept, except those of principals the checkpoint does not have.
+
+pub mod diff;
+
+use bibifi_database::Value;
+use bibifi_parser::parse;
+use bibifi_parser::types::*;
+use bibifi_parser::types::{Right as ParserRight, Target as ParserTarget, Value as ParserValue};
+use bibifi_runtime::status::{Entry, Status};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// A right which may be held on a global variable.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Right {
+    Read,
+    Write,
+    Append,
+    Delegate,
+}
+
+const RIGHTS: [Right; 4] = [Right::Read, Right::Write, Right::Append, Right::Delegate];
+
+/// How many versions of each variable the default history policy keeps.
+const HISTORY: usize = 16;
+
+/// How many checkpoints the runtime keeps by default.
+const CHECKPOINTS: usize = 16;
+
+impl From<&ParserRight> for Right {
+    fn from(right: &ParserRight) -> Self {
+        match right {
+            ParserRight::Read => Right::Read,
+            ParserRight::Write => Right::Write,
+            ParserRight::Append => Right::Append,
+            ParserRight::Delegate => Right::Delegate,
+        }
+    }
+}
+
+/// A single delegation assertion, `target delegator right -> delegated`, exactly as it would be
+/// written in a `set delegation` command for a variable.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Assertion {
+    pub target: String,
+    pub delegator: String,
+    pub right: Right,
+    pub delegated: String,
+}
+
+/// Whether an assertion on `held` applies to `target`: they are the same, or `target` is a field
+/// of the variable `held`, or `held` is a pattern `prefix*` and `target` starts with the prefix.
+fn covers(held: &str, target: &str) -> bool {
+    held == target
+        || target
+            .strip_prefix(held)
+            .is_some_and(|field| field.starts_with('.'))
+        || held
+            .strip_suffix('*')
+            .is_some_and(|prefix| target.starts_with(prefix))
+}
+
+/// A denial, `denial target right -> principal`, where a target of none is `all`. It applies to
+/// the variable or field targeted, to the variable holding a field, and to the fields of a
+/// variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Denial {
+    target: Option<String>,
+    right: Right,
+    principal: String,
+}
+
+impl Denial {
+    fn applies(&self, target: &str) -> bool {
+        self.target
+            .as_ref()
+            .is_none_or(|key| covers(key, target) || covers(target, key))
+    }
+}
+
+/// A group: a principal which cannot log in, whose members have every right it has.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Group {
+    owner: String,
+    members: BTreeSet<String>,
+}
+
+/// The complete state of the reference model.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Reference {
+    /// Every principal and its password hash. `anyone` has no password and can never log in.
+    principals: HashMap<String, Option<[u8; 32]>>,
+    /// Principals which may not log in until they are enabled again.
+    disabled: BTreeSet<String>,
+    /// Users granted everything admin may do.
+    superusers: BTreeSet<String>,
+    /// The groups among the principals, which have no password.
+    groups: HashMap<String, Group>,
+    variables: HashMap<String, Value>,
+    assertions: BTreeSet<Assertion>,
+    default_delegator: String,
+    /// The principal of each open session, keyed by the hash of its token.
+    sessions: HashMap<[u8; 32], String>,
+    /// Denials in the order they were set.
+    denials: Vec<Denial>,
+    /// The number of the program running, or of the last one committed.
+    sequence: u64,
+    /// The versions of each global variable, oldest first, as `(sequence, principal, value)`.
+    versions: HashMap<String, Vec<(u64, String, Value)>>,
+    /// The principal which created each global variable.
+    creators: HashMap<String, String>,
+    /// The state after each of the most recent programs to commit, oldest first. The checkpoints
+    /// themselves hold none.
+    checkpoints: VecDeque<Reference>,
+}
+
+impl Reference {
+    pub fn new(admin_hash: [u8; 32]) -> Reference {
+        let mut principals = HashMap::new();
+        principals.insert("admin".to_string(), Some(admin_hash));
+        principals.insert("anyone".to_string(), None);
+        Reference {
+            principals,
+            disabled: BTreeSet::new(),
+            superusers: BTreeSet::new(),
+            groups: HashMap::new(),
+            variables: HashMap::new(),
+            assertions: BTreeSet::new(),
+            default_delegator: "anyone".to_string(),
+            sessions: HashMap::new(),
+            denials: Vec::new(),
+            sequence: 0,
+            versions: HashMap::new(),
+            creators: HashMap::new(),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// Runs a single program, committing its effects only if every command succeeds.
+    pub fn run_program(&mut self, program: String) -> Vec<Entry> {
+        let program = match parse(program) {
+            Ok(program) => program,
+            Err(_) => return vec![entry(Status::FAILED)],
+        };
+        let mut next = self.clone();
+        next.sequence += 1;
+        match next.execute(&program) {
+            Ok(entries) => {
+                let mut checkpoints = std::mem::take(&mut next.checkpoints);
+                if checkpoints.len() == CHECKPOINTS {
+                    checkpoints.pop_front();
+                }
+                checkpoints.push_back(next.clone());
+                next.checkpoints = checkpoints;
+                *self = next;
+                entries
+            }
+            Err(status) => vec![entry(status)],
+        }
+    }
+
+    /// Every assertion currently held, in a stable order.
+    pub fn assertions(&self) -> impl Iterator<Item = &Assertion> {
+        self.assertions.iter()
+    }
+
+    /// Whether `principal` holds `right` on `target`, which is either a variable `x` or a field
+    /// `x.f`: either it is a superuser, or some assertion on `target` for `right` names it (or
+    /// anyone) as delegated and its delegator holds the right, or it is a member of a group which
+    /// holds the right; and in the latter cases, it is not denied the right.
+    pub fn has_right(&self, principal: &str, target: &str, right: Right) -> bool {
+        self.search(principal, target, right, &mut BTreeSet::new())
+    }
+
+    fn search(
+        &self,
+        principal: &str,
+        target: &str,
+        right: Right,
+        visited: &mut BTreeSet<String>,
+    ) -> bool {
+        if self.is_superuser(principal) {
+            return true;
+        }
+        if self.denials.iter().any(|d| {
+            d.right == right
+                && (d.principal == principal || d.principal == "anyone")
+                && d.applies(target)
+        }) {
+            return false;
+        }
+        if !visited.insert(principal.to_string()) {
+            return false;
+        }
+        self.assertions
+            .iter()
+            .filter(|a| covers(&a.target, target) && a.right == right)
+            .filter(|a| a.delegated == principal || a.delegated == "anyone")
+            .any(|a| self.search(&a.delegator, target, right, visited))
+            || self
+                .groups
+                .iter()
+                .filter(|(_, g)| g.members.contains(principal))
+                .any(|(g, _)| self.search(g, target, right, visited))
+    }
+
+    fn execute(&mut self, program: &Program) -> Result<Vec<Entry>, Status> {
+        let user = &program.principal.ident.name;
+        match self.principals.get(user) {
+            None => return Err(Status::FAILED),
+            Some(Some(hash)) if program.credential == Credential::Password(*hash) => {}
+            Some(_) => match &program.credential {
+                Credential::Token(token) if self.sessions.get(token) == Some(user) => {}
+                _ => return Err(Status::DENIED),
+            },
+        }
+        ensure(!self.disabled.contains(user), Status::DENIED)?;
+        let by_token = matches!(program.credential, Credential::Token(_));
+        let mut locals = HashMap::new();
+        let mut entries = Vec::new();
+        for command in &program.commands {
+            self.run(user, by_token, &mut locals, command, &mut entries)?;
+        }
+        match &program.terminator {
+            TerminatorCommand::Exit => {
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                entries.push(entry(Status::EXITING));
+            }
+            TerminatorCommand::Return(e) => {
+                let value = self.evaluate(user, &locals, e)?;
+                entries.push(Entry {
+                    status: Status::RETURNING,
+                    output: Some(value),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Runs a command, recording its status and, for a conditional, those of the taken block.
+    /// `by_token` is whether the program authenticated with a token.
+    fn run(
+        &mut self,
+        user: &str,
+        by_token: bool,
+        locals: &mut HashMap<String, Value>,
+        command: &PrimitiveCommand,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), Status> {
+        if let PrimitiveCommand::Conditional(c) = command {
+            let holds = self.holds(user, locals, &c.condition)?;
+            let (status, block) = if holds {
+                (Status::IF_THEN, &c.then)
+            } else {
+                (Status::IF_ELSE, &c.otherwise)
+            };
+            entries.push(entry(status));
+            for command in block {
+                self.run(user, by_token, locals, command, entries)?;
+            }
+            Ok(())
+        } else if let PrimitiveCommand::Login(seconds) = command {
+            ensure(!by_token, Status::DENIED)?;
+            ensure(
+                seconds.is_none_or(|seconds| seconds <= 3600),
+                Status::FAILED,
+            )?;
+            let token = bibifi_util::token();
+            self.sessions
+                .insert(bibifi_util::hash(token.clone()), user.to_string());
+            entries.push(Entry {
+                status: Status::LOGIN,
+                output: Some(Value::Immediate(token)),
+            });
+            Ok(())
+        } else if let PrimitiveCommand::Watch(x) = command {
+            ensure(self.variables.contains_key(&x.name), Status::FAILED)?;
+            ensure(self.has_right(user, &x.name, Right::Read), Status::DENIED)?;
+            entries.push(Entry {
+                status: Status::WATCH,
+                output: Some(Value::Immediate(x.name.clone())),
+            });
+            Ok(())
+        } else {
+            entries.push(entry(self.command(user, locals, command)?));
+            Ok(())
+        }
+    }
+
+    fn holds(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        condition: &Condition,
+    ) -> Result<bool, Status> {
+        Ok(match condition {
+            Condition::Equal(a, b) => {
+                self.string(user, locals, a)? == self.string(user, locals, b)?
+            }
+            Condition::NotEqual(a, b) => {
+                self.string(user, locals, a)? != self.string(user, locals, b)?
+            }
+            Condition::Defined(Variable::Variable(x)) => self.is_defined(locals, &x.name),
+            Condition::Defined(Variable::Member(x, f)) => {
+                if !self.is_defined(locals, &x.name) {
+                    false
+                } else {
+                    if let (None, Variable::Variable(f)) = (locals.get(&x.name), f.as_ref()) {
+                        let field = format!("{}.{}", x.name, f.name);
+                        ensure(self.has_right(user, &field, Right::Read), Status::DENIED)?;
+                    }
+                    match (self.variable(locals, &x.name), f.as_ref()) {
+                        (Value::FieldVals(record), Variable::Variable(f)) => {
+                            record.contains_key(&f.name)
+                        }
+                        _ => false,
+                    }
+                }
+            }
+        })
+    }
+
+    fn command(
+        &mut self,
+        user: &str,
+        locals: &mut HashMap<String, Value>,
+        command: &PrimitiveCommand,
+    ) -> Result<Status, Status> {
+        match command {
+            PrimitiveCommand::CreatePrincipal(cp) => {
+                let p = &cp.principal.ident.name;
+                ensure(!self.principals.contains_key(p), Status::FAILED)?;
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                self.principals
+                    .insert(p.clone(), Some(bibifi_util::hash(cp.password.clone())));
+                let delegator = self.default_delegator.clone();
+                for right in RIGHTS.iter() {
+                    self.delegate_all(&delegator, *right, p);
+                }
+                Ok(Status::CREATE_PRINCIPAL)
+            }
+            PrimitiveCommand::ChangePassword(cp) => {
+                let p = &cp.principal.ident.name;
+                ensure(self.principals.contains_key(p), Status::FAILED)?;
+                ensure(self.is_superuser(user) || user == p, Status::DENIED)?;
+                ensure(
+                    p != "anyone" && !self.groups.contains_key(p),
+                    Status::FAILED,
+                )?;
+                self.principals
+                    .insert(p.clone(), Some(bibifi_util::hash(cp.password.clone())));
+                self.sessions.retain(|_, q| q != p);
+                Ok(Status::CHANGE_PASSWORD)
+            }
+            PrimitiveCommand::DeletePrincipal(p) => {
+                let p = &p.ident.name;
+                self.check_removable(user, p)?;
+                self.principals.remove(p);
+                self.disabled.remove(p);
+                self.superusers.remove(p);
+                self.assertions
+                    .retain(|a| &a.delegator != p && &a.delegated != p);
+                self.groups.remove(p);
+                for group in self.groups.values_mut() {
+                    group.members.remove(p);
+                    if &group.owner == p {
+                        group.owner = "admin".to_string();
+                    }
+                }
+                if &self.default_delegator == p {
+                    self.default_delegator = "anyone".to_string();
+                }
+                self.sessions.retain(|_, q| q != p);
+                self.denials.retain(|d| &d.principal != p);
+                Ok(Status::DELETE_PRINCIPAL)
+            }
+            PrimitiveCommand::CreateGroup(cg) => {
+                let g = &cg.group.ident.name;
+                let owner = cg.owner.as_ref().map_or("admin", |p| p.ident.name.as_str());
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                ensure(!self.principals.contains_key(g), Status::FAILED)?;
+                ensure(self.principals.contains_key(owner), Status::FAILED)?;
+                ensure(
+                    owner != "anyone" && !self.groups.contains_key(owner),
+                    Status::FAILED,
+                )?;
+                self.principals.insert(g.clone(), None);
+                self.groups.insert(
+                    g.clone(),
+                    Group {
+                        owner: owner.to_string(),
+                        members: BTreeSet::new(),
+                    },
+                );
+                Ok(Status::CREATE_GROUP)
+            }
+            PrimitiveCommand::AddMember(m) | PrimitiveCommand::RemoveMember(m) => {
+                let (p, g) = (&m.member.ident.name, &m.group.ident.name);
+                ensure(self.principals.contains_key(p), Status::FAILED)?;
+                ensure(p != "admin" && p != "anyone", Status::FAILED)?;
+                ensure(self.principals.contains_key(g), Status::FAILED)?;
+                let superuser = self.is_superuser(user);
+                let group = self.groups.get_mut(g).ok_or(Status::FAILED)?;
+                ensure(superuser || user == group.owner, Status::DENIED)?;
+                if let PrimitiveCommand::AddMember(_) = command {
+                    group.members.insert(p.clone());
+                    Ok(Status::ADD_MEMBER)
+                } else {
+                    group.members.remove(p);
+                    Ok(Status::REMOVE_MEMBER)
+                }
+            }
+            PrimitiveCommand::DisablePrincipal(p) => {
+                self.check_removable(user, &p.ident.name)?;
+                self.disabled.insert(p.ident.name.clone());
+                Ok(Status::DISABLE_PRINCIPAL)
+            }
+            PrimitiveCommand::EnablePrincipal(p) => {
+                self.check_removable(user, &p.ident.name)?;
+                self.disabled.remove(&p.ident.name);
+                Ok(Status::ENABLE_PRINCIPAL)
+            }
+            PrimitiveCommand::Assignment(a) => {
+                let value = self.evaluate(user, locals, &a.expr)?;
+                match &a.variable {
+                    Variable::Variable(x) => self.set(user, locals, &x.name, value)?,
+                    Variable::Member(x, f) => match (f.as_ref(), value) {
+                        (Variable::Variable(f), Value::Immediate(s)) => {
+                            self.set_member(user, locals, &x.name, &f.name, s)?
+                        }
+                        _ => return Err(Status::FAILED),
+                    },
+                }
+                Ok(Status::SET)
+            }
+            PrimitiveCommand::Append(a) => {
+                let value = self.evaluate(user, locals, &a.expr)?;
+                let x = match &a.variable {
+                    Variable::Variable(x) => &x.name,
+                    Variable::Member(_, _) => return Err(Status::FAILED),
+                };
+                let global = !locals.contains_key(x);
+                let list = match locals.get_mut(x) {
+                    Some(list) => list,
+                    None => {
+                        ensure(self.variables.contains_key(x), Status::FAILED)?;
+                        ensure(
+                            self.has_right(user, x, Right::Write)
+                                || self.has_right(user, x, Right::Append),
+                            Status::DENIED,
+                        )?;
+                        self.variables.get_mut(x).unwrap()
+                    }
+                };
+                match (list, value) {
+                    (Value::List(list), Value::List(other)) => list.extend(other),
+                    (Value::List(list), value) => list.push(value),
+                    _ => return Err(Status::FAILED),
+                }
+                if global {
+                    self.record(user, x);
+                }
+                Ok(Status::APPEND)
+            }
+            PrimitiveCommand::LocalAssignment(a) => {
+                let value = self.evaluate(user, locals, &a.expr)?;
+                let x = match &a.variable {
+                    Variable::Variable(x) => &x.name,
+                    Variable::Member(_, _) => return Err(Status::FAILED),
+                };
+                ensure(!self.is_defined(locals, x), Status::FAILED)?;
+                locals.insert(x.clone(), value);
+                Ok(Status::LOCAL)
+            }
+            PrimitiveCommand::ForEach(fe) => {
+                let (y, x) = match (&fe.value, &fe.list) {
+                    (Variable::Variable(y), Variable::Variable(x)) => (&y.name, &x.name),
+                    _ => return Err(Status::FAILED),
+                };
+                let global = !locals.contains_key(x);
+                if global {
+                    ensure(self.variables.contains_key(x), Status::FAILED)?;
+                    ensure(
+                        self.has_right(user, x, Right::Read)
+                            && self.has_right(user, x, Right::Write),
+                        Status::DENIED,
+                    )?;
+                }
+                ensure(!self.is_defined(locals, y), Status::FAILED)?;
+                let list = match locals.get(x).or_else(|| self.variables.get(x)) {
+                    Some(Value::List(list)) => list.clone(),
+                    _ => return Err(Status::FAILED),
+                };
+                let mut replaced = Vec::new();
+                for item in list {
+                    let mut scope = locals.clone();
+                    scope.insert(y.clone(), item);
+                    match self.evaluate(user, &scope, &fe.expr)? {
+                        Value::List(_) => return Err(Status::FAILED),
+                        value => replaced.push(value),
+                    }
+                }
+                if global {
+                    self.variables.insert(x.clone(), Value::List(replaced));
+                    self.record(user, x);
+                } else {
+                    locals.insert(x.clone(), Value::List(replaced));
+                }
+                Ok(Status::FOREACH)
+            }
+            PrimitiveCommand::FilterEach(fe) => {
+                let (y, x) = match (&fe.value, &fe.list) {
+                    (Variable::Variable(y), Variable::Variable(x)) => (&y.name, &x.name),
+                    _ => return Err(Status::FAILED),
+                };
+                let global = !locals.contains_key(x);
+                if global {
+                    ensure(self.variables.contains_key(x), Status::FAILED)?;
+                    ensure(
+                        self.has_right(user, x, Right::Read)
+                            && self.has_right(user, x, Right::Write),
+                        Status::DENIED,
+                    )?;
+                }
+                ensure(!self.is_defined(locals, y), Status::FAILED)?;
+                let list = match locals.get(x).or_else(|| self.variables.get(x)) {
+                    Some(Value::List(list)) => list.clone(),
+                    _ => return Err(Status::FAILED),
+                };
+                let mut kept = Vec::new();
+                for item in list {
+                    let mut scope = locals.clone();
+                    scope.insert(y.clone(), item.clone());
+                    if self.holds(user, &scope, &fe.condition)? {
+                        kept.push(item);
+                    }
+                }
+                if global {
+                    self.variables.insert(x.clone(), Value::List(kept));
+                    self.record(user, x);
+                } else {
+                    locals.insert(x.clone(), Value::List(kept));
+                }
+                Ok(Status::FILTEREACH)
+            }
+            PrimitiveCommand::Conditional(_) => unreachable!("conditionals are handled by run"),
+            PrimitiveCommand::Login(_) => unreachable!("logins are handled by run"),
+            PrimitiveCommand::Watch(_) => unreachable!("watches are handled by run"),
+            PrimitiveCommand::RevokeTokens(p) => {
+                let p = &p.ident.name;
+                ensure(self.is_superuser(user) || user == p, Status::DENIED)?;
+                ensure(self.principals.contains_key(p), Status::FAILED)?;
+                self.sessions.retain(|_, q| q != p);
+                Ok(Status::REVOKE_TOKENS)
+            }
+            PrimitiveCommand::GrantSuperuser(p) => {
+                self.check_removable(user, &p.ident.name)?;
+                ensure(!self.groups.contains_key(&p.ident.name), Status::FAILED)?;
+                self.superusers.insert(p.ident.name.clone());
+                Ok(Status::GRANT_SUPERUSER)
+            }
+            PrimitiveCommand::RevokeSuperuser(p) => {
+                self.check_removable(user, &p.ident.name)?;
+                ensure(!self.groups.contains_key(&p.ident.name), Status::FAILED)?;
+                self.superusers.remove(&p.ident.name);
+                Ok(Status::REVOKE_SUPERUSER)
+            }
+            PrimitiveCommand::SetDelegation(d, _) => {
+                let (q, p, right, target) = self.check_delegation(user, locals, d, false)?;
+                match target {
+                    Some(target) => {
+                        self.assertions.insert(Assertion {
+                            target,
+                            delegator: q,
+                            right,
+                            delegated: p,
+                        });
+                    }
+                    None => self.delegate_all(&q, right, &p),
+                }
+                Ok(Status::SET_DELEGATION)
+            }
+            PrimitiveCommand::DeleteDelegation(d) => {
+                let (q, p, right, target) = self.check_delegation(user, locals, d, true)?;
+                let targets: Vec<String> = match target {
+                    Some(target) => vec![target],
+                    None => self
+                        .variables
+                        .keys()
+                        .filter(|x| self.has_right(&q, x, Right::Delegate))
+                        .cloned()
+                        .collect(),
+                };
+                for target in targets {
+                    self.assertions.remove(&Assertion {
+                        target,
+                        delegator: q.clone(),
+                        right,
+                        delegated: p.clone(),
+                    });
+                }
+                Ok(Status::DELETE_DELEGATION)
+            }
+            PrimitiveCommand::DeleteVariable(x) => {
+                let x = &x.name;
+                if locals.remove(x).is_none() {
+                    ensure(self.variables.contains_key(x), Status::FAILED)?;
+                    ensure(self.has_right(user, x, Right::Write), Status::DENIED)?;
+                    self.variables.remove(x);
+                    self.versions.remove(x);
+                    self.creators.remove(x);
+                    self.assertions.retain(|a| !covers(x, &a.target));
+                    self.denials
+                        .retain(|d| d.target.as_ref().is_none_or(|key| !covers(x, key)));
+                }
+                Ok(Status::DELETE_VARIABLE)
+            }
+            PrimitiveCommand::SetDenial(d) | PrimitiveCommand::DeleteDenial(d) => {
+                let p = &d.denied.ident.name;
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                ensure(self.principals.contains_key(p), Status::FAILED)?;
+                ensure(p != "admin", Status::FAILED)?;
+                let denial = Denial {
+                    target: self.target(locals, &d.target)?,
+                    right: Right::from(&d.right),
+                    principal: p.clone(),
+                };
+                if let PrimitiveCommand::SetDenial(_) = command {
+                    if !self.denials.contains(&denial) {
+                        self.denials.push(denial);
+                    }
+                    Ok(Status::SET_DENIAL)
+                } else {
+                    self.denials.retain(|d| d != &denial);
+                    Ok(Status::DELETE_DENIAL)
+                }
+            }
+            PrimitiveCommand::DefaultDelegator(p) => {
+                ensure(self.principals.contains_key(&p.ident.name), Status::FAILED)?;
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                self.default_delegator = p.ident.name.clone();
+                Ok(Status::DEFAULT_DELEGATOR)
+            }
+            PrimitiveCommand::Restore(n) => {
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                let checkpoint = self.checkpoints.iter().find(|c| c.sequence == *n);
+                let mut restored = checkpoint.ok_or(Status::FAILED)?.clone();
+                restored.sequence = self.sequence;
+                restored.checkpoints = std::mem::take(&mut self.checkpoints);
+                restored.sessions = std::mem::take(&mut self.sessions);
+                let principals = &restored.principals;
+                restored.sessions.retain(|_, p| principals.contains_key(p));
+                *self = restored;
+                Ok(Status::RESTORE)
+            }
+        }
+    }
+
+    /// Whether the principal is admin or has been granted the superuser role.
+    fn is_superuser(&self, principal: &str) -> bool {
+        principal == "admin" || self.superusers.contains(principal)
+    }
+
+    /// Shared checks of `delete principal`, `disable principal`, `enable principal`, and granting
+    /// or revoking the superuser role.
+    fn check_removable(&self, user: &str, p: &str) -> Result<(), Status> {
+        ensure(self.is_superuser(user), Status::DENIED)?;
+        ensure(self.principals.contains_key(p), Status::FAILED)?;
+        ensure(p != "admin" && p != "anyone", Status::FAILED)
+    }
+
+    /// Shared checks of `set delegation` and `delete delegation`, returning `(q, p, right, target)`
+    /// where the target is none for `all`.
+    fn check_delegation(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        d: &Delegation,
+        deleting: bool,
+    ) -> Result<(String, String, Right, Option<String>), Status> {
+        let q = d.delegator.ident.name.clone();
+        let p = d.delegated.ident.name.clone();
+        ensure(
+            self.principals.contains_key(&q) && self.principals.contains_key(&p),
+            Status::FAILED,
+        )?;
+        let target = self.target(locals, &d.target)?;
+        ensure(
+            self.is_superuser(user) || user == q || (deleting && user == p),
+            Status::DENIED,
+        )?;
+        if let Some(target) = &target {
+            if !(self.is_superuser(user) || (deleting && user == p)) {
+                ensure(self.has_right(&q, target, Right::Delegate), Status::DENIED)?;
+            }
+        }
+        Ok((q, p, Right::from(&d.right), target))
+    }
+
+    /// The global variable, field or pattern targeted, or none for `all`, failing if the variable
+    /// or field does not exist.
+    fn target(
+        &self,
+        locals: &HashMap<String, Value>,
+        target: &ParserTarget,
+    ) -> Result<Option<String>, Status> {
+        match target {
+            ParserTarget::All => Ok(None),
+            ParserTarget::Pattern(prefix) => Ok(Some(format!("{}*", prefix))),
+            ParserTarget::Variable(x) => {
+                ensure(
+                    !locals.contains_key(&x.name) && self.variables.contains_key(&x.name),
+                    Status::FAILED,
+                )?;
+                Ok(Some(x.name.clone()))
+            }
+            ParserTarget::Field(x, f) => {
+                ensure(!locals.contains_key(&x.name), Status::FAILED)?;
+                match self.variables.get(&x.name) {
+                    Some(Value::FieldVals(record)) if record.contains_key(&f.name) => {}
+                    _ => return Err(Status::FAILED),
+                }
+                Ok(Some(format!("{}.{}", x.name, f.name)))
+            }
+        }
+    }
+
+    /// `set delegation all q right -> p`: one assertion per variable q can currently delegate.
+    fn delegate_all(&mut self, q: &str, right: Right, p: &str) {
+        let targets: Vec<String> = self
+            .variables
+            .keys()
+            .filter(|x| self.has_right(q, x, Right::Delegate))
+            .cloned()
+            .collect();
+        for target in targets {
+            self.assertions.insert(Assertion {
+                target,
+                delegator: q.to_string(),
+                right,
+                delegated: p.to_string(),
+            });
+        }
+    }
+
+    fn set(
+        &mut self,
+        user: &str,
+        locals: &mut HashMap<String, Value>,
+        x: &str,
+        value: Value,
+    ) -> Result<(), Status> {
+        if let Some(local) = locals.get_mut(x) {
+            *local = value;
+        } else if self.variables.contains_key(x) {
+            ensure(self.has_right(user, x, Right::Write), Status::DENIED)?;
+            self.variables.insert(x.to_string(), value);
+            self.record(user, x);
+        } else {
+            self.variables.insert(x.to_string(), value);
+            self.record(user, x);
+            if user != "admin" {
+                for right in RIGHTS.iter() {
+                    self.assertions.insert(Assertion {
+                        target: x.to_string(),
+                        delegator: "admin".to_string(),
+                        right: *right,
+                        delegated: user.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_member(
+        &mut self,
+        user: &str,
+        locals: &mut HashMap<String, Value>,
+        x: &str,
+        f: &str,
+        s: String,
+    ) -> Result<(), Status> {
+        match locals.get(x).or_else(|| self.variables.get(x)) {
+            Some(Value::FieldVals(record)) if record.contains_key(f) => {}
+            _ => return Err(Status::FAILED),
+        }
+        let global = !locals.contains_key(x);
+        let record = match locals.get_mut(x) {
+            Some(record) => record,
+            None => {
+                let field = format!("{}.{}", x, f);
+                ensure(self.has_right(user, &field, Right::Write), Status::DENIED)?;
+                self.variables.get_mut(x).unwrap()
+            }
+        };
+        if let Value::FieldVals(record) = record {
+            record.insert(f.to_string(), s);
+        }
+        if global {
+            self.record(user, x);
+        }
+        Ok(())
+    }
+
+    /// Keeps the current value of the global variable as a version written by `user`, replacing
+    /// an earlier version from the same program.
+    fn record(&mut self, user: &str, x: &str) {
+        self.creators
+            .entry(x.to_string())
+            .or_insert_with(|| user.to_string());
+        let (current, value) = (self.sequence, self.variables[x].clone());
+        let versions = self.versions.entry(x.to_string()).or_default();
+        versions.retain(|(sequence, _, _)| *sequence != current);
+        versions.push((current, user.to_string(), value));
+        if versions.len() > HISTORY {
+            versions.remove(0);
+        }
+    }
+
+    fn is_defined(&self, locals: &HashMap<String, Value>, x: &str) -> bool {
+        locals.contains_key(x) || self.variables.contains_key(x)
+    }
+
+    fn evaluate(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        expr: &Expr,
+    ) -> Result<Value, Status> {
+        match expr {
+            Expr::Value(v) => self.value(user, locals, v),
+            Expr::EmptyList => Ok(Value::List(Vec::new())),
+            Expr::Function(f) => self.function(user, locals, f),
+            Expr::Let(l) => {
+                ensure(!self.is_defined(locals, &l.variable.name), Status::FAILED)?;
+                let value = self.evaluate(user, locals, &l.value)?;
+                let mut scoped = locals.clone();
+                scoped.insert(l.variable.name.clone(), value);
+                self.evaluate(user, &scoped, &l.body)
+            }
+            Expr::FieldVals(fields) => {
+                let mut record = HashMap::new();
+                for a in fields {
+                    let f = match &a.variable {
+                        Variable::Variable(f) => f.name.clone(),
+                        Variable::Member(_, _) => return Err(Status::FAILED),
+                    };
+                    ensure(!record.contains_key(&f), Status::FAILED)?;
+                    match self.evaluate(user, locals, &a.expr)? {
+                        Value::Immediate(s) => record.insert(f, s),
+                        _ => return Err(Status::FAILED),
+                    };
+                }
+                Ok(Value::FieldVals(record))
+            }
+        }
+    }
+
+    fn function(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        function: &Function,
+    ) -> Result<Value, Status> {
+        match function {
+            Function::Concat(a, b) => {
+                let joined = self.string(user, locals, a)? + &self.string(user, locals, b)?;
+                Ok(Value::Immediate(joined.chars().take(65535).collect()))
+            }
+            Function::ToLower(a) => Ok(Value::Immediate(
+                self.string(user, locals, a)?.to_ascii_lowercase(),
+            )),
+            Function::Equal(a, b) | Function::NotEqual(a, b) => {
+                let a = self.evaluate(user, locals, a)?;
+                let b = self.evaluate(user, locals, b)?;
+                let equal = match (&a, &b) {
+                    (Value::Immediate(_), Value::Immediate(_)) => a == b,
+                    (Value::FieldVals(_), Value::FieldVals(_)) => a == b,
+                    _ => return Err(Status::FAILED),
+                };
+                let wanted = matches!(function, Function::Equal(_, _));
+                Ok(Value::Immediate(
+                    if equal == wanted { "" } else { "0" }.to_string(),
+                ))
+            }
+            Function::Split(a, b) => {
+                let a = self.string(user, locals, a)?;
+                let b = self.string(user, locals, b)?;
+                let mut record = HashMap::new();
+                match a.find(&b) {
+                    Some(at) if !b.is_empty() => {
+                        record.insert("fst".to_string(), a[..at].to_string());
+                        record.insert("snd".to_string(), a[at + b.len()..].to_string());
+                    }
+                    _ => {
+                        record.insert("fst".to_string(), a);
+                        record.insert("snd".to_string(), String::new());
+                    }
+                }
+                Ok(Value::FieldVals(record))
+            }
+            Function::Denials(t) => {
+                ensure(self.is_superuser(user), Status::DENIED)?;
+                let target = self.target(locals, t)?;
+                let listed = self
+                    .denials
+                    .iter()
+                    .filter(|d| target.as_ref().is_none_or(|key| d.applies(key)))
+                    .map(|d| {
+                        let mut record = HashMap::new();
+                        let target = d.target.clone().unwrap_or_else(|| "all".to_string());
+                        record.insert("target".to_string(), target);
+                        let right = format!("{:?}", d.right).to_lowercase();
+                        record.insert("right".to_string(), right);
+                        record.insert("principal".to_string(), d.principal.clone());
+                        Value::FieldVals(record)
+                    })
+                    .collect();
+                Ok(Value::List(listed))
+            }
+            Function::History(x) => {
+                ensure(self.variables.contains_key(&x.name), Status::FAILED)?;
+                ensure(self.has_right(user, &x.name, Right::Read), Status::DENIED)?;
+                let listed = self.versions[&x.name]
+                    .iter()
+                    .map(|(sequence, principal, _)| {
+                        let mut record = HashMap::new();
+                        record.insert("sequence".to_string(), sequence.to_string());
+                        record.insert("principal".to_string(), principal.clone());
+                        Value::FieldVals(record)
+                    })
+                    .collect();
+                Ok(Value::List(listed))
+            }
+            Function::Metadata(x) => {
+                ensure(self.variables.contains_key(&x.name), Status::FAILED)?;
+                ensure(self.has_right(user, &x.name, Right::Read), Status::DENIED)?;
+                let (_, writer, value) = self.versions[&x.name].last().unwrap();
+                let mut record = HashMap::new();
+                record.insert("creator".to_string(), self.creators[&x.name].clone());
+                record.insert("writer".to_string(), writer.clone());
+                record.insert("created".to_string(), "0".to_string());
+                record.insert("modified".to_string(), "0".to_string());
+                record.insert("size".to_string(), value.size().to_string());
+                Ok(Value::FieldVals(record))
+            }
+        }
+    }
+
+    fn string(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        expr: &Expr,
+    ) -> Result<String, Status> {
+        match self.evaluate(user, locals, expr)? {
+            Value::Immediate(s) => Ok(s),
+            _ => Err(Status::FAILED),
+        }
+    }
+
+    fn value(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        value: &ParserValue,
+    ) -> Result<Value, Status> {
+        match value {
+            ParserValue::String(s) => Ok(Value::Immediate(s.clone())),
+            ParserValue::Version(x, n) => {
+                ensure(self.variables.contains_key(&x.name), Status::FAILED)?;
+                ensure(self.has_right(user, &x.name, Right::Read), Status::DENIED)?;
+                self.versions[&x.name]
+                    .iter()
+                    .rev()
+                    .find(|(sequence, _, _)| sequence <= n)
+                    .map(|(_, _, value)| value.clone())
+                    .ok_or(Status::FAILED)
+            }
+            ParserValue::Variable(Variable::Variable(x)) => self.lookup(user, locals, &x.name),
+            ParserValue::Variable(Variable::Member(x, f)) => {
+                let f = match f.as_ref() {
+                    Variable::Variable(f) => &f.name,
+                    Variable::Member(_, _) => return Err(Status::FAILED),
+                };
+                if !locals.contains_key(&x.name) {
+                    ensure(self.variables.contains_key(&x.name), Status::FAILED)?;
+                    let field = format!("{}.{}", x.name, f);
+                    ensure(self.has_right(user, &field, Right::Read), Status::DENIED)?;
+                }
+                match self.variable(locals, &x.name) {
+                    Value::FieldVals(record) => record
+                        .get(f)
+                        .map(|s| Value::Immediate(s.clone()))
+                        .ok_or(Status::FAILED),
+                    _ => Err(Status::FAILED),
+                }
+            }
+        }
+    }
+
+    fn lookup(
+        &self,
+        user: &str,
+        locals: &HashMap<String, Value>,
+        x: &str,
+    ) -> Result<Value, Status> {
+        if let Some(local) = locals.get(x) {
+            return Ok(local.clone());
+        }
+        let value = self.variables.get(x).ok_or(Status::FAILED)?;
+        ensure(self.has_right(user, x, Right::Read), Status::DENIED)?;
+        Ok(value.clone())
+    }
+
+    /// The value of a local or global variable which is known to exist, without any right check.
+    fn variable(&self, locals: &HashMap<String, Value>, x: &str) -> Value {
+        locals
+            .get(x)
+            .or_else(|| self.variables.get(x))
+            .cloned()
+            .unwrap()
+    }
+}
+
+fn ensure(condition: bool, status: Status) -> Result<(), Status> {
+    if condition {
+        Ok(())
+    } else {
+        Err(status)
+    }
+}
+
+fn entry(status: Status) -> Entry {
+    Entry {
+        status,
+        output: None,
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,730 @@
+use super::diff::check;
+use super::*;
+use bibifi_runtime::status::Status::*;
+use bibifi_util::hash;
+
+fn returning(value: Value) -> Entry {
+    Entry {
+        status: RETURNING,
+        output: Some(value),
+    }
+}
+
+// the example programs from the specification, run in sequence
+#[test]
+fn spec_example() {
+    let mut reference = Reference::new(hash("admin".to_string()));
+    let out = reference.run_program(
+        r#"as principal admin password "admin" do
+   create principal bob "B0BPWxxd"
+   set x = "my string"
+   set y = { f1 = x, f2 = "field2" }
+   set delegation x admin read -> bob
+   return y.f1
+***"#
+            .to_string(),
+    );
+    assert_eq!(
+        vec![
+            entry(CREATE_PRINCIPAL),
+            entry(SET),
+            entry(SET),
+            entry(SET_DELEGATION),
+            returning(Value::Immediate("my string".to_string())),
+        ],
+        out
+    );
+
+    let out = reference.run_program(
+        r#"as principal bob password "B0BPWxxd" do
+   return x
+***"#
+            .to_string(),
+    );
+    assert_eq!(
+        vec![returning(Value::Immediate("my string".to_string()))],
+        out
+    );
+
+    let before = reference.clone();
+    let out = reference.run_program(
+        r#"as principal bob password "B0BPWxxd" do
+   set z = "bobs string"
+   set x = "another string"
+   return x
+***"#
+            .to_string(),
+    );
+    assert_eq!(vec![entry(DENIED)], out);
+    assert_eq!(before, reference);
+}
+
+// a delegation to anyone reaches every principal, including ones created later
+#[test]
+fn delegation_through_anyone() {
+    let mut reference = Reference::new(hash("admin".to_string()));
+    let out = reference.run_program(
+        r#"as principal admin password "admin" do
+   set x = "secret"
+   set delegation x admin read -> anyone
+   create principal bob "bob"
+   return "ok"
+***"#
+            .to_string(),
+    );
+    assert_eq!(RETURNING, out.last().unwrap().status);
+    assert!(reference.has_right("bob", "x", Right::Read));
+    assert!(!reference.has_right("bob", "x", Right::Write));
+
+    // bob has no delegate right, so he cannot pass read on
+    let out = reference.run_program(
+        r#"as principal bob password "bob" do
+   set delegation x bob read -> admin
+   return "ok"
+***"#
+            .to_string(),
+    );
+    assert_eq!(vec![entry(DENIED)], out);
+}
+
+// removing one link of a delegation chain revokes the right for everyone after it
+#[test]
+fn chain_revocation() {
+    let mut reference = Reference::new(hash("admin".to_string()));
+    let out = reference.run_program(
+        r#"as principal admin password "admin" do
+   create principal alice "alice"
+   create principal bob "bob"
+   set x = "value"
+   set delegation x admin delegate -> alice
+   set delegation x admin read -> alice
+   set delegation x alice read -> bob
+   return "ok"
+***"#
+            .to_string(),
+    );
+    assert_eq!(RETURNING, out.last().unwrap().status);
+    assert!(reference.has_right("bob", "x", Right::Read));
+    assert_eq!(3, reference.assertions().count());
+
+    let out = reference.run_program(
+        r#"as principal alice password "alice" do
+   delete delegation x admin read -> alice
+   return "ok"
+***"#
+            .to_string(),
+    );
+    assert_eq!(
+        vec![
+            entry(DELETE_DELEGATION),
+            returning(Value::Immediate("ok".to_string()))
+        ],
+        out
+    );
+    assert!(!reference.has_right("alice", "x", Right::Read));
+    assert!(!reference.has_right("bob", "x", Right::Read));
+}
+
+// a session token stands in for the password until the password changes
+#[test]
+fn sessions() {
+    let mut reference = Reference::new(hash("admin".to_string()));
+    let out = reference.run_program(
+        r#"as principal admin password "admin" do
+   login
+   return "ok"
+***"#
+            .to_string(),
+    );
+    let token = match &out[0] {
+        Entry {
+            status: LOGIN,
+            output: Some(Value::Immediate(token)),
+        } => token.clone(),
+        other => panic!("{:?}", other),
+    };
+    let with_token = |command: &str| {
+        format!(
+            "as principal admin token \"{}\" do\n{}\nreturn \"ok\"\n***",
+            token, command
+        )
+    };
+    let out = reference.run_program(with_token("create principal bob \"bob\""));
+    assert_eq!(CREATE_PRINCIPAL, out[0].status);
+    let out = reference.run_program(with_token("change password admin \"new\""));
+    assert_eq!(CHANGE_PASSWORD, out[0].status);
+    let out = reference.run_program(with_token("create principal carol \"carol\""));
+    assert_eq!(vec![entry(DENIED)], out);
+}
+
+// The differential tests below each start from a fresh server, and cover one feature. They can
+// only cover what the model does, so these are left to the runtime's own tests:
+//  - limits on delegations, as the model has no clock and does not count uses;
+//  - the expiry of session tokens, for the same reason;
+//  - field projection, and password and history policies other than the defaults.
+// The model also shares the parser and the value and status types with the runtime, so a mistake
+// in those is not caught here.
+
+/// Checks that the runtime and the model agree on every one of `programs`, run in order.
+fn agree(programs: &[&str]) {
+    let programs = programs.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    if let Some(divergence) = check(hash("admin".to_string()), &programs) {
+        panic!("{}", divergence);
+    }
+}
+
+#[test]
+fn differential_commands() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   set records = []
+   append to records with { name = "mike", date = "1-1-90" }
+   append to records with { name = "dave", date = "1-1-85" }
+   local names = records
+   foreach rec in names replacewith rec.name
+   filtereach rec in records with rec.name != "dave"
+   set delegation records admin read -> bob
+   return names
+***"#,
+        r#"as principal bob password "bob" do
+   return records
+***"#,
+        r#"as principal bob password "wrong" do
+   return records
+***"#,
+        r#"as principal bob password "bob" do
+   set records = []
+   return records
+***"#,
+        r#"as principal admin password "admin" do
+   delete delegation records admin read -> bob
+   return "revoked"
+***"#,
+        r#"as principal bob password "bob" do
+   return records
+***"#,
+        r#"as principal bob password "bob" do
+   if defined records then
+      local mine = "no access"
+   else
+      local mine = "none"
+   end
+   if mine == "none" then
+      set mine = records
+   end
+   return mine
+***"#,
+        r#"as principal admin password "admin" do
+   local parts = split(concat(tolower("Mike-Dave"), "-x"), "-")
+   if equal(parts.fst, "mike") == "" then
+      set parts.snd = notequal(parts, parts)
+   end
+   return let p = parts in p
+***"#,
+        r#"as principal admin password "admin" do
+   set scratch = "temporary"
+   set delegation scratch admin read -> bob
+   delete scratch
+   local scratch = "local"
+   delete scratch
+   set scratch = "recreated"
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+        r#"as principal admin password "admin" do
+   return let records = "shadowed" in records
+***"#,
+        r#"as principal dave password "dave" do
+   exit
+***"#,
+        r#"as principal admin password "admin" do
+   exit
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_principals() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   set scratch = "value"
+   create principal carol "carol"
+   set delegation scratch admin read -> carol
+   set delegation scratch carol read -> bob
+   default delegator = carol
+   disable principal carol
+   return "ok"
+***"#,
+        r#"as principal carol password "carol" do
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+        r#"as principal admin password "admin" do
+   delete principal carol
+   create principal dave "dave"
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   delete principal dave
+   return "ok"
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_groups() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   set scratch = "value"
+   create group team owner dave
+   create group staff
+   set delegation scratch admin read -> staff
+   set delegation scratch admin delegate -> staff
+   add principal team to group staff
+   return "ok"
+***"#,
+        r#"as principal dave password "dave" do
+   add principal bob to group team
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   set delegation scratch bob read -> dave
+   add principal dave to group staff
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   set delegation scratch bob read -> dave
+   return scratch
+***"#,
+        r#"as principal dave password "dave" do
+   remove principal bob from group team
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+        r#"as principal team password "dave" do
+   return scratch
+***"#,
+        r#"as principal dave password "dave" do
+   set delegation scratch bob read -> team
+   return "ok"
+***"#,
+        r#"as principal dave password "dave" do
+   delete delegation scratch admin read -> staff
+   return "ok"
+***"#,
+        r#"as principal dave password "dave" do
+   set delegation scratch nobody read -> team
+   return "ok"
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_fields() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   create group team owner dave
+   set card = { owner = "dave", number = "1234" }
+   set delegation card.owner admin read -> bob
+   set delegation card.number admin read -> team
+   set delegation card admin delegate -> dave
+   set delegation card.expiry admin read -> bob
+   return card.owner
+***"#,
+        r#"as principal bob password "bob" do
+   if defined card.owner then
+      local seen = card.owner
+   end
+   return seen
+***"#,
+        r#"as principal bob password "bob" do
+   return card.number
+***"#,
+        r#"as principal bob password "bob" do
+   return card
+***"#,
+        r#"as principal dave password "dave" do
+   set delegation card.number dave read -> bob
+   return card.number
+***"#,
+        r#"as principal admin password "admin" do
+   set delegation card read -> dave
+   set delegation card.owner admin write -> dave
+   return card.owner
+***"#,
+        r#"as principal dave password "dave" do
+   set card.owner = "bob"
+   set card.number = "0000"
+   return card
+***"#,
+        r#"as principal admin password "admin" do
+   set card.owner = "bob"
+   delete card
+   set card = { owner = "carol" }
+   return card
+***"#,
+        r#"as principal bob password "bob" do
+   return card.owner
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_sessions() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   login
+   login for 60 seconds
+   revoke tokens dave
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   login
+   revoke tokens bob
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   login for 3601 seconds
+   return "ok"
+***"#,
+        r#"as principal bob token "0123abcd" do
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   revoke tokens nobody
+   return "ok"
+***"#,
+        r#"as principal nobody password "bob" do
+   return "nobody"
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_superusers() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   create group team
+   set scratch = "value"
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   grant superuser bob
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   grant superuser dave
+   return "ok"
+***"#,
+        r#"as principal dave password "dave" do
+   create principal erin "erin"
+   set delegation scratch dave read -> erin
+   grant superuser anyone
+   return scratch
+***"#,
+        r#"as principal dave password "dave" do
+   create principal erin "erin"
+   set delegation scratch dave read -> erin
+   grant superuser team
+   return scratch
+***"#,
+        r#"as principal dave password "dave" do
+   create principal erin "erin"
+   set delegation scratch dave read -> erin
+   default delegator = erin
+   return scratch
+***"#,
+        r#"as principal erin password "erin" do
+   return scratch
+***"#,
+        r#"as principal erin password "erin" do
+   revoke superuser dave
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   revoke superuser dave
+   return "ok"
+***"#,
+        r#"as principal erin password "erin" do
+   return scratch
+***"#,
+        r#"as principal dave password "dave" do
+   exit
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_denials() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal erin "erin"
+   create group team
+   set scratch = "value"
+   set delegation scratch admin read -> bob
+   set delegation scratch admin write -> bob
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   set ledger = { holder = "dave" }
+   set denial scratch read -> bob
+   set denial all write -> anyone
+   set denial ledger.holder read -> team
+   return denials(scratch)
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+        r#"as principal bob password "bob" do
+   set scratch = "overwritten"
+   return "ok"
+***"#,
+        r#"as principal erin password "erin" do
+   set denial scratch read -> bob
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   set denial nothing read -> bob
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   set denial ledger read -> admin
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   local listed = denials(ledger)
+   delete denial scratch read -> bob
+   delete denial all write -> anyone
+   return listed
+***"#,
+        r#"as principal admin password "admin" do
+   return denials(all)
+***"#,
+        r#"as principal bob password "bob" do
+   return scratch
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_patterns() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   create principal erin "erin"
+   set delegation reports_* admin read -> bob
+   set delegation * admin delegate -> dave
+   set delegation * admin read -> dave
+   set reports_q1 = "first"
+   return reports_q1
+***"#,
+        r#"as principal bob password "bob" do
+   local q = reports_q1
+   set delegation reports_q* bob read -> erin
+   return q
+***"#,
+        r#"as principal dave password "dave" do
+   set delegation reports_q* dave read -> erin
+   set delegation reports_x* dave read -> erin
+   return "ok"
+***"#,
+        r#"as principal admin password "admin" do
+   set reports_q2 = "second"
+   delete reports_q1
+   set reports_q1 = "again"
+   return "ok"
+***"#,
+        r#"as principal erin password "erin" do
+   return reports_q2
+***"#,
+        r#"as principal bob password "bob" do
+   return reports_q1
+***"#,
+        r#"as principal admin password "admin" do
+   delete delegation all admin read -> bob
+   set denial reports_q2 read -> bob
+   return denials(reports_*)
+***"#,
+        r#"as principal bob password "bob" do
+   return reports_q1
+***"#,
+        r#"as principal bob password "bob" do
+   return reports_q2
+***"#,
+        r#"as principal admin password "admin" do
+   delete delegation reports_* admin read -> bob
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   return reports_q1
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_history() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   set versioned = []
+   append to versioned with "a"
+   append to versioned with "b"
+   set tally = "one"
+   return history(versioned)
+***"#,
+        r#"as principal admin password "admin" do
+   set tally = "two"
+   foreach item in versioned replacewith concat(item, "!")
+   set delegation tally admin read -> bob
+   return history(tally)
+***"#,
+        r#"as principal bob password "bob" do
+   set tally = "lost"
+   return "ok"
+***"#,
+        r#"as principal bob password "bob" do
+   return history(tally)
+***"#,
+        r#"as principal bob password "bob" do
+   return versioned@1000
+***"#,
+        r#"as principal admin password "admin" do
+   local latest = versioned@1000
+   filtereach item in versioned with item == "a!"
+   return latest
+***"#,
+        r#"as principal admin password "admin" do
+   return tally@1
+***"#,
+        r#"as principal admin password "admin" do
+   delete tally
+   set tally = "three"
+   return history(tally)
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_restore() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   set checkpointed = "before"
+   create principal frank "frank"
+   return history(checkpointed)
+***"#,
+        r#"as principal frank password "frank" do
+   login
+   return ""
+***"#,
+        r#"as principal admin password "admin" do
+   set checkpointed = "after"
+   delete principal frank
+   return ""
+***"#,
+        r#"as principal bob password "bob" do
+   restore to 1
+   return ""
+***"#,
+        r#"as principal admin password "admin" do
+   restore to 1
+   return checkpointed
+***"#,
+        r#"as principal frank password "frank" do
+   return "back"
+***"#,
+        r#"as principal frank password "frank" do
+   return history(checkpointed)
+***"#,
+        r#"as principal admin password "admin" do
+   restore to 4
+   return checkpointed
+***"#,
+        r#"as principal admin password "admin" do
+   restore to 100
+   return ""
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_metadata() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   set described = []
+   set delegation described admin append -> bob
+   set delegation described admin read -> dave
+   return metadata(described)
+***"#,
+        r#"as principal bob password "bob" do
+   append to described with "some text"
+   return "ok"
+***"#,
+        r#"as principal dave password "dave" do
+   return metadata(described)
+***"#,
+        r#"as principal bob password "bob" do
+   return metadata(described)
+***"#,
+        r#"as principal admin password "admin" do
+   return metadata(nothing)
+***"#,
+    ]);
+}
+
+#[test]
+fn differential_watch() {
+    agree(&[
+        r#"as principal admin password "admin" do
+   create principal bob "bob"
+   create principal dave "dave"
+   set described = []
+   set delegation described admin read -> dave
+   return ""
+***"#,
+        r#"as principal dave password "dave" do
+   watch described
+   return "watching"
+***"#,
+        r#"as principal bob password "bob" do
+   watch described
+   return "watching"
+***"#,
+        r#"as principal admin password "admin" do
+   local l = "x"
+   watch l
+   return ""
+***"#,
+        r#"as principal admin password "admin" do
+   watch described
+   delete described
+   return ""
+***"#,
+    ]);
+}
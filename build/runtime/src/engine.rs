@@ -0,0 +1,137 @@
+use crate::status::Entry;
+use crate::{BiBiFi, Error, Subscription, CHECKPOINTS};
+use bibifi_database::{Clock, Database, FieldPolicy, HistoryPolicy, PasswordPolicy};
+use std::collections::VecDeque;
+
+/// A synchronous handle on a database, for embedding the runtime without channels or an async
+/// executor. Each call to [execute](#method.execute) runs one program transactionally: either every
+/// command takes effect, or the database is left exactly as it was.
+///
+/// ```
+/// use bibifi_runtime::status::Status;
+/// use bibifi_runtime::Engine;
+///
+/// let mut engine = Engine::new(bibifi_util::hash("admin".to_string()));
+/// let entries = engine
+///     .execute("as principal admin password \"admin\" do\nset x = \"hi\"\nreturn x\n***")
+///     .unwrap();
+/// assert_eq!(Status::RETURNING, entries[1].status);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Engine {
+    database: Database,
+    /// The state after each of the most recent commits, oldest first, for `restore`.
+    checkpoints: VecDeque<Database>,
+    /// The most checkpoints kept.
+    checkpoint_limit: usize,
+    /// The users whose local processes alone may run programs as a superuser, if restricted.
+    admin_uids: Option<Vec<u32>>,
+}
+
+impl Engine {
+    /// Creates an engine over a fresh database, with admin's password hashed as `admin_hash`.
+    pub fn new(admin_hash: [u8; 32]) -> Engine {
+        Engine::from(Database::new(admin_hash))
+    }
+
+    /// Creates an engine over a fresh database, whose time-limited delegations expire according to
+    /// `clock`.
+    pub fn with_clock(admin_hash: [u8; 32], clock: Clock) -> Engine {
+        Engine::from(Database::with_clock(admin_hash, clock))
+    }
+
+    /// Runs a single program. If it commits, the output entries are returned and the new state is
+    /// kept as a checkpoint for `restore`; if it fails or issues a security violation, the
+    /// database is unchanged and the reason is returned. The error converts into the single
+    /// FAILED or DENIED entry a client would see.
+    pub fn execute(&mut self, source: &str) -> Result<Vec<Entry>, Error> {
+        self.execute_watching(source).map(|(messages, _)| messages)
+    }
+
+    /// Like [execute](#method.execute), but also returns the variables the program asked to
+    /// `watch`, if it committed and asked for any. The caller sends the subscriber the
+    /// subscription's [events](struct.Subscription.html#method.events) after each later commit.
+    pub fn execute_watching(
+        &mut self,
+        source: &str,
+    ) -> Result<(Vec<Entry>, Option<Subscription>), Error> {
+        self.execute_from(source, None)
+    }
+
+    /// Like [execute_watching](#method.execute_watching), for a program sent by a local process
+    /// run by the user `uid`, if known.
+    pub fn execute_from(
+        &mut self,
+        source: &str,
+        uid: Option<u32>,
+    ) -> Result<(Vec<Entry>, Option<Subscription>), Error> {
+        let (messages, database, subscription) = BiBiFi::execute(
+            self.database.clone(),
+            &self.checkpoints,
+            source.to_string(),
+            uid,
+            self.admin_uids.as_deref(),
+        )?;
+        self.database = database;
+        if self.checkpoint_limit > 0 {
+            if self.checkpoints.len() == self.checkpoint_limit {
+                self.checkpoints.pop_front();
+            }
+            self.checkpoints.push_back(self.database.clone());
+        }
+        Ok((messages, subscription))
+    }
+
+    /// Sets whether reading a whole record with read permission on only some of its fields is
+    /// denied or yields just those fields.
+    pub fn set_field_policy(&mut self, policy: FieldPolicy) {
+        self.database.set_field_policy(policy);
+    }
+
+    /// Sets the rules which passwords given to `create principal` and `change password` must
+    /// satisfy.
+    pub fn set_password_policy(&mut self, policy: PasswordPolicy) {
+        self.database.set_password_policy(policy);
+    }
+
+    /// Sets how many earlier values of each variable are kept for `x@n` reads and `history(x)`.
+    pub fn set_history_policy(&mut self, policy: HistoryPolicy) {
+        self.database.set_history_policy(policy);
+    }
+
+    /// Sets how many of the most recent commits `restore` can go back to.
+    pub fn set_checkpoint_limit(&mut self, limit: usize) {
+        self.checkpoint_limit = limit;
+        while self.checkpoints.len() > limit {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// Denies programs running as a superuser unless they were sent by a local process run by one
+    /// of `uids`, on top of the password or token they give.
+    pub fn set_admin_uids(&mut self, uids: Vec<u32>) {
+        self.admin_uids = Some(uids);
+    }
+
+    /// Removes expired and used up delegations from the database. This does not change the outcome
+    /// of any program, so it may be done whenever convenient.
+    pub fn sweep(&mut self) {
+        self.database.sweep();
+    }
+
+    /// The current, committed state of the database.
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+}
+
+impl From<Database> for Engine {
+    fn from(database: Database) -> Self {
+        Engine {
+            database,
+            checkpoints: VecDeque::new(),
+            checkpoint_limit: CHECKPOINTS,
+            admin_uids: None,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::status::{Entry, Status};
+use bibifi_database::{Error as DBError, Status as DBStatus};
+use std::fmt;
+
+/// The reason a program was rolled back. Clients only ever see the status code from
+/// [status](#method.status); the rest is for internal logging and diagnostics.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The program could not be parsed.
+    Parse(String),
+    /// A database operation did not succeed.
+    Database(DBError),
+    /// Only admin and superusers may use the `exit` and `restore` commands.
+    NotAdmin(String),
+    /// Superusers must send their programs from a local process run by one of the users the
+    /// engine allows.
+    NotLocal(String),
+    /// No checkpoint is kept for the program with this commit sequence number.
+    UnknownCheckpoint(u64),
+    /// The variable is already defined as a local or global, so it cannot be introduced as a
+    /// local (by `local` or `foreach`).
+    DuplicateLocal(String),
+    /// The field appears more than once in a record expression.
+    DuplicateField(String),
+    /// The record has no field by this name.
+    UnknownField(String),
+    /// An expression or variable did not evaluate to a value of the required type.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The program uses a construct the grammar accepts but the runtime does not implement, such
+    /// as nested members.
+    Unsupported,
+    /// A `login` asked for a session of more seconds than sessions may last.
+    SessionTooLong(u64),
+    /// The principal authenticated with a token, and may not `login` again to renew it.
+    TokenLogin(String),
+}
+
+impl Error {
+    /// The wire-level status code this error is reported as.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Database(e) => match e.status() {
+                DBStatus::DENIED => Status::DENIED,
+                _ => Status::FAILED,
+            },
+            Error::NotAdmin(_) | Error::NotLocal(_) | Error::TokenLogin(_) => Status::DENIED,
+            Error::Parse(_)
+            | Error::UnknownCheckpoint(_)
+            | Error::DuplicateLocal(_)
+            | Error::DuplicateField(_)
+            | Error::UnknownField(_)
+            | Error::TypeMismatch { .. }
+            | Error::Unsupported
+            | Error::SessionTooLong(_) => Status::FAILED,
+        }
+    }
+}
+
+impl From<DBError> for Error {
+    fn from(e: DBError) -> Self {
+        Error::Database(e)
+    }
+}
+
+impl From<Error> for Entry {
+    fn from(e: Error) -> Self {
+        Entry {
+            status: e.status(),
+            output: None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "parse error: {}", e),
+            Error::Database(e) => e.fmt(f),
+            Error::NotAdmin(p) => write!(f, "principal {} is not admin", p),
+            Error::NotLocal(p) => write!(f, "principal {} did not connect as a local admin", p),
+            Error::UnknownCheckpoint(n) => write!(f, "no checkpoint of commit {} is kept", n),
+            Error::DuplicateLocal(x) => write!(f, "variable {} is already defined", x),
+            Error::DuplicateField(field) => write!(f, "field {} is given more than once", field),
+            Error::UnknownField(field) => write!(f, "record has no field {}", field),
+            Error::TypeMismatch { expected, found } => {
+                write!(f, "found a {}, expected a {}", found, expected)
+            }
+            Error::Unsupported => write!(f, "unsupported construct"),
+            Error::SessionTooLong(seconds) => {
+                write!(f, "a session of {} seconds is too long", seconds)
+            }
+            Error::TokenLogin(p) => write!(f, "principal {} logged in with a token", p),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
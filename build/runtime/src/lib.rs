@@ -1,25 +1,54 @@
-use crate::status::Status::FAILED;
 use crate::status::{Entry, Status};
-use bibifi_database::{Database, Right, Status as DBStatus, Target, Value};
+use bibifi_database::{Database, Error as DBError, Limit as DBLimit, Right, Target, Value};
 use bibifi_parser::parse;
 use bibifi_parser::types::*;
 use bibifi_parser::types::{Right as ParserRight, Target as ParserTarget, Value as ParserValue};
-use std::collections::HashMap;
+use bibifi_util::log;
+use bibifi_util::{debug, info};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+mod engine;
+mod error;
 pub mod status;
+mod watch;
+
+pub use bibifi_database::{FieldPolicy, HistoryPolicy, PasswordPolicy};
+pub use engine::Engine;
+pub use error::Error;
+pub use watch::{Event, Subscription};
+
+/// How often [run](struct.BiBiFi.html#method.run) sweeps expired sessions and delegations out of
+/// the database.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long, in seconds, the token from a `login` without a `for` clause is accepted, and the
+/// longest a `for` clause may ask for.
+const SESSION_LIFETIME: u64 = 3600;
+
+/// How many of the most recent commits `restore` can go back to, unless the engine is configured
+/// otherwise.
+const CHECKPOINTS: usize = 16;
+
+/// A program submitted to the runtime, along with the channel its output is sent back on, the
+/// channel events for the variables it watches are sent on if the connection can stay open for
+/// them, and the user id of the local process which sent it, if known.
+pub type Submission = (
+    String,
+    UnboundedSender<Vec<Entry>>,
+    Option<UnboundedSender<Event>>,
+    Option<u32>,
+);
 
 #[derive(Clone)]
 pub struct BiBiFi {
-    sender: UnboundedSender<(String, UnboundedSender<Vec<Entry>>)>,
+    sender: UnboundedSender<Submission>,
 }
 
 impl BiBiFi {
-    pub fn new() -> (
-        BiBiFi,
-        UnboundedReceiver<(String, UnboundedSender<Vec<Entry>>)>,
-    ) {
+    pub fn new() -> (BiBiFi, UnboundedReceiver<Submission>) {
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
         (BiBiFi { sender }, receiver)
     }
@@ -28,153 +57,416 @@ impl BiBiFi {
         &self,
         program: String,
         logback: UnboundedSender<Vec<Entry>>,
-    ) -> Result<(), SendError<(String, UnboundedSender<Vec<Entry>>)>> {
-        self.sender.send((program, logback))
+    ) -> Result<(), SendError<Submission>> {
+        self.sender.send((program, logback, None, None))
+    }
+
+    /// Like [submit](#method.submit), but if the program commits having run `watch` commands, the
+    /// changes to the variables it watches are sent on `events` from then on. The channel is
+    /// closed once no variable is watched any more.
+    pub async fn watch(
+        &self,
+        program: String,
+        logback: UnboundedSender<Vec<Entry>>,
+        events: UnboundedSender<Event>,
+    ) -> Result<(), SendError<Submission>> {
+        self.sender.send((program, logback, Some(events), None))
+    }
+
+    /// Like [watch](#method.watch), but for a program sent by a local process run by the user
+    /// `uid`, as the peer credentials of a Unix socket tell. Only such programs may run as a
+    /// superuser once the engine [requires it](struct.Engine.html#method.set_admin_uids).
+    pub async fn watch_from(
+        &self,
+        program: String,
+        logback: UnboundedSender<Vec<Entry>>,
+        events: UnboundedSender<Event>,
+        uid: u32,
+    ) -> Result<(), SendError<Submission>> {
+        self.sender
+            .send((program, logback, Some(events), Some(uid)))
     }
 
     // single "thread" per task
-    pub async fn run(
-        hash: [u8; 32],
-        mut receiver: UnboundedReceiver<(String, UnboundedSender<Vec<Entry>>)>,
-    ) {
-        let mut database = Database::new(hash);
-        while let Some((program, sender)) = receiver.recv().await {
-            let (messages, returned) = BiBiFi::run_program(database.clone(), program).await;
-            sender.send(messages).unwrap();
-            database = match returned {
-                None => database,
-                Some(returned) => returned,
+    pub async fn run(hash: [u8; 32], receiver: UnboundedReceiver<Submission>) {
+        BiBiFi::run_with(Engine::new(hash), receiver).await
+    }
+
+    /// Like [run](#method.run), but over an engine which has already been configured.
+    pub async fn run_with(mut engine: Engine, mut receiver: UnboundedReceiver<Submission>) {
+        let mut sweep = tokio::time::interval(SWEEP_INTERVAL);
+        let mut watchers: Vec<(Subscription, UnboundedSender<Event>)> = Vec::new();
+        loop {
+            let (program, sender, events, uid) = tokio::select! {
+                submission = receiver.recv() => match submission {
+                    Some(submission) => submission,
+                    None => break,
+                },
+                _ = sweep.tick() => {
+                    engine.sweep();
+                    continue;
+                }
+            };
+            let messages = match engine.execute_from(&program, uid) {
+                Ok((messages, subscription)) => {
+                    // watchers which have gone away, or no longer watch anything, are dropped
+                    watchers.retain_mut(|(subscription, events)| {
+                        subscription
+                            .events(engine.database())
+                            .into_iter()
+                            .all(|event| events.send(event).is_ok())
+                            && !subscription.is_empty()
+                    });
+                    if let (Some(subscription), Some(events)) = (subscription, events) {
+                        watchers.push((subscription, events));
+                    }
+                    messages
+                }
+                Err(error) => vec![Entry::from(error)],
             };
+            sender.send(messages).unwrap();
         }
     }
 
     // segmented out for testing :)
-    async fn run_program(
-        mut database: Database,
+    #[cfg(test)]
+    async fn run_program(database: Database, program: String) -> (Vec<Entry>, Option<Database>) {
+        match BiBiFi::execute(database, &VecDeque::new(), program, None, None) {
+            Ok((messages, database, _)) => (messages, Some(database)),
+            Err(error) => (vec![Entry::from(error)], None),
+        }
+    }
+
+    /// Runs a single program against the provided database, returning the output entries, the
+    /// resulting database and what the program asked to watch if it committed, or the reason it was
+    /// rolled back. `restore` commands look up the state to go back to in `checkpoints`. If
+    /// `admin_uids` is given, superusers are denied unless the program was sent by a local process
+    /// run by one of those users.
+    fn execute(
+        database: Database,
+        checkpoints: &VecDeque<Database>,
         program: String,
-    ) -> (Vec<Entry>, Option<Database>) {
-        let program = parse(program);
+        uid: Option<u32>,
+        admin_uids: Option<&[u32]>,
+    ) -> Result<(Vec<Entry>, Database, Option<Subscription>), Error> {
+        let start = Instant::now();
+        let digest = log::digest(&program);
+        let program = match parse(program) {
+            Ok(program) => program,
+            Err(e) => {
+                info!("program not parsed", program = digest, error = e);
+                return Err(Error::Parse(e.to_string()));
+            }
+        };
+        let principal = &program.principal.ident.name;
+        let outcome =
+            BiBiFi::execute_parsed(database, checkpoints, &program, &digest, uid, admin_uids);
+        let latency_us = start.elapsed().as_micros();
+        match &outcome {
+            Ok((messages, _, _)) => info!(
+                "program committed",
+                program = digest,
+                principal = principal,
+                entries = messages.len(),
+                latency_us = latency_us,
+            ),
+            Err(e) => info!(
+                "program rolled back",
+                program = digest,
+                principal = principal,
+                status = e.status(),
+                error = e,
+                latency_us = latency_us,
+            ),
+        }
+        outcome
+    }
+
+    /// The rest of [execute](#method.execute), once the program has been parsed. `digest`
+    /// identifies the program in the log.
+    fn execute_parsed(
+        mut database: Database,
+        checkpoints: &VecDeque<Database>,
+        program: &Program,
+        digest: &str,
+        uid: Option<u32>,
+        admin_uids: Option<&[u32]>,
+    ) -> Result<(Vec<Entry>, Database, Option<Subscription>), Error> {
+        database.begin();
+        match &program.credential {
+            Credential::Password(hash) => {
+                database.check_pass(&program.principal.ident.name, hash)?
+            }
+            Credential::Token(hash) => database.check_token(&program.principal.ident.name, hash)?,
+        }
+        if let Some(admin_uids) = admin_uids {
+            if database.is_superuser(&program.principal.ident.name)
+                && !uid.is_some_and(|uid| admin_uids.contains(&uid))
+            {
+                return Err(Error::NotLocal(program.principal.ident.name.clone()));
+            }
+        }
         let mut messages = Vec::new();
-        if let Ok(program) = program {
-            match database.check_pass(&program.principal.ident.name, &program.password) {
-                DBStatus::SUCCESS => {
-                    let cmd = &mut program.commands.iter();
-                    let mut locals: HashMap<String, Value> = HashMap::new();
-
-                    while let Some(prim) = cmd.next() {
-                        let res = match prim {
-                            PrimitiveCommand::CreatePrincipal(cp) => {
-                                BiBiFi::create_principal(&mut database, &program, cp)
-                            }
-                            PrimitiveCommand::ChangePassword(cp) => {
-                                BiBiFi::change_password(&mut database, &program, cp)
-                            }
-                            PrimitiveCommand::Assignment(a) => {
-                                BiBiFi::assignment(&mut database, &mut locals, &program, a)
-                            }
-                            PrimitiveCommand::Append(a) => {
-                                BiBiFi::append(&mut database, &mut locals, &program, a)
-                            }
-                            PrimitiveCommand::LocalAssignment(a) => {
-                                BiBiFi::local_assignment(&mut database, &mut locals, &program, a)
-                            }
-                            PrimitiveCommand::ForEach(fe) => {
-                                BiBiFi::for_each(&mut database, &mut locals, &program, fe)
-                            }
-                            PrimitiveCommand::SetDelegation(d) => {
-                                BiBiFi::set_delegation(&mut database, &program, d)
-                            }
-                            PrimitiveCommand::DeleteDelegation(d) => {
-                                BiBiFi::delete_delegation(&mut database, &program, d)
-                            }
-                            PrimitiveCommand::DefaultDelegator(p) => {
-                                BiBiFi::default_delegator(&mut database, &program, p)
-                            }
-                        };
-                        if res.status == Status::DENIED || res.status == Status::FAILED {
-                            return (vec![res], None);
-                        }
-                        messages.push(res);
-                    }
-                    match &program.terminator {
-                        TerminatorCommand::Exit => {
-                            if &program.principal.ident.name != "admin" {
-                                (
-                                    vec![Entry {
-                                        status: Status::DENIED,
-                                        output: None,
-                                    }],
-                                    None,
-                                )
-                            } else {
-                                messages.push(Entry {
-                                    status: Status::EXITING,
-                                    output: None,
-                                });
-                                (messages, Some(database))
-                            }
-                        }
-                        TerminatorCommand::Return(e) => {
-                            let value = BiBiFi::evaluate(&database, &locals, &program, e);
-                            match value {
-                                Ok(value) => {
-                                    messages.push(Entry {
-                                        status: Status::RETURNING,
-                                        output: Some(value),
-                                    });
-                                    (messages, Some(database))
-                                }
-                                Err(e) => (vec![e], None),
-                            }
-                        }
-                    }
-                }
-                DBStatus::DENIED => (
-                    vec![Entry {
-                        status: Status::DENIED,
-                        output: None,
-                    }],
-                    None,
+        let mut locals: HashMap<String, Value> = HashMap::new();
+
+        for (index, prim) in program.commands.iter().enumerate() {
+            let (start, before) = (Instant::now(), messages.len());
+            let outcome = BiBiFi::command(
+                &mut database,
+                checkpoints,
+                &mut locals,
+                program,
+                prim,
+                &mut messages,
+            );
+            let latency_us = start.elapsed().as_micros();
+            match &outcome {
+                Ok(()) => debug!(
+                    "command done",
+                    program = digest,
+                    command = index,
+                    // a conditional reports the statuses of the commands it ran, if any
+                    status = messages[before..]
+                        .iter()
+                        .map(|entry| entry.status.to_string())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    latency_us = latency_us,
                 ),
-                DBStatus::FAILED => (
-                    vec![Entry {
-                        status: Status::FAILED,
-                        output: None,
-                    }],
-                    None,
+                Err(e) => debug!(
+                    "command failed",
+                    program = digest,
+                    command = index,
+                    status = e.status(),
+                    error = e,
+                    latency_us = latency_us,
                 ),
             }
-        } else {
-            (
-                vec![Entry {
-                    status: Status::FAILED,
+            outcome?;
+            database.settle();
+        }
+        match &program.terminator {
+            TerminatorCommand::Exit => {
+                if !database.is_superuser(&program.principal.ident.name) {
+                    return Err(Error::NotAdmin(program.principal.ident.name.clone()));
+                }
+                messages.push(Entry {
+                    status: Status::EXITING,
                     output: None,
-                }],
-                None,
-            )
+                });
+            }
+            TerminatorCommand::Return(e) => {
+                let value = BiBiFi::evaluate(&mut database, &locals, program, e)?;
+                messages.push(Entry {
+                    status: Status::RETURNING,
+                    output: Some(value),
+                });
+            }
         }
+        database.settle();
+        let subscription =
+            Subscription::from_entries(&program.principal.ident.name, &messages, &database);
+        Ok((messages, database, subscription))
     }
 
-    fn change_password(database: &mut Database, program: &Program, cp: &ChangePassword) -> Entry {
-        Entry::from(
-            database.change_password(
-                &program.principal.ident.name,
-                &cp.principal.ident.name,
-                &cp.password,
-            ),
-            Status::CHANGE_PASSWORD,
-        )
+    fn command(
+        database: &mut Database,
+        checkpoints: &VecDeque<Database>,
+        locals: &mut HashMap<String, Value>,
+        program: &Program,
+        prim: &PrimitiveCommand,
+        messages: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        let status = match prim {
+            PrimitiveCommand::CreatePrincipal(cp) => {
+                BiBiFi::create_principal(database, program, cp)
+            }
+            PrimitiveCommand::ChangePassword(cp) => BiBiFi::change_password(database, program, cp),
+            PrimitiveCommand::DeletePrincipal(p) => BiBiFi::delete_principal(database, program, p),
+            PrimitiveCommand::CreateGroup(cg) => BiBiFi::create_group(database, program, cg),
+            PrimitiveCommand::AddMember(m) => BiBiFi::set_member_of(database, program, m, true),
+            PrimitiveCommand::RemoveMember(m) => BiBiFi::set_member_of(database, program, m, false),
+            PrimitiveCommand::DisablePrincipal(p) => {
+                BiBiFi::set_disabled(database, program, p, true)
+            }
+            PrimitiveCommand::EnablePrincipal(p) => {
+                BiBiFi::set_disabled(database, program, p, false)
+            }
+            PrimitiveCommand::Login(seconds) => {
+                return BiBiFi::login(database, program, *seconds, messages)
+            }
+            PrimitiveCommand::RevokeTokens(p) => BiBiFi::revoke_tokens(database, program, p),
+            PrimitiveCommand::GrantSuperuser(p) => {
+                BiBiFi::set_superuser(database, program, p, true)
+            }
+            PrimitiveCommand::RevokeSuperuser(p) => {
+                BiBiFi::set_superuser(database, program, p, false)
+            }
+            PrimitiveCommand::Assignment(a) => BiBiFi::assignment(database, locals, program, a),
+            PrimitiveCommand::Append(a) => BiBiFi::append(database, locals, program, a),
+            PrimitiveCommand::LocalAssignment(a) => {
+                BiBiFi::local_assignment(database, locals, program, a)
+            }
+            PrimitiveCommand::ForEach(fe) => BiBiFi::for_each(database, locals, program, fe),
+            PrimitiveCommand::FilterEach(fe) => BiBiFi::filter_each(database, locals, program, fe),
+            PrimitiveCommand::Conditional(c) => {
+                return BiBiFi::conditional(database, checkpoints, locals, program, c, messages)
+            }
+            PrimitiveCommand::SetDelegation(d, l) => {
+                BiBiFi::set_delegation(database, program, d, l)
+            }
+            PrimitiveCommand::DeleteDelegation(d) => {
+                BiBiFi::delete_delegation(database, program, d)
+            }
+            PrimitiveCommand::SetDenial(d) => BiBiFi::set_denial(database, program, d),
+            PrimitiveCommand::DeleteDenial(d) => BiBiFi::delete_denial(database, program, d),
+            PrimitiveCommand::DeleteVariable(i) => {
+                BiBiFi::delete_variable(database, locals, program, i)
+            }
+            PrimitiveCommand::DefaultDelegator(p) => {
+                BiBiFi::default_delegator(database, program, p)
+            }
+            PrimitiveCommand::Restore(n) => BiBiFi::restore(database, checkpoints, program, *n),
+            PrimitiveCommand::Watch(i) => return BiBiFi::subscribe(database, program, i, messages),
+        }?;
+        messages.push(Entry {
+            status,
+            output: None,
+        });
+        Ok(())
     }
 
-    fn create_principal(database: &mut Database, program: &Program, cp: &CreatePrincipal) -> Entry {
-        Entry::from(
-            database.create_principal(
-                &program.principal.ident.name,
-                &cp.principal.ident.name,
-                &cp.password,
-            ),
-            Status::CREATE_PRINCIPAL,
-        )
+    fn change_password(
+        database: &mut Database,
+        program: &Program,
+        cp: &ChangePassword,
+    ) -> Result<Status, Error> {
+        database.change_password(
+            &program.principal.ident.name,
+            &cp.principal.ident.name,
+            &cp.password,
+        )?;
+        Ok(Status::CHANGE_PASSWORD)
+    }
+
+    fn delete_principal(
+        database: &mut Database,
+        program: &Program,
+        p: &Principal,
+    ) -> Result<Status, Error> {
+        database.delete_principal(&program.principal.ident.name, &p.ident.name)?;
+        Ok(Status::DELETE_PRINCIPAL)
+    }
+
+    fn create_group(
+        database: &mut Database,
+        program: &Program,
+        cg: &CreateGroup,
+    ) -> Result<Status, Error> {
+        database.create_group(
+            &program.principal.ident.name,
+            &cg.group.ident.name,
+            &cg.owner
+                .as_ref()
+                .map_or_else(|| "admin".to_string(), |p| p.ident.name.clone()),
+        )?;
+        Ok(Status::CREATE_GROUP)
+    }
+
+    fn set_member_of(
+        database: &mut Database,
+        program: &Program,
+        m: &Membership,
+        member_of: bool,
+    ) -> Result<Status, Error> {
+        database.set_member_of(
+            &program.principal.ident.name,
+            &m.group.ident.name,
+            &m.member.ident.name,
+            member_of,
+        )?;
+        Ok(if member_of {
+            Status::ADD_MEMBER
+        } else {
+            Status::REMOVE_MEMBER
+        })
+    }
+
+    fn set_disabled(
+        database: &mut Database,
+        program: &Program,
+        p: &Principal,
+        disabled: bool,
+    ) -> Result<Status, Error> {
+        database.set_disabled(&program.principal.ident.name, &p.ident.name, disabled)?;
+        Ok(if disabled {
+            Status::DISABLE_PRINCIPAL
+        } else {
+            Status::ENABLE_PRINCIPAL
+        })
+    }
+
+    fn login(
+        database: &mut Database,
+        program: &Program,
+        seconds: Option<u64>,
+        messages: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        // otherwise a token could be renewed for as long as it is never revoked
+        if let Credential::Token(_) = program.credential {
+            return Err(Error::TokenLogin(program.principal.ident.name.clone()));
+        }
+        let seconds = seconds.unwrap_or(SESSION_LIFETIME);
+        if seconds > SESSION_LIFETIME {
+            return Err(Error::SessionTooLong(seconds));
+        }
+        let token = bibifi_util::token();
+        database.open_session(
+            &program.principal.ident.name,
+            bibifi_util::hash(token.clone()),
+            seconds,
+        );
+        messages.push(Entry {
+            status: Status::LOGIN,
+            output: Some(Value::Immediate(token)),
+        });
+        Ok(())
+    }
+
+    fn revoke_tokens(
+        database: &mut Database,
+        program: &Program,
+        p: &Principal,
+    ) -> Result<Status, Error> {
+        database.revoke_sessions(&program.principal.ident.name, &p.ident.name)?;
+        Ok(Status::REVOKE_TOKENS)
+    }
+
+    fn set_superuser(
+        database: &mut Database,
+        program: &Program,
+        p: &Principal,
+        superuser: bool,
+    ) -> Result<Status, Error> {
+        database.set_superuser(&program.principal.ident.name, &p.ident.name, superuser)?;
+        Ok(if superuser {
+            Status::GRANT_SUPERUSER
+        } else {
+            Status::REVOKE_SUPERUSER
+        })
+    }
+
+    fn create_principal(
+        database: &mut Database,
+        program: &Program,
+        cp: &CreatePrincipal,
+    ) -> Result<Status, Error> {
+        database.create_principal(
+            &program.principal.ident.name,
+            &cp.principal.ident.name,
+            &cp.password,
+        )?;
+        Ok(Status::CREATE_PRINCIPAL)
     }
 
     fn assignment(
@@ -182,73 +474,48 @@ impl BiBiFi {
         locals: &mut HashMap<String, Value>,
         program: &Program,
         a: &Assignment,
-    ) -> Entry {
-        let evaluated = match BiBiFi::evaluate(database, locals, program, &a.expr) {
-            Ok(evaluated) => evaluated,
-            Err(e) => return e,
-        };
+    ) -> Result<Status, Error> {
+        let evaluated = BiBiFi::evaluate(database, locals, program, &a.expr)?;
         match &a.variable {
             Variable::Variable(i) => {
                 if locals.contains_key(&i.name) {
                     locals.insert(i.name.clone(), evaluated);
-                    Entry {
-                        status: Status::SET,
-                        output: None,
-                    }
                 } else {
-                    Entry::from(
-                        database.set(&program.principal.ident.name, &i.name, &evaluated),
-                        Status::SET,
-                    )
+                    database.set(&program.principal.ident.name, &i.name, &evaluated)?;
                 }
             }
-            Variable::Member(i1, i2) => match evaluated {
-                Value::Immediate(s) => {
-                    if let Variable::Variable(i) = i2.as_ref() {
-                        if let Some(ref mut value) = locals.get_mut(&i1.name) {
-                            if let Value::FieldVals(map) = value {
-                                if let Some(existing) = map.get_mut(&i.name) {
-                                    *existing = s;
-                                    Entry {
-                                        status: Status::SET,
-                                        output: None,
-                                    }
-                                } else {
-                                    Entry {
-                                        status: Status::FAILED,
-                                        output: None,
-                                    }
-                                }
-                            } else {
-                                Entry {
-                                    status: Status::FAILED,
-                                    output: None,
-                                }
-                            }
-                        } else {
-                            Entry::from(
-                                database.set_member(
-                                    &program.principal.ident.name,
-                                    &i1.name,
-                                    &i.name,
-                                    &s,
-                                ),
-                                Status::SET,
-                            )
-                        }
-                    } else {
-                        Entry {
-                            status: Status::FAILED,
-                            output: None,
-                        }
+            Variable::Member(i1, i2) => {
+                let s = match evaluated {
+                    Value::Immediate(s) => s,
+                    other => {
+                        return Err(Error::TypeMismatch {
+                            expected: "string",
+                            found: other.type_name(),
+                        })
+                    }
+                };
+                let i = match i2.as_ref() {
+                    Variable::Variable(i) => i,
+                    Variable::Member(_, _) => return Err(Error::Unsupported),
+                };
+                match locals.get_mut(&i1.name) {
+                    Some(Value::FieldVals(map)) => match map.get_mut(&i.name) {
+                        Some(existing) => *existing = s,
+                        None => return Err(Error::UnknownField(i.name.clone())),
+                    },
+                    Some(other) => {
+                        return Err(Error::TypeMismatch {
+                            expected: "record",
+                            found: other.type_name(),
+                        })
+                    }
+                    None => {
+                        database.set_member(&program.principal.ident.name, &i1.name, &i.name, &s)?
                     }
                 }
-                _ => Entry {
-                    status: Status::FAILED,
-                    output: None,
-                },
-            },
+            }
         }
+        Ok(Status::SET)
     }
 
     fn append(
@@ -256,30 +523,16 @@ impl BiBiFi {
         locals: &mut HashMap<String, Value>,
         program: &Program,
         ap: &Append,
-    ) -> Entry {
+    ) -> Result<Status, Error> {
         if let Variable::Variable(i) = &ap.variable {
-            let evaluated = match BiBiFi::evaluate(database, locals, program, &ap.expr) {
-                Ok(evaluated) => evaluated,
-                Err(e) => return e,
-            };
+            let evaluated = BiBiFi::evaluate(database, locals, program, &ap.expr)?;
             match locals.get_mut(&i.name) {
-                None => Entry::from(
-                    database.append(&program.principal.ident.name, &i.name, &evaluated),
-                    Status::APPEND,
-                ),
-                Some(ref mut value) => {
-                    **value = evaluated;
-                    Entry {
-                        status: Status::APPEND,
-                        output: None,
-                    }
-                }
+                None => database.append(&program.principal.ident.name, &i.name, &evaluated)?,
+                Some(ref mut value) => **value = evaluated,
             }
+            Ok(Status::APPEND)
         } else {
-            Entry {
-                status: Status::FAILED,
-                output: None,
-            }
+            Err(Error::Unsupported)
         }
     }
 
@@ -288,30 +541,18 @@ impl BiBiFi {
         locals: &mut HashMap<String, Value>,
         program: &Program,
         la: &Assignment,
-    ) -> Entry {
+    ) -> Result<Status, Error> {
         match &la.variable {
             Variable::Variable(i) => {
                 if database.contains(&i.name) || locals.contains_key(&i.name) {
-                    Entry {
-                        status: Status::FAILED,
-                        output: None,
-                    }
+                    Err(Error::DuplicateLocal(i.name.clone()))
                 } else {
-                    let evaluated = match BiBiFi::evaluate(database, locals, program, &la.expr) {
-                        Ok(evaluated) => evaluated,
-                        Err(e) => return e,
-                    };
+                    let evaluated = BiBiFi::evaluate(database, locals, program, &la.expr)?;
                     locals.insert(i.name.clone(), evaluated);
-                    Entry {
-                        status: Status::LOCAL,
-                        output: None,
-                    }
+                    Ok(Status::LOCAL)
                 }
             }
-            Variable::Member(_, _) => Entry {
-                status: Status::FAILED,
-                output: None,
-            },
+            Variable::Member(_, _) => Err(Error::Unsupported),
         }
     }
 
@@ -320,298 +561,547 @@ impl BiBiFi {
         locals: &mut HashMap<String, Value>,
         program: &Program,
         fe: &ForEach,
-    ) -> Entry {
-        match &fe.value {
-            Variable::Variable(i) => {
-                if locals.contains_key(&i.name) || database.contains(&i.name) {
-                    Entry {
-                        status: Status::FAILED,
-                        output: None,
-                    }
-                } else {
-                    match &fe.list {
-                        Variable::Variable(listi) => {
-                            let mut locallocals = locals.clone();
-                            let modification = |item: Value| {
-                                locallocals.insert(i.name.clone(), item);
-                                let res = match BiBiFi::evaluate(
-                                    database,
-                                    &locallocals,
-                                    program,
-                                    &fe.expr,
-                                ) {
-                                    Ok(v) => Ok(v),
-                                    Err(e) => Err(e),
-                                };
-                                res
-                            };
+    ) -> Result<Status, Error> {
+        let i = match &fe.value {
+            Variable::Variable(i) => i,
+            Variable::Member(_, _) => return Err(Error::Unsupported),
+        };
+        if locals.contains_key(&i.name) || database.contains(&i.name) {
+            return Err(Error::DuplicateLocal(i.name.clone()));
+        }
+        let listi = match &fe.list {
+            Variable::Variable(listi) => listi,
+            Variable::Member(_, _) => return Err(Error::Unsupported),
+        };
+        let mut locallocals = locals.clone();
+        let mut modification = |database: &mut Database, item: Value| {
+            locallocals.insert(i.name.clone(), item);
+            BiBiFi::evaluate(database, &locallocals, program, &fe.expr)
+        };
 
-                            if let Some(list) = locals.get(&listi.name).cloned() {
-                                match list {
-                                    Value::List(list) => {
-                                        let modified = list
-                                            .iter()
-                                            .cloned()
-                                            .map(modification)
-                                            .collect::<Vec<Result<Value, Entry>>>();
-                                        if let Some(bad) =
-                                            modified.iter().find(|item| item.is_err())
-                                        {
-                                            match bad {
-                                                Err(e) => e.clone(),
-                                                _ => panic!(),
-                                            }
-                                        } else {
-                                            locals.insert(
-                                                listi.name.clone(),
-                                                Value::List(
-                                                    modified
-                                                        .iter()
-                                                        .map(|item| item.clone().unwrap())
-                                                        .collect(),
-                                                ),
-                                            );
-                                            Entry {
-                                                status: Status::FOREACH,
-                                                output: None,
-                                            }
-                                        }
-                                    }
-                                    _ => Entry {
-                                        status: Status::FAILED,
-                                        output: None,
-                                    },
-                                }
-                            } else {
-                                match database
-                                    .get(&program.principal.ident.name, &listi.name)
-                                    .map(|value| value.clone())
-                                {
-                                    Ok(list) => match list {
-                                        Value::List(list) => {
-                                            let modified = list
-                                                .iter()
-                                                .cloned()
-                                                .map(modification)
-                                                .collect::<Vec<Result<Value, Entry>>>();
-                                            if let Some(bad) =
-                                                modified.iter().find(|item| item.is_err())
-                                            {
-                                                match bad {
-                                                    Err(e) => e.clone(),
-                                                    _ => panic!(),
-                                                }
-                                            } else {
-                                                Entry::from(
-                                                    database.set(
-                                                        &program.principal.ident.name,
-                                                        &listi.name,
-                                                        &Value::List(
-                                                            modified
-                                                                .iter()
-                                                                .map(|item| item.clone().unwrap())
-                                                                .collect(),
-                                                        ),
-                                                    ),
-                                                    Status::FOREACH,
-                                                )
-                                            }
-                                        }
-                                        _ => Entry {
-                                            status: Status::FAILED,
-                                            output: None,
-                                        },
-                                    },
-                                    Err(e) => Entry::from(e, Status::FAILED),
-                                }
-                            }
+        if let Some(list) = locals.get(&listi.name).cloned() {
+            match list {
+                Value::List(list) => {
+                    let modified = list
+                        .iter()
+                        .cloned()
+                        .map(|item| modification(database, item))
+                        .collect::<Result<Vec<Value>, Error>>()?;
+                    locals.insert(listi.name.clone(), Value::List(modified));
+                    Ok(Status::FOREACH)
+                }
+                other => Err(Error::TypeMismatch {
+                    expected: "list",
+                    found: other.type_name(),
+                }),
+            }
+        } else {
+            match database
+                .get(&program.principal.ident.name, &listi.name)
+                .cloned()?
+            {
+                Value::List(list) => {
+                    let modified = list
+                        .iter()
+                        .cloned()
+                        .map(|item| modification(database, item))
+                        .collect::<Result<Vec<Value>, Error>>()?;
+                    database.set(
+                        &program.principal.ident.name,
+                        &listi.name,
+                        &Value::List(modified),
+                    )?;
+                    Ok(Status::FOREACH)
+                }
+                other => Err(Error::TypeMismatch {
+                    expected: "list",
+                    found: other.type_name(),
+                }),
+            }
+        }
+    }
+
+    fn filter_each(
+        database: &mut Database,
+        locals: &mut HashMap<String, Value>,
+        program: &Program,
+        fe: &FilterEach,
+    ) -> Result<Status, Error> {
+        let i = match &fe.value {
+            Variable::Variable(i) => i,
+            Variable::Member(_, _) => return Err(Error::Unsupported),
+        };
+        if locals.contains_key(&i.name) || database.contains(&i.name) {
+            return Err(Error::DuplicateLocal(i.name.clone()));
+        }
+        let listi = match &fe.list {
+            Variable::Variable(listi) => listi,
+            Variable::Member(_, _) => return Err(Error::Unsupported),
+        };
+        let global = !locals.contains_key(&listi.name);
+        let list = match locals.get(&listi.name) {
+            Some(list) => list.clone(),
+            None => database
+                .get(&program.principal.ident.name, &listi.name)?
+                .clone(),
+        };
+        let list = match list {
+            Value::List(list) => list,
+            other => {
+                return Err(Error::TypeMismatch {
+                    expected: "list",
+                    found: other.type_name(),
+                })
+            }
+        };
+
+        let mut locallocals = locals.clone();
+        let mut kept = Vec::new();
+        for item in list {
+            locallocals.insert(i.name.clone(), item.clone());
+            if BiBiFi::condition(database, &locallocals, program, &fe.condition)? {
+                kept.push(item);
+            }
+        }
+        if global {
+            database.set(
+                &program.principal.ident.name,
+                &listi.name,
+                &Value::List(kept),
+            )?;
+        } else {
+            locals.insert(listi.name.clone(), Value::List(kept));
+        }
+        Ok(Status::FILTEREACH)
+    }
+
+    fn conditional(
+        database: &mut Database,
+        checkpoints: &VecDeque<Database>,
+        locals: &mut HashMap<String, Value>,
+        program: &Program,
+        c: &Conditional,
+        messages: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        let (status, block) = if BiBiFi::condition(database, locals, program, &c.condition)? {
+            (Status::IF_THEN, &c.then)
+        } else {
+            (Status::IF_ELSE, &c.otherwise)
+        };
+        database.settle();
+        messages.push(Entry {
+            status,
+            output: None,
+        });
+        for prim in block {
+            BiBiFi::command(database, checkpoints, locals, program, prim, messages)?;
+            database.settle();
+        }
+        Ok(())
+    }
+
+    fn condition(
+        database: &mut Database,
+        locals: &HashMap<String, Value>,
+        program: &Program,
+        condition: &Condition,
+    ) -> Result<bool, Error> {
+        match condition {
+            Condition::Equal(a, b) | Condition::NotEqual(a, b) => {
+                let a = BiBiFi::evaluate_string(database, locals, program, a)?;
+                let b = BiBiFi::evaluate_string(database, locals, program, b)?;
+                Ok((a == b) == matches!(condition, Condition::Equal(_, _)))
+            }
+            Condition::Defined(Variable::Variable(i)) => {
+                Ok(locals.contains_key(&i.name) || database.contains(&i.name))
+            }
+            Condition::Defined(Variable::Member(i1, v2)) => {
+                let i2 = match v2.as_ref() {
+                    Variable::Variable(i2) => i2,
+                    Variable::Member(_, _) => return Err(Error::Unsupported),
+                };
+                match locals.get(&i1.name) {
+                    Some(Value::FieldVals(fv)) => Ok(fv.contains_key(&i2.name)),
+                    Some(_) => Ok(false),
+                    None if !database.contains(&i1.name) => Ok(false),
+                    None => {
+                        match database.get_member(&program.principal.ident.name, &i1.name, &i2.name)
+                        {
+                            Ok(_) => Ok(true),
+                            Err(DBError::UnknownField { .. })
+                            | Err(DBError::TypeMismatch { .. }) => Ok(false),
+                            Err(e) => Err(e.into()),
                         }
-                        Variable::Member(_, _) => Entry {
-                            status: Status::FAILED,
-                            output: None,
-                        },
                     }
                 }
             }
-            Variable::Member(_, _) => Entry {
-                status: Status::FAILED,
-                output: None,
+        }
+    }
+
+    fn set_delegation(
+        database: &mut Database,
+        program: &Program,
+        d: &Delegation,
+        l: &Limit,
+    ) -> Result<Status, Error> {
+        database.delegate_limited(
+            &program.principal.ident.name,
+            &BiBiFi::target(&d.target),
+            &d.delegator.ident.name,
+            &BiBiFi::right(&d.right),
+            &d.delegated.ident.name,
+            &DBLimit {
+                seconds: l.seconds,
+                uses: l.uses,
             },
+        )?;
+        Ok(Status::SET_DELEGATION)
+    }
+
+    fn delete_delegation(
+        database: &mut Database,
+        program: &Program,
+        d: &Delegation,
+    ) -> Result<Status, Error> {
+        database.undelegate(
+            &program.principal.ident.name,
+            &BiBiFi::target(&d.target),
+            &d.delegator.ident.name,
+            &BiBiFi::right(&d.right),
+            &d.delegated.ident.name,
+        )?;
+        Ok(Status::DELETE_DELEGATION)
+    }
+
+    fn set_denial(database: &mut Database, program: &Program, d: &Denial) -> Result<Status, Error> {
+        database.deny(
+            &program.principal.ident.name,
+            &BiBiFi::target(&d.target),
+            &BiBiFi::right(&d.right),
+            &d.denied.ident.name,
+        )?;
+        Ok(Status::SET_DENIAL)
+    }
+
+    fn delete_denial(
+        database: &mut Database,
+        program: &Program,
+        d: &Denial,
+    ) -> Result<Status, Error> {
+        database.undeny(
+            &program.principal.ident.name,
+            &BiBiFi::target(&d.target),
+            &BiBiFi::right(&d.right),
+            &d.denied.ident.name,
+        )?;
+        Ok(Status::DELETE_DENIAL)
+    }
+
+    fn target(target: &ParserTarget) -> Target {
+        match target {
+            ParserTarget::All => Target::All,
+            ParserTarget::Variable(i) => Target::Variable(i.name.clone()),
+            ParserTarget::Field(i, f) => Target::Field(i.name.clone(), f.name.clone()),
+            ParserTarget::Pattern(prefix) => Target::Pattern(prefix.clone()),
         }
     }
 
-    fn set_delegation(database: &mut Database, program: &Program, d: &Delegation) -> Entry {
-        Entry::from(
-            database.delegate(
-                &program.principal.ident.name,
-                &match &d.target {
-                    ParserTarget::All => Target::All,
-                    ParserTarget::Variable(i) => Target::Variable(i.name.clone()),
-                },
-                &d.delegator.ident.name,
-                &match &d.right {
-                    ParserRight::Read => Right::Read,
-                    ParserRight::Write => Right::Write,
-                    ParserRight::Append => Right::Append,
-                    ParserRight::Delegate => Right::Delegate,
-                },
-                &d.delegated.ident.name,
-            ),
-            Status::SET_DELEGATION,
-        )
+    fn right(right: &ParserRight) -> Right {
+        match right {
+            ParserRight::Read => Right::Read,
+            ParserRight::Write => Right::Write,
+            ParserRight::Append => Right::Append,
+            ParserRight::Delegate => Right::Delegate,
+        }
     }
 
-    fn delete_delegation(database: &mut Database, program: &Program, d: &Delegation) -> Entry {
-        Entry::from(
-            database.undelegate(
-                &program.principal.ident.name,
-                &match &d.target {
-                    ParserTarget::All => Target::All,
-                    ParserTarget::Variable(i) => Target::Variable(i.name.clone()),
-                },
-                &d.delegator.ident.name,
-                &match &d.right {
-                    ParserRight::Read => Right::Read,
-                    ParserRight::Write => Right::Write,
-                    ParserRight::Append => Right::Append,
-                    ParserRight::Delegate => Right::Delegate,
-                },
-                &d.delegated.ident.name,
-            ),
-            Status::DELETE_DELEGATION,
-        )
+    fn delete_variable(
+        database: &mut Database,
+        locals: &mut HashMap<String, Value>,
+        program: &Program,
+        i: &Identifier,
+    ) -> Result<Status, Error> {
+        if locals.remove(&i.name).is_none() {
+            database.delete(&program.principal.ident.name, &i.name)?;
+        }
+        Ok(Status::DELETE_VARIABLE)
+    }
+
+    fn default_delegator(
+        database: &mut Database,
+        program: &Program,
+        p: &Principal,
+    ) -> Result<Status, Error> {
+        database.set_default_delegator(&program.principal.ident.name, &p.ident.name)?;
+        Ok(Status::DEFAULT_DELEGATOR)
+    }
+
+    fn subscribe(
+        database: &mut Database,
+        program: &Program,
+        i: &Identifier,
+        messages: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        database.get(&program.principal.ident.name, &i.name)?;
+        messages.push(Entry {
+            status: Status::WATCH,
+            output: Some(Value::Immediate(i.name.clone())),
+        });
+        Ok(())
     }
 
-    fn default_delegator(database: &mut Database, program: &Program, p: &Principal) -> Entry {
-        Entry::from(
-            database.set_default_delegator(&program.principal.ident.name, &p.ident.name),
-            Status::DEFAULT_DELEGATOR,
-        )
+    fn restore(
+        database: &mut Database,
+        checkpoints: &VecDeque<Database>,
+        program: &Program,
+        sequence: u64,
+    ) -> Result<Status, Error> {
+        if !database.is_superuser(&program.principal.ident.name) {
+            return Err(Error::NotAdmin(program.principal.ident.name.clone()));
+        }
+        let checkpoint = checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.sequence() == sequence)
+            .ok_or(Error::UnknownCheckpoint(sequence))?;
+        database.restore(checkpoint);
+        Ok(Status::RESTORE)
     }
 
     fn evaluate(
-        database: &Database,
+        database: &mut Database,
         locals: &HashMap<String, Value>,
         program: &Program,
         expr: &Expr,
-    ) -> Result<Value, Entry> {
+    ) -> Result<Value, Error> {
         match expr {
             Expr::Value(v) => BiBiFi::evaluate_value(database, locals, program, v),
             Expr::EmptyList => Ok(Value::List(Vec::new())),
             Expr::FieldVals(fv) => BiBiFi::evaluate_fieldvals(database, locals, program, fv),
+            Expr::Function(f) => BiBiFi::evaluate_function(database, locals, program, f),
+            Expr::Let(l) => BiBiFi::evaluate_let(database, locals, program, l),
+        }
+    }
+
+    fn evaluate_let(
+        database: &mut Database,
+        locals: &HashMap<String, Value>,
+        program: &Program,
+        l: &Let,
+    ) -> Result<Value, Error> {
+        let name = &l.variable.name;
+        if locals.contains_key(name) || database.contains(name) {
+            return Err(Error::DuplicateLocal(name.clone()));
+        }
+        let value = BiBiFi::evaluate(database, locals, program, &l.value)?;
+        let mut scoped = locals.clone();
+        scoped.insert(name.clone(), value);
+        BiBiFi::evaluate(database, &scoped, program, &l.body)
+    }
+
+    fn evaluate_function(
+        database: &mut Database,
+        locals: &HashMap<String, Value>,
+        program: &Program,
+        function: &Function,
+    ) -> Result<Value, Error> {
+        let string = |database: &mut Database, expr: &Expr| {
+            BiBiFi::evaluate_string(database, locals, program, expr)
+        };
+        match function {
+            Function::Concat(a, b) => {
+                let mut a = string(database, a)?;
+                a.push_str(&string(database, b)?);
+                a.truncate(65535);
+                Ok(Value::Immediate(a))
+            }
+            Function::ToLower(a) => Ok(Value::Immediate(string(database, a)?.to_ascii_lowercase())),
+            Function::Equal(a, b) | Function::NotEqual(a, b) => {
+                let a = BiBiFi::evaluate(database, locals, program, a)?;
+                let b = BiBiFi::evaluate(database, locals, program, b)?;
+                let equal = match (&a, &b) {
+                    (Value::Immediate(_), Value::Immediate(_))
+                    | (Value::FieldVals(_), Value::FieldVals(_)) => a == b,
+                    (Value::Immediate(_), _) | (Value::FieldVals(_), _) => {
+                        return Err(Error::TypeMismatch {
+                            expected: a.type_name(),
+                            found: b.type_name(),
+                        })
+                    }
+                    (Value::List(_), _) => {
+                        return Err(Error::TypeMismatch {
+                            expected: "string or record",
+                            found: "list",
+                        })
+                    }
+                };
+                let holds = equal == matches!(function, Function::Equal(_, _));
+                Ok(Value::Immediate(if holds { "" } else { "0" }.to_string()))
+            }
+            Function::Split(a, b) => {
+                let a = string(database, a)?;
+                let b = string(database, b)?;
+                let (fst, snd) = match a.find(&b) {
+                    Some(at) if !b.is_empty() => {
+                        (a[..at].to_string(), a[at + b.len()..].to_string())
+                    }
+                    _ => (a, String::new()),
+                };
+                let mut map = HashMap::new();
+                map.insert("fst".to_string(), fst);
+                map.insert("snd".to_string(), snd);
+                Ok(Value::FieldVals(map))
+            }
+            Function::Denials(t) => {
+                let denials =
+                    database.denials(&program.principal.ident.name, &BiBiFi::target(t))?;
+                Ok(Value::List(
+                    denials
+                        .into_iter()
+                        .map(|d| {
+                            let mut map = HashMap::new();
+                            let target = match &d.target {
+                                Target::All => "all".to_string(),
+                                Target::Variable(x) => x.clone(),
+                                Target::Field(x, f) => format!("{}.{}", x, f),
+                                Target::Pattern(prefix) => format!("{}*", prefix),
+                            };
+                            map.insert("target".to_string(), target);
+                            let right = format!("{:?}", d.right).to_lowercase();
+                            map.insert("right".to_string(), right);
+                            map.insert("principal".to_string(), d.principal.clone());
+                            Value::FieldVals(map)
+                        })
+                        .collect(),
+                ))
+            }
+            Function::History(i) => {
+                let history = database.history(&program.principal.ident.name, &i.name)?;
+                Ok(Value::List(
+                    history
+                        .iter()
+                        .map(|v| {
+                            let mut map = HashMap::new();
+                            map.insert("sequence".to_string(), v.sequence.to_string());
+                            map.insert("principal".to_string(), v.principal.clone());
+                            Value::FieldVals(map)
+                        })
+                        .collect(),
+                ))
+            }
+            Function::Metadata(i) => {
+                let metadata = database.metadata(&program.principal.ident.name, &i.name)?;
+                let mut map = HashMap::new();
+                map.insert("creator".to_string(), metadata.creator.clone());
+                map.insert("writer".to_string(), metadata.writer.clone());
+                map.insert("created".to_string(), metadata.created.to_string());
+                map.insert("modified".to_string(), metadata.modified.to_string());
+                map.insert("size".to_string(), metadata.size.to_string());
+                Ok(Value::FieldVals(map))
+            }
+        }
+    }
+
+    fn evaluate_string(
+        database: &mut Database,
+        locals: &HashMap<String, Value>,
+        program: &Program,
+        expr: &Expr,
+    ) -> Result<String, Error> {
+        match BiBiFi::evaluate(database, locals, program, expr)? {
+            Value::Immediate(s) => Ok(s),
+            other => Err(Error::TypeMismatch {
+                expected: "string",
+                found: other.type_name(),
+            }),
         }
     }
 
     fn get_variable(
-        database: &Database,
+        database: &mut Database,
         locals: &HashMap<String, Value>,
         program: &Program,
         variable: &String,
-    ) -> Result<Value, Entry> {
+    ) -> Result<Value, Error> {
         match locals.get(variable) {
-            None => match database.get(&program.principal.ident.name, variable) {
-                Ok(value) => Ok(value.clone()),
-                Err(DBStatus::DENIED) => Err(Entry {
-                    status: Status::DENIED,
-                    output: None,
-                }),
-                Err(DBStatus::FAILED) => Err(Entry {
-                    status: Status::FAILED,
-                    output: None,
-                }),
-                _ => panic!(),
-            },
+            None => Ok(database.read(&program.principal.ident.name, variable)?),
             Some(value) => Ok(value.clone()),
         }
     }
 
     fn evaluate_value(
-        database: &Database,
+        database: &mut Database,
         locals: &HashMap<String, Value>,
         program: &Program,
         value: &ParserValue,
-    ) -> Result<Value, Entry> {
+    ) -> Result<Value, Error> {
         match value {
             ParserValue::Variable(v) => match v {
                 Variable::Variable(i) => BiBiFi::get_variable(database, locals, program, &i.name),
                 Variable::Member(i1, v2) => match v2.as_ref() {
+                    Variable::Variable(i2) if !locals.contains_key(&i1.name) => {
+                        Ok(Value::Immediate(
+                            database
+                                .get_member(&program.principal.ident.name, &i1.name, &i2.name)?
+                                .clone(),
+                        ))
+                    }
                     Variable::Variable(i2) => {
-                        match BiBiFi::get_variable(database, locals, program, &i1.name) {
-                            Ok(variable) => match variable {
-                                Value::FieldVals(fv) => {
-                                    if let Some(s) = fv.get(&i2.name) {
-                                        Ok(Value::Immediate(s.clone()))
-                                    } else {
-                                        Err(Entry {
-                                            status: FAILED,
-                                            output: None,
-                                        })
-                                    }
-                                }
-                                _ => Err(Entry {
-                                    status: FAILED,
-                                    output: None,
-                                }),
+                        match BiBiFi::get_variable(database, locals, program, &i1.name)? {
+                            Value::FieldVals(fv) => match fv.get(&i2.name) {
+                                Some(s) => Ok(Value::Immediate(s.clone())),
+                                None => Err(Error::UnknownField(i2.name.clone())),
                             },
-                            Err(e) => Err(e),
+                            other => Err(Error::TypeMismatch {
+                                expected: "record",
+                                found: other.type_name(),
+                            }),
                         }
                     }
-                    Variable::Member(_, _) => panic!(),
+                    Variable::Member(_, _) => Err(Error::Unsupported),
                 },
             },
             ParserValue::String(s) => Ok(Value::Immediate(s.clone())),
+            ParserValue::Version(i, sequence) => Ok(database
+                .get_version(&program.principal.ident.name, &i.name, *sequence)?
+                .clone()),
         }
     }
 
     fn evaluate_fieldvals(
-        database: &Database,
+        database: &mut Database,
         locals: &HashMap<String, Value>,
         program: &Program,
-        value: &Vec<Assignment>,
-    ) -> Result<Value, Entry> {
+        value: &[Assignment],
+    ) -> Result<Value, Error> {
         let mut map = HashMap::new();
         for a in value {
             match &a.variable {
                 Variable::Variable(i) => {
                     if map.contains_key(&i.name) {
-                        return Err(Entry {
-                            // duplicate entry
-                            status: Status::FAILED,
-                            output: None,
-                        });
+                        return Err(Error::DuplicateField(i.name.clone()));
                     }
                     map.insert(
                         i.name.clone(),
                         match &a.expr {
                             Expr::Value(value) => {
-                                match BiBiFi::evaluate_value(database, locals, program, value) {
-                                    Ok(value) => match value {
-                                        Value::Immediate(i) => i,
-                                        Value::List(_) | Value::FieldVals(_) => {
-                                            return Err(Entry {
-                                                status: Status::FAILED,
-                                                output: None,
-                                            })
-                                        }
-                                    },
-                                    Err(e) => return Err(e),
+                                match BiBiFi::evaluate_value(database, locals, program, value)? {
+                                    Value::Immediate(i) => i,
+                                    other => {
+                                        return Err(Error::TypeMismatch {
+                                            expected: "string",
+                                            found: other.type_name(),
+                                        })
+                                    }
                                 }
                             }
-                            Expr::EmptyList | Expr::FieldVals(_) => {
-                                return Err(Entry {
-                                    status: Status::FAILED,
-                                    output: None,
-                                })
-                            }
+                            Expr::EmptyList
+                            | Expr::FieldVals(_)
+                            | Expr::Function(_)
+                            | Expr::Let(_) => return Err(Error::Unsupported),
                         },
                     );
                 }
-                Variable::Member(_, _) => {
-                    return Err(Entry {
-                        status: Status::FAILED,
-                        output: None,
-                    })
-                }
+                Variable::Member(_, _) => return Err(Error::Unsupported),
             }
         }
         Ok(Value::FieldVals(map))
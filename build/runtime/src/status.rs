@@ -1,5 +1,6 @@
-use bibifi_database::{Status as DBStatus, Value};
+use bibifi_database::Value;
 use serde::Serialize;
+use std::fmt;
 
 #[derive(Clone, Serialize, Debug, Eq, PartialEq)]
 pub struct Entry {
@@ -13,34 +14,42 @@ pub struct Entry {
 pub enum Status {
     CREATE_PRINCIPAL,
     CHANGE_PASSWORD,
+    DELETE_PRINCIPAL,
+    CREATE_GROUP,
+    ADD_MEMBER,
+    REMOVE_MEMBER,
+    DISABLE_PRINCIPAL,
+    ENABLE_PRINCIPAL,
+    LOGIN,
+    REVOKE_TOKENS,
+    GRANT_SUPERUSER,
+    REVOKE_SUPERUSER,
     SET,
     APPEND,
     LOCAL,
     FOREACH,
+    FILTEREACH,
+    IF_THEN,
+    IF_ELSE,
     SET_DELEGATION,
     DELETE_DELEGATION,
+    SET_DENIAL,
+    DELETE_DENIAL,
+    DELETE_VARIABLE,
     DEFAULT_DELEGATOR,
+    RESTORE,
+    WATCH,
     DENIED,
     FAILED,
     RETURNING,
     EXITING,
+    CHANGED,
+    DELETED,
 }
 
-impl Entry {
-    pub fn from(status: DBStatus, success_status: Status) -> Self {
-        match status {
-            DBStatus::SUCCESS => Entry {
-                status: success_status,
-                output: None,
-            },
-            DBStatus::DENIED => Entry {
-                status: Status::DENIED,
-                output: None,
-            },
-            DBStatus::FAILED => Entry {
-                status: Status::FAILED,
-                output: None,
-            },
-        }
+impl fmt::Display for Status {
+    /// The status as it is written to clients.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
     }
 }
@@ -1,8 +1,13 @@
+#![allow(unused_imports, unused_mut, unused_variables)]
+#![allow(clippy::assertions_on_constants)]
+
 use super::*;
 use crate::status::Status::*;
 use bibifi_database::Database;
+use bibifi_database::Error as DBError;
 use bibifi_database::Value;
 use bibifi_database::Value::Immediate;
+use bibifi_database::Weakness;
 use bibifi_util::hash;
 use tokio::sync::mpsc::unbounded_channel;
 
@@ -120,12 +125,8 @@ async fn t4_acting_p_inc_pass() {
 async fn t5_non_admin_exit_cmd() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let program = r#"as principal bob password "bob_pass" do
                             exit
@@ -150,12 +151,8 @@ async fn t5_non_admin_exit_cmd() {
 async fn t6_non_admin_exit_cmd() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let program = r#"as principal bob password "bob_pass" do
                             return "done"
@@ -181,12 +178,8 @@ async fn t7_create_principal() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     let mut db_out_exp = db_in.clone();
     assert_eq!(
-        db_out_exp.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_out_exp.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let program = r#"as principal admin password "admin_pass" do
                             create principal bob "bob_pass"
@@ -219,12 +212,8 @@ async fn t7_create_principal() {
 async fn t8_non_admin_create_principal() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let program = r#"as principal bob password "bob_pass" do
                             create principal alice "alice_pass"
@@ -250,12 +239,8 @@ async fn t8_non_admin_create_principal() {
 async fn t9_recreate_principal() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let program = r#"as principal admin password "admin_pass" do
                             create principal anyone "anyone_pass"
@@ -280,21 +265,13 @@ async fn t9_recreate_principal() {
 async fn t10_change_password() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass",),
+        Ok(())
     );
     let mut db_out_exp = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_out_exp.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_new_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_out_exp.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_new_pass",),
+        Ok(())
     );
     let program = r#"as principal bob password "bob_pass" do
                             change password bob "bob_new_pass"
@@ -327,12 +304,8 @@ async fn t11_admin_change_password() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     let mut db_out_exp = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_out_exp.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_new_pass".to_string()),
-        ),
-        DBStatus::SUCCESS
+        db_out_exp.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_new_pass",),
+        Ok(())
     );
     let program = r#"as principal admin password "admin_pass" do
                             create principal bob "bob_pass"
@@ -371,12 +344,8 @@ async fn t12_non_exist_pric_change_password() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     let mut db_out_exp = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_out_exp.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_new_pass".to_string())
-        ),
-        DBStatus::SUCCESS
+        db_out_exp.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_new_pass"),
+        Ok(())
     );
     let program = r#"as principal admin password "admin_pass" do
                             create principal bob "bob_pass"
@@ -408,15 +377,11 @@ async fn t13_set_without_permission() {
             &"my_var".to_string(),
             &Value::Immediate("wolla".to_string())
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string())
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass"),
+        Ok(())
     );
     let (sender, mut receiver) = unbounded_channel::<Entry>();
     let program = r#"as principal bob password "bob_pass" do
@@ -448,15 +413,11 @@ async fn t14_lhs_rhs_pref() {
             &"my_var".to_string(),
             &Value::Immediate("wolla".to_string())
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string())
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass"),
+        Ok(())
     );
     let (sender, mut receiver) = unbounded_channel::<Entry>();
     let program = r#"as principal bob password "bob_pass" do
@@ -482,12 +443,8 @@ async fn t14_lhs_rhs_pref() {
 async fn t15_append() {
     let mut db_in = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_in.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string())
-        ),
-        DBStatus::SUCCESS
+        db_in.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass"),
+        Ok(())
     );
     assert_eq!(
         db_in.set(
@@ -495,7 +452,7 @@ async fn t15_append() {
             &"my_var".to_string(),
             &Value::List(vec![Value::Immediate("wolla".to_string())])
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     assert_eq!(
         db_in.delegate(
@@ -505,16 +462,12 @@ async fn t15_append() {
             &Right::Append,
             &"bob".to_string()
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     let mut db_out_exp = Database::new(hash("admin_pass".to_string()));
     assert_eq!(
-        db_out_exp.create_principal(
-            &"admin".to_string(),
-            &"bob".to_string(),
-            &hash("bob_pass".to_string())
-        ),
-        DBStatus::SUCCESS
+        db_out_exp.create_principal(&"admin".to_string(), &"bob".to_string(), "bob_pass"),
+        Ok(())
     );
     assert_eq!(
         db_out_exp.set(
@@ -525,7 +478,7 @@ async fn t15_append() {
                 Value::Immediate("added".to_string())
             ])
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     assert_eq!(
         db_out_exp.delegate(
@@ -535,7 +488,7 @@ async fn t15_append() {
             &Right::Append,
             &"bob".to_string()
         ),
-        DBStatus::SUCCESS
+        Ok(())
     );
     let (sender, mut receiver) = unbounded_channel::<Entry>();
     let program = r#"as principal bob password "bob_pass" do
@@ -562,3 +515,1440 @@ async fn t15_append() {
         _ => assert!(false),
     }
 }
+
+// the engine commits successful programs and leaves the database untouched otherwise
+#[test]
+fn engine_transactions() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            set my_var = "wolla"
+                            return my_var
+                            ***"#;
+    assert_eq!(
+        Ok(vec![
+            Entry {
+                status: SET,
+                output: None
+            },
+            Entry {
+                status: RETURNING,
+                output: Some(Value::Immediate("wolla".to_string()))
+            }
+        ]),
+        engine.execute(program)
+    );
+    let committed = engine.clone();
+    let program = r#"as principal admin password "admin_pass" do
+                            set my_var = "changed"
+                            set other = missing
+                            return my_var
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::UnknownVariable(
+            "missing".to_string()
+        ))),
+        engine.execute(program)
+    );
+    assert_eq!(committed, engine);
+    assert!(engine.database().contains(&"my_var".to_string()));
+    assert!(!engine.database().contains(&"other".to_string()));
+}
+
+// the engine reports why a program was rolled back
+#[test]
+fn engine_error_reasons() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob_pass"
+                            set my_var = "wolla"
+                            return "done"
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+
+    let program = r#"as principal bob password "bob_pass" do
+                            local my_var = "shadow"
+                            return my_var
+                            ***"#;
+    let error = engine.execute(program).unwrap_err();
+    assert_eq!(Error::DuplicateLocal("my_var".to_string()), error);
+    assert_eq!(FAILED, error.status());
+
+    let program = r#"as principal bob password "bob_pass" do
+                            return my_var
+                            ***"#;
+    let error = engine.execute(program).unwrap_err();
+    assert_eq!(
+        Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "my_var".to_string(),
+            right: Right::Read,
+        }),
+        error
+    );
+    assert_eq!(DENIED, error.status());
+
+    let program = r#"as principal bob password "bob_pass" do
+                            exit
+                            ***"#;
+    assert_eq!(
+        Entry {
+            status: DENIED,
+            output: None
+        },
+        Entry::from(engine.execute(program).unwrap_err())
+    );
+}
+
+// only the taken block of a conditional runs, and its failures roll back the whole program
+#[test]
+fn conditional() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            set answer = "yes"
+                            set rec = { f = "field" }
+                            if answer == "yes" then
+                               if defined rec.g then
+                                  set result = "wrong"
+                               else
+                                  set result = rec.f
+                               end
+                            else
+                               set result = missing
+                            end
+                            if answer != "yes" then
+                               set other = "wrong"
+                            end
+                            return result
+                            ***"#;
+    let statuses = |entries: Vec<Entry>| {
+        entries
+            .into_iter()
+            .map(|entry| entry.status)
+            .collect::<Vec<Status>>()
+    };
+    assert_eq!(
+        vec![SET, SET, IF_THEN, IF_ELSE, SET, IF_ELSE, RETURNING],
+        statuses(engine.execute(program).unwrap())
+    );
+    assert!(!engine.database().contains(&"other".to_string()));
+
+    let committed = engine.clone();
+    let program = r#"as principal admin password "admin_pass" do
+                            set answer = "no"
+                            if defined answer then
+                               set rec = []
+                               if answer == rec then
+                                  set other = "wrong"
+                               end
+                            end
+                            return answer
+                            ***"#;
+    assert_eq!(
+        Err(Error::TypeMismatch {
+            expected: "string",
+            found: "list"
+        }),
+        engine.execute(program)
+    );
+    assert_eq!(committed, engine);
+}
+
+#[test]
+fn functions() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            set name = "Alice Smith"
+                            local parts = split(tolower(name), " ")
+                            local email = concat(concat(parts.fst, "."), "example.com")
+                            local missing = split(name, "")
+                            local rec = { fst = "alice smith", snd = "" }
+                            if equal(split(tolower(name), "!"), rec) == "" then
+                               set same = notequal(parts.snd, "smith")
+                            end
+                            return { email = email, first = missing.fst, same = same }
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    let mut record = HashMap::new();
+    record.insert("email".to_string(), "alice.example.com".to_string());
+    record.insert("first".to_string(), "Alice Smith".to_string());
+    record.insert("same".to_string(), "0".to_string());
+    assert_eq!(
+        Entry {
+            status: RETURNING,
+            output: Some(Value::FieldVals(record))
+        },
+        *entries.last().unwrap()
+    );
+
+    // concatenation is truncated to the maximum string length
+    let long = "a".repeat(40000);
+    let program = format!(
+        r#"as principal admin password "admin_pass" do
+                            return concat("{}", "{}")
+                            ***"#,
+        long, long
+    );
+    match &engine.execute(&program).unwrap()[0].output {
+        Some(Value::Immediate(s)) => assert_eq!(65535, s.len()),
+        _ => panic!(),
+    }
+
+    // strings and records cannot be compared
+    let program = r#"as principal admin password "admin_pass" do
+                            return equal(name, split(name, " "))
+                            ***"#;
+    assert_eq!(
+        Err(Error::TypeMismatch {
+            expected: "string",
+            found: "record"
+        }),
+        engine.execute(program)
+    );
+}
+
+#[test]
+fn let_expressions() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            set names = []
+                            append to names with { first = "Ann", last = "Lee" }
+                            append to names with { first = "Bo", last = "Ray" }
+                            foreach n in names replacewith let f = tolower(n.first) in let l = tolower(n.last) in concat(concat(f, " "), l)
+                            local first = let n = "reused" in n
+                            local second = let n = "again" in n
+                            local after = let n = names in "ok"
+                            return { first = first, second = second, after = after }
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    let mut record = HashMap::new();
+    record.insert("first".to_string(), "reused".to_string());
+    record.insert("second".to_string(), "again".to_string());
+    record.insert("after".to_string(), "ok".to_string());
+    assert_eq!(
+        Entry {
+            status: RETURNING,
+            output: Some(Value::FieldVals(record))
+        },
+        *entries.last().unwrap()
+    );
+    assert_eq!(
+        Ok(&Value::List(vec![
+            Value::Immediate("ann lee".to_string()),
+            Value::Immediate("bo ray".to_string())
+        ])),
+        engine
+            .database()
+            .clone()
+            .get(&"admin".to_string(), &"names".to_string())
+    );
+
+    // a let cannot shadow a global, a local, a foreach element or an enclosing let
+    for shadowing in &[
+        "return let names = \"x\" in names",
+        "local n = \"x\"\nreturn let n = \"y\" in n",
+        "foreach n in names replacewith let n = \"y\" in n\nreturn \"\"",
+        "return let n = \"x\" in let n = \"y\" in n",
+    ] {
+        let program = format!(
+            "as principal admin password \"admin_pass\" do\n{}\n***",
+            shadowing
+        );
+        assert_eq!(
+            Err(Error::DuplicateLocal(
+                if shadowing.contains("names = ") {
+                    "names"
+                } else {
+                    "n"
+                }
+                .to_string()
+            )),
+            engine.execute(&program)
+        );
+    }
+
+    // the bound name is not visible outside the body
+    let program = r#"as principal admin password "admin_pass" do
+                            local x = let hidden = "a" in hidden
+                            return hidden
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::UnknownVariable(
+            "hidden".to_string()
+        ))),
+        engine.execute(program)
+    );
+}
+
+#[test]
+fn filter_each() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set records = []
+                            append to records with { name = "mike", date = "1-1-90" }
+                            append to records with { name = "dave", date = "1-1-85" }
+                            append to records with "note"
+                            append to records with { name = "mike", date = "2-2-92" }
+                            local names = records
+                            filtereach rec in records with defined rec.name
+                            filtereach rec in records with rec.name == "mike"
+                            foreach rec in names replacewith "x"
+                            filtereach rec in names with rec != "x"
+                            set delegation records admin read -> bob
+                            return names
+                            ***"#;
+    let statuses = |entries: &Vec<Entry>| {
+        entries
+            .iter()
+            .map(|entry| entry.status)
+            .collect::<Vec<Status>>()
+    };
+    let entries = engine.execute(program).unwrap();
+    assert_eq!(
+        vec![
+            CREATE_PRINCIPAL,
+            SET,
+            APPEND,
+            APPEND,
+            APPEND,
+            APPEND,
+            LOCAL,
+            FILTEREACH,
+            FILTEREACH,
+            FOREACH,
+            FILTEREACH,
+            SET_DELEGATION,
+            RETURNING
+        ],
+        statuses(&entries)
+    );
+    assert_eq!(Some(Value::List(vec![])), entries.last().unwrap().output);
+    let record = |date: &str| {
+        let mut record = HashMap::new();
+        record.insert("name".to_string(), "mike".to_string());
+        record.insert("date".to_string(), date.to_string());
+        Value::FieldVals(record)
+    };
+    assert_eq!(
+        Ok(&Value::List(vec![record("1-1-90"), record("2-2-92")])),
+        engine
+            .database()
+            .clone()
+            .get(&"admin".to_string(), &"records".to_string())
+    );
+
+    // filtering needs write permission as well as read, and rolls back on failure
+    let committed = engine.clone();
+    let program = r#"as principal bob password "bob" do
+                            filtereach rec in records with rec.date == "1-1-90"
+                            return records
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "records".to_string(),
+            right: Right::Write
+        })),
+        engine.execute(program)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            filtereach rec in records with rec.date == "1-1-90"
+                            filtereach rec in records with rec == "note"
+                            return records
+                            ***"#;
+    assert_eq!(
+        Err(Error::TypeMismatch {
+            expected: "string",
+            found: "record"
+        }),
+        engine.execute(program)
+    );
+    assert_eq!(committed, engine);
+}
+
+#[test]
+fn delete_variable() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "old"
+                            set delegation x admin read -> bob
+                            set delegation x admin write -> bob
+                            local y = "local"
+                            delete y
+                            local y = "again"
+                            return y
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    assert_eq!(DELETE_VARIABLE, entries[5].status);
+    assert_eq!(
+        Some(Value::Immediate("again".to_string())),
+        entries.last().unwrap().output
+    );
+
+    // bob may write x, so may delete it; the name is then free for a local
+    let program = r#"as principal bob password "bob" do
+                            delete x
+                            local x = "mine"
+                            return x
+                            ***"#;
+    assert_eq!(
+        vec![
+            Entry {
+                status: DELETE_VARIABLE,
+                output: None
+            },
+            Entry {
+                status: LOCAL,
+                output: None
+            },
+            Entry {
+                status: RETURNING,
+                output: Some(Value::Immediate("mine".to_string()))
+            }
+        ],
+        engine.execute(program).unwrap()
+    );
+    assert!(!engine.database().contains(&"x".to_string()));
+
+    // bob's rights went with the old x
+    let program = r#"as principal admin password "admin_pass" do
+                            set x = "new"
+                            return x
+                            ***"#;
+    engine.execute(program).unwrap();
+    let program = r#"as principal bob password "bob" do
+                            delete x
+                            return ""
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "x".to_string(),
+            right: Right::Write
+        })),
+        engine.execute(program)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            delete z
+                            return ""
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::UnknownVariable("z".to_string()))),
+        engine.execute(program)
+    );
+}
+
+#[test]
+fn principal_lifecycle() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal alice "alice"
+                            create principal bob "bob"
+                            set x = "secret"
+                            set delegation x admin read -> alice
+                            set delegation x alice read -> bob
+                            default delegator = alice
+                            disable principal alice
+                            return ""
+                            ***"#;
+    assert_eq!(
+        DISABLE_PRINCIPAL,
+        engine.execute(program).unwrap()[6].status
+    );
+    let as_alice = r#"as principal alice password "alice" do
+                            return x
+                            ***"#;
+    let as_bob = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::Disabled("alice".to_string()))),
+        engine.execute(as_alice)
+    );
+    // bob still reads x through alice
+    assert_eq!(RETURNING, engine.execute(as_bob).unwrap()[0].status);
+
+    let program = r#"as principal bob password "bob" do
+                            enable principal alice
+                            return ""
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::NotAdmin("bob".to_string()))),
+        engine.execute(program)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            enable principal alice
+                            return ""
+                            ***"#;
+    assert_eq!(ENABLE_PRINCIPAL, engine.execute(program).unwrap()[0].status);
+    assert_eq!(RETURNING, engine.execute(as_alice).unwrap()[0].status);
+
+    // deleting alice cuts bob off, and resets the default delegator
+    let program = r#"as principal admin password "admin_pass" do
+                            delete principal alice
+                            create principal carol "carol"
+                            return ""
+                            ***"#;
+    assert_eq!(DELETE_PRINCIPAL, engine.execute(program).unwrap()[0].status);
+    assert_eq!(
+        Err(Error::Database(DBError::UnknownPrincipal(
+            "alice".to_string()
+        ))),
+        engine.execute(as_alice)
+    );
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "x".to_string(),
+            right: Right::Read
+        })),
+        engine.execute(as_bob)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            delete principal anyone
+                            return ""
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::Builtin("anyone".to_string()))),
+        engine.execute(program)
+    );
+}
+
+#[test]
+fn groups() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal alice "alice"
+                            create principal bob "bob"
+                            set x = "secret"
+                            create group team owner alice
+                            set delegation x admin read -> team
+                            return ""
+                            ***"#;
+    assert_eq!(CREATE_GROUP, engine.execute(program).unwrap()[3].status);
+    let as_bob = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "x".to_string(),
+            right: Right::Read
+        })),
+        engine.execute(as_bob)
+    );
+
+    // the owner manages membership, and members read what the group reads
+    let program = r#"as principal bob password "bob" do
+                            add principal bob to group team
+                            return ""
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::NotOwner {
+            principal: "bob".to_string(),
+            group: "team".to_string()
+        })),
+        engine.execute(program)
+    );
+    let program = r#"as principal alice password "alice" do
+                            add principal bob to group team
+                            return ""
+                            ***"#;
+    assert_eq!(ADD_MEMBER, engine.execute(program).unwrap()[0].status);
+    assert_eq!(
+        Some(Value::Immediate("secret".to_string())),
+        engine.execute(as_bob).unwrap()[0].output
+    );
+    let program = r#"as principal alice password "alice" do
+                            remove principal bob from group team
+                            return ""
+                            ***"#;
+    assert_eq!(REMOVE_MEMBER, engine.execute(program).unwrap()[0].status);
+    assert!(engine.execute(as_bob).is_err());
+
+    // a group cannot log in
+    let program = r#"as principal team password "" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::BadPassword("team".to_string()))),
+        engine.execute(program)
+    );
+}
+
+#[test]
+fn limited_delegations() {
+    use bibifi_database::Clock;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let time = Arc::new(AtomicU64::new(0));
+    let clock = {
+        let time = time.clone();
+        Clock::new(move || time.load(Ordering::SeqCst))
+    };
+    let mut engine = Engine::with_clock(hash("admin_pass".to_string()), clock);
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "secret"
+                            set delegation x admin read -> bob for 3600 seconds
+                            set delegation x admin write -> bob for 1 use
+                            return ""
+                            ***"#;
+    assert_eq!(SET_DELEGATION, engine.execute(program).unwrap()[3].status);
+
+    // a failed program does not spend the single write
+    let write = |then: &str| {
+        format!(
+            "as principal bob password \"bob\" do\nset x = \"mine\"\n{}\n***",
+            then
+        )
+    };
+    assert!(engine.execute(&write("return missing")).is_err());
+    assert_eq!(SET, engine.execute(&write("return x")).unwrap()[0].status);
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "x".to_string(),
+            right: Right::Write
+        })),
+        engine.execute(&write("return x"))
+    );
+
+    // reading stops after an hour, and sweeping changes nothing observable
+    let read = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    time.store(3599, Ordering::SeqCst);
+    assert_eq!(
+        Some(Value::Immediate("mine".to_string())),
+        engine.execute(read).unwrap()[0].output
+    );
+    time.store(3600, Ordering::SeqCst);
+    let expired = engine.execute(read);
+    assert!(expired.is_err());
+    engine.sweep();
+    assert_eq!(expired, engine.execute(read));
+}
+
+#[test]
+fn limited_delegations_per_operation() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = { f = "one" }
+                            set delegation x admin read -> bob for 2 uses
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+
+    // however often a command reads x, it uses the delegation once
+    let program = r#"as principal bob password "bob" do
+                            local a = { g = x.f, h = x.f }
+                            return x
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+
+    // but each command relying on it uses it, so a single write is only one
+    let program = r#"as principal admin password "admin_pass" do
+                            set delegation x admin write -> bob for 1 use
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            set x = "mine"
+                            set x = "again"
+                            return ""
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+    let program = r#"as principal bob password "bob" do
+                            set x = "mine"
+                            return ""
+                            ***"#;
+    assert_eq!(SET, engine.execute(program).unwrap()[0].status);
+}
+
+#[test]
+fn field_delegations() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set y = { f1 = "one", f2 = "two" }
+                            set delegation y.f1 admin read -> bob
+                            return ""
+                            ***"#;
+    assert_eq!(SET_DELEGATION, engine.execute(program).unwrap()[2].status);
+
+    // bob may read y.f1, and test for y.f1, but not y.f2
+    let run = |engine: &mut Engine, body: &str| {
+        engine.execute(&format!(
+            "as principal bob password \"bob\" do\n{}\n***",
+            body
+        ))
+    };
+    assert_eq!(
+        Some(Value::Immediate("one".to_string())),
+        run(&mut engine, "return y.f1").unwrap()[0].output
+    );
+    assert_eq!(
+        IF_THEN,
+        run(&mut engine, "if defined y.f1 then\nend\nreturn \"\"").unwrap()[0].status
+    );
+    assert_eq!(
+        Err(Error::Database(DBError::MissingRight {
+            principal: "bob".to_string(),
+            variable: "y.f2".to_string(),
+            right: Right::Read
+        })),
+        run(&mut engine, "return y.f2")
+    );
+    assert!(run(&mut engine, "set y.f1 = \"uno\"\nreturn \"\"").is_err());
+
+    // reading all of y is denied by default, or projected onto f1
+    assert!(run(&mut engine, "return y").is_err());
+    engine.set_field_policy(FieldPolicy::Project);
+    let projected = vec![("f1".to_string(), "one".to_string())]
+        .into_iter()
+        .collect();
+    assert_eq!(
+        Some(Value::FieldVals(projected)),
+        run(&mut engine, "return y").unwrap()[0].output
+    );
+}
+
+#[test]
+fn sessions() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "secret"
+                            set delegation x admin read -> bob
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let login = r#"as principal bob password "bob" do
+                            login
+                            return x
+                            ***"#;
+    let entries = engine.execute(login).unwrap();
+    assert_eq!(LOGIN, entries[0].status);
+    let token = match &entries[0].output {
+        Some(Value::Immediate(token)) => token.clone(),
+        other => panic!("{:?}", other),
+    };
+
+    // the token stands in for the password until the password changes
+    let with_token = |body: &str| {
+        format!(
+            "as principal bob token \"{}\" do\n{}return x\n***",
+            token, body
+        )
+    };
+    assert!(engine.execute(&with_token("")).is_ok());
+    // a token cannot be used to get a fresh one
+    assert_eq!(
+        Err(Error::TokenLogin("bob".to_string())),
+        engine.execute(&with_token("login\n"))
+    );
+    assert_eq!(
+        DENIED,
+        Entry::from(
+            engine
+                .execute(&with_token("login for 60 seconds\n"))
+                .unwrap_err()
+        )
+        .status
+    );
+    assert_eq!(
+        Err(Error::Database(DBError::BadToken("admin".to_string()))),
+        engine.execute(&with_token("").replace("principal bob", "principal admin"))
+    );
+    assert!(engine
+        .execute(&with_token("change password bob \"new\"\n"))
+        .is_ok());
+    assert_eq!(
+        Err(Error::Database(DBError::BadToken("bob".to_string()))),
+        engine.execute(&with_token(""))
+    );
+}
+
+#[test]
+fn session_lifetime() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let login = |seconds: &str| {
+        format!(
+            "as principal admin password \"admin_pass\" do\nlogin for {} seconds\nreturn \"\"\n***",
+            seconds
+        )
+    };
+    assert_eq!(LOGIN, engine.execute(&login("3600")).unwrap()[0].status);
+    // a session may not outlast the default lifetime, however long it asks for
+    assert_eq!(
+        Err(Error::SessionTooLong(3601)),
+        engine.execute(&login("3601"))
+    );
+    let forever = engine.execute(&login(&u64::MAX.to_string()));
+    assert_eq!(Err(Error::SessionTooLong(u64::MAX)), forever);
+    assert_eq!(FAILED, Entry::from(forever.unwrap_err()).status);
+}
+
+#[test]
+fn password_policy() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    engine.set_password_policy("min_length=8,deny_name".parse().unwrap());
+    let create = |password: &str| {
+        format!(
+            "as principal admin password \"admin_pass\" do\ncreate principal bob \"{}\"\nreturn \"\"\n***",
+            password
+        )
+    };
+    assert_eq!(
+        Err(Error::Database(DBError::WeakPassword {
+            principal: "bob".to_string(),
+            weakness: Weakness::TooShort(8)
+        })),
+        engine.execute(&create("short"))
+    );
+    assert_eq!(
+        FAILED,
+        Entry::from(engine.execute(&create("BOB")).unwrap_err()).status
+    );
+    assert!(engine.execute(&create("long enough")).is_ok());
+}
+
+#[test]
+fn superusers() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "secret"
+                            grant superuser bob
+                            return ""
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    assert_eq!(GRANT_SUPERUSER, entries[2].status);
+    let program = r#"as principal bob password "bob" do
+                            create principal carol "carol"
+                            local y = x
+                            revoke superuser bob
+                            return y
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    assert_eq!(REVOKE_SUPERUSER, entries[2].status);
+    assert_eq!(
+        Some(Value::Immediate("secret".to_string())),
+        entries[3].output
+    );
+
+    // without the role bob may neither read x nor stop the server
+    let program = r#"as principal bob password "bob" do
+                            exit
+                            ***"#;
+    assert_eq!(
+        Err(Error::NotAdmin("bob".to_string())),
+        engine.execute(program)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            grant superuser bob
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            exit
+                            ***"#;
+    assert_eq!(EXITING, engine.execute(program).unwrap()[0].status);
+}
+
+#[test]
+fn denials() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "secret"
+                            set delegation x admin read -> bob
+                            set denial x read -> bob
+                            set denial all write -> anyone
+                            return denials(x)
+                            ***"#;
+    let entries = engine.execute(program).unwrap();
+    assert_eq!(SET_DENIAL, entries[3].status);
+    let record = |target: &str, right: &str, principal: &str| {
+        let mut map = HashMap::new();
+        map.insert("target".to_string(), target.to_string());
+        map.insert("right".to_string(), right.to_string());
+        map.insert("principal".to_string(), principal.to_string());
+        Value::FieldVals(map)
+    };
+    assert_eq!(
+        Some(Value::List(vec![
+            record("x", "read", "bob"),
+            record("all", "write", "anyone")
+        ])),
+        entries[5].output
+    );
+
+    let read = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(read).unwrap_err()).status
+    );
+    let program = r#"as principal bob password "bob" do
+                            return denials(all)
+                            ***"#;
+    assert_eq!(
+        Err(Error::Database(DBError::NotAdmin("bob".to_string()))),
+        engine.execute(program)
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            delete denial x read -> bob
+                            return ""
+                            ***"#;
+    assert_eq!(DELETE_DENIAL, engine.execute(program).unwrap()[0].status);
+    assert!(engine.execute(read).is_ok());
+}
+
+#[test]
+fn pattern_delegations() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set delegation reports_* admin read -> bob
+                            set delegation all admin write -> bob
+                            set reports_q1 = "first"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            return reports_q1
+                            ***"#;
+    assert_eq!(
+        Some(Value::Immediate("first".to_string())),
+        engine.execute(program).unwrap()[0].output
+    );
+
+    // all only covered the variables which existed at the time
+    let program = r#"as principal bob password "bob" do
+                            set reports_q1 = "changed"
+                            return ""
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+}
+
+#[test]
+fn version_history() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    // a program which is rolled back is not numbered
+    let program = r#"as principal bob password "bob" do
+                            set x = "lost"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_err());
+    let program = r#"as principal admin password "admin_pass" do
+                            set x = "two"
+                            return x@1
+                            ***"#;
+    assert_eq!(
+        Some(Value::Immediate("one".to_string())),
+        engine.execute(program).unwrap()[1].output
+    );
+
+    let program = r#"as principal admin password "admin_pass" do
+                            return history(x)
+                            ***"#;
+    let version = |sequence: &str| {
+        let mut record = HashMap::new();
+        record.insert("sequence".to_string(), sequence.to_string());
+        record.insert("principal".to_string(), "admin".to_string());
+        Value::FieldVals(record)
+    };
+    assert_eq!(
+        Some(Value::List(vec![version("1"), version("2")])),
+        engine.execute(program).unwrap()[0].output
+    );
+
+    // old versions need the read right on the variable as it is now, and x@n reads the latest
+    // version as of program n
+    let program = r#"as principal bob password "bob" do
+                            return x@1
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            return x@9
+                            ***"#;
+    assert_eq!(
+        Some(Value::Immediate("two".to_string())),
+        engine.execute(program).unwrap()[0].output
+    );
+}
+
+#[test]
+fn restore() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set shared = []
+                            append to shared with "a"
+                            append to shared with "b"
+                            set delegation shared admin read -> bob
+                            set delegation shared admin write -> bob
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            foreach item in shared replacewith ""
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal admin password "admin_pass" do
+                            delete delegation shared admin read -> bob
+                            create principal carol "carol"
+                            set other = "x"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+
+    let program = r#"as principal bob password "bob" do
+                            restore to 1
+                            return ""
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            restore to 99
+                            return ""
+                            ***"#;
+    assert_eq!(
+        FAILED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+
+    // principals, delegations and variables all go back to how they were after the first program
+    let program = r#"as principal admin password "admin_pass" do
+                            restore to 1
+                            return shared
+                            ***"#;
+    assert_eq!(
+        vec![
+            Entry {
+                status: RESTORE,
+                output: None
+            },
+            Entry {
+                status: RETURNING,
+                output: Some(Value::List(vec![
+                    Value::Immediate("a".to_string()),
+                    Value::Immediate("b".to_string())
+                ]))
+            }
+        ],
+        engine.execute(program).unwrap()
+    );
+    let program = r#"as principal bob password "bob" do
+                            return shared
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal carol password "carol" do
+                            return ""
+                            ***"#;
+    assert_eq!(
+        FAILED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+    let program = r#"as principal admin password "admin_pass" do
+                            return other
+                            ***"#;
+    assert_eq!(
+        FAILED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+
+    // the restore can itself be undone, until its checkpoint is dropped
+    let program = r#"as principal admin password "admin_pass" do
+                            restore to 3
+                            return other
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    engine.set_checkpoint_limit(2);
+    let program = r#"as principal admin password "admin_pass" do
+                            restore to 3
+                            return ""
+                            ***"#;
+    assert_eq!(
+        FAILED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+}
+
+#[test]
+fn metadata() {
+    use bibifi_database::Clock;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let time = Arc::new(AtomicU64::new(100));
+    let clock = {
+        let time = time.clone();
+        Clock::new(move || time.load(Ordering::SeqCst))
+    };
+    let mut engine = Engine::with_clock(hash("admin_pass".to_string()), clock);
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "hello"
+                            set delegation x admin write -> bob
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    time.store(250, Ordering::SeqCst);
+    let program = r#"as principal bob password "bob" do
+                            set x = { greeting = "hi" }
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+
+    let program = r#"as principal admin password "admin_pass" do
+                            return metadata(x)
+                            ***"#;
+    let mut record = HashMap::new();
+    for (field, value) in &[
+        ("creator", "admin"),
+        ("writer", "bob"),
+        ("created", "100"),
+        ("modified", "250"),
+        ("size", "10"),
+    ] {
+        record.insert(field.to_string(), value.to_string());
+    }
+    assert_eq!(
+        Some(Value::FieldVals(record)),
+        engine.execute(program).unwrap()[0].output
+    );
+
+    // bob may write x but not read it
+    let program = r#"as principal bob password "bob" do
+                            return metadata(x)
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+}
+
+#[tokio::test]
+async fn watch() {
+    let (runtime, receiver) = BiBiFi::new();
+    tokio::spawn(BiBiFi::run(hash("admin_pass".to_string()), receiver));
+    let submit = |program: &str| {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (events, watched) = tokio::sync::mpsc::unbounded_channel();
+        let runtime = runtime.clone();
+        let program = program.to_string();
+        async move {
+            runtime.watch(program, sender, events).await.unwrap();
+            (receiver.recv().await.unwrap(), watched)
+        }
+    };
+    submit(
+        r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            set y = "one"
+                            set delegation x admin read -> bob
+                            return ""
+                            ***"#,
+    )
+    .await;
+
+    // watching needs the read right
+    let (entries, mut denied) = submit(
+        r#"as principal bob password "bob" do
+                            watch y
+                            return ""
+                            ***"#,
+    )
+    .await;
+    assert_eq!(DENIED, entries[0].status);
+    assert_eq!(None, denied.recv().await);
+
+    let (entries, mut watched) = submit(
+        r#"as principal bob password "bob" do
+                            watch x
+                            return ""
+                            ***"#,
+    )
+    .await;
+    assert_eq!(
+        Entry {
+            status: WATCH,
+            output: Some(Value::Immediate("x".to_string()))
+        },
+        entries[0]
+    );
+
+    // only committed changes to x are sent
+    submit(
+        r#"as principal admin password "admin_pass" do
+                            set x = "lost"
+                            set z = x.f
+                            return ""
+                            ***"#,
+    )
+    .await;
+    submit(
+        r#"as principal admin password "admin_pass" do
+                            set y = "two"
+                            return ""
+                            ***"#,
+    )
+    .await;
+    submit(
+        r#"as principal admin password "admin_pass" do
+                            set x = "two"
+                            return ""
+                            ***"#,
+    )
+    .await;
+    assert_eq!(
+        Some(Event {
+            status: CHANGED,
+            variable: "x".to_string(),
+            output: Some(Value::Immediate("two".to_string()))
+        }),
+        watched.recv().await
+    );
+
+    // once bob may no longer read x, the feed stops
+    submit(
+        r#"as principal admin password "admin_pass" do
+                            delete delegation x admin read -> bob
+                            set x = "three"
+                            return ""
+                            ***"#,
+    )
+    .await;
+    assert_eq!(None, watched.recv().await);
+}
+
+#[test]
+fn watch_deleted() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            set x = "one"
+                            set y = "one"
+                            watch x
+                            watch y
+                            return ""
+                            ***"#;
+    let (_, subscription) = engine.execute_watching(program).unwrap();
+    let mut subscription = subscription.unwrap();
+    assert_eq!(Vec::<Event>::new(), subscription.events(engine.database()));
+
+    let program = r#"as principal admin password "admin_pass" do
+                            delete x
+                            set y = "two"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    assert_eq!(
+        vec![
+            Event {
+                status: DELETED,
+                variable: "x".to_string(),
+                output: None
+            },
+            Event {
+                status: CHANGED,
+                variable: "y".to_string(),
+                output: Some(Value::Immediate("two".to_string()))
+            }
+        ],
+        subscription.events(engine.database())
+    );
+    assert!(!subscription.is_empty());
+
+    // a program without watch commands subscribes to nothing
+    let program = r#"as principal admin password "admin_pass" do
+                            return y
+                            ***"#;
+    assert_eq!(None, engine.execute_watching(program).unwrap().1);
+}
+
+#[test]
+fn watch_partly() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            set y = "one"
+                            set delegation y admin read -> bob
+                            watch x
+                            watch y
+                            delete x
+                            return ""
+                            ***"#;
+    // x is deleted later in the program, but y is still watched
+    let mut admin = engine.execute_watching(program).unwrap().1.unwrap();
+
+    // likewise when watching uses up the right to read a variable
+    let program = r#"as principal admin password "admin_pass" do
+                            set x = "one"
+                            set delegation x admin read -> bob for 1 use
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            watch x
+                            watch y
+                            return ""
+                            ***"#;
+    let mut bob = engine.execute_watching(program).unwrap().1.unwrap();
+
+    let program = r#"as principal admin password "admin_pass" do
+                            set x = "two"
+                            set y = "two"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let changed = vec![Event {
+        status: CHANGED,
+        variable: "y".to_string(),
+        output: Some(Value::Immediate("two".to_string())),
+    }];
+    assert_eq!(changed, admin.events(engine.database()));
+    assert_eq!(changed, bob.events(engine.database()));
+}
+
+#[test]
+fn watch_deleted_principal() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            set delegation x admin read -> bob
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            watch x
+                            return ""
+                            ***"#;
+    let (_, subscription) = engine.execute_watching(program).unwrap();
+    let mut subscription = subscription.unwrap();
+
+    // once bob is gone, the subscription is dropped rather than checked
+    let program = r#"as principal admin password "admin_pass" do
+                            delete principal bob
+                            set x = "two"
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    assert_eq!(Vec::<Event>::new(), subscription.events(engine.database()));
+    assert!(subscription.is_empty());
+}
+
+#[test]
+fn watch_limited() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            set delegation x admin read -> bob for 2 uses
+                            return ""
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    let program = r#"as principal bob password "bob" do
+                            watch x
+                            return ""
+                            ***"#;
+    let (_, subscription) = engine.execute_watching(program).unwrap();
+    let mut subscription = subscription.unwrap();
+
+    // telling bob of commits does not use up his delegation
+    for n in 0..3 {
+        let program = format!(
+            r#"as principal admin password "admin_pass" do
+                            set y = "{}"
+                            return ""
+                            ***"#,
+            n
+        );
+        assert!(engine.execute(&program).is_ok());
+        assert_eq!(Vec::<Event>::new(), subscription.events(engine.database()));
+    }
+    let program = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert_eq!(RETURNING, engine.execute(program).unwrap()[0].status);
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute(program).unwrap_err()).status
+    );
+}
+
+#[test]
+fn admin_uids() {
+    let mut engine = Engine::new(hash("admin_pass".to_string()));
+    let program = r#"as principal admin password "admin_pass" do
+                            create principal bob "bob"
+                            set x = "one"
+                            set delegation x admin read -> bob
+                            return x
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+    engine.set_admin_uids(vec![1000]);
+
+    // admin needs both the password and a local process of an allowed user
+    let program = r#"as principal admin password "admin_pass" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        Err(Error::NotLocal("admin".to_string())),
+        engine.execute(program)
+    );
+    assert_eq!(
+        Err(Error::NotLocal("admin".to_string())),
+        engine
+            .execute_from(program, Some(0))
+            .map(|(messages, _)| messages)
+    );
+    assert!(engine.execute_from(program, Some(1000)).is_ok());
+    let program = r#"as principal admin password "wrong" do
+                            return x
+                            ***"#;
+    assert_eq!(
+        DENIED,
+        Entry::from(engine.execute_from(program, Some(1000)).unwrap_err()).status
+    );
+
+    // other principals are not restricted
+    let program = r#"as principal bob password "bob" do
+                            return x
+                            ***"#;
+    assert!(engine.execute(program).is_ok());
+}
@@ -0,0 +1,98 @@
+use crate::status::{Entry, Status};
+use bibifi_database::{Database, Error as DBError, Value};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A change to a watched variable, sent once the program which made it has committed.
+#[derive(Clone, Serialize, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// CHANGED, or DELETED once the variable is gone.
+    pub status: Status,
+    pub variable: String,
+    /// The new value, unless the variable was deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+}
+
+/// The variables a connection watches, and the principal it watches them as.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Subscription {
+    principal: String,
+    /// The commit sequence number of the last version of each variable the connection was told of,
+    /// or subscribed at.
+    variables: BTreeMap<String, u64>,
+}
+
+impl Subscription {
+    /// The subscription asked for by the `watch` commands among a committed program's output, if
+    /// there were any. A variable the program deleted after watching it, or can no longer read
+    /// once it has committed, is left out, but the others are still watched.
+    pub(crate) fn from_entries(
+        principal: &str,
+        entries: &[Entry],
+        database: &Database,
+    ) -> Option<Subscription> {
+        let mut subscription = Subscription {
+            principal: principal.to_string(),
+            variables: BTreeMap::new(),
+        };
+        for entry in entries.iter().filter(|entry| entry.status == Status::WATCH) {
+            if let Some(Value::Immediate(variable)) = &entry.output {
+                let history = match database.peek_history(&subscription.principal, variable) {
+                    Ok(history) => history,
+                    Err(_) => continue,
+                };
+                let sequence = history.last().map_or(0, |version| version.sequence);
+                subscription.variables.insert(variable.clone(), sequence);
+            }
+        }
+        if subscription.is_empty() {
+            None
+        } else {
+            Some(subscription)
+        }
+    }
+
+    /// The events for the variables which have changed since the subscriber was last told of them.
+    /// Variables which have been deleted, or which the principal may no longer read, stop being
+    /// watched, and nothing is watched once the principal has been deleted or restored away.
+    pub fn events(&mut self, database: &Database) -> Vec<Event> {
+        if !database.contains_principal(&self.principal) {
+            self.variables.clear();
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        let principal = &self.principal;
+        self.variables.retain(
+            |variable, seen| match database.peek_history(principal, variable) {
+                Ok(history) => {
+                    let latest = history.last().unwrap();
+                    if latest.sequence != *seen {
+                        *seen = latest.sequence;
+                        events.push(Event {
+                            status: Status::CHANGED,
+                            variable: variable.clone(),
+                            output: Some(latest.value.clone()),
+                        });
+                    }
+                    true
+                }
+                Err(DBError::UnknownVariable(_)) => {
+                    events.push(Event {
+                        status: Status::DELETED,
+                        variable: variable.clone(),
+                        output: None,
+                    });
+                    false
+                }
+                Err(_) => false,
+            },
+        );
+        events
+    }
+
+    /// Whether no variable is watched any more.
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+}
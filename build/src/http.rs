@@ -0,0 +1,192 @@
+//! An HTTP front-end to the same runtime as the raw protocol. A program is submitted as the body
+//! of a `POST /`, and its entries come back as a JSON array, with the HTTP status taken from the
+//! outcome: 403 if the program was denied, 400 if it failed, and 200 otherwise. Each connection
+//! carries a single request. `watch` is accepted but sends no events, as the response ends with
+//! the program's output. A client which is too slow to send its request is answered 408.
+use crate::shutdown::Shutdown;
+use bibifi_runtime::status::{Entry, Status};
+use bibifi_runtime::BiBiFi;
+use bibifi_util::log;
+use bibifi_util::{debug, info};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// The most bytes a program may have, as on the raw protocol.
+const MAX_PROGRAM: usize = 1000000;
+
+/// The most bytes the request line and headers may have together.
+const MAX_HEAD: u64 = 16384;
+
+/// How long a client may take to send the request line and headers, and then again the body.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A response which is not a program's output.
+struct Refusal {
+    code: u16,
+    reason: &'static str,
+}
+
+const BAD_REQUEST: Refusal = Refusal {
+    code: 400,
+    reason: "Bad Request",
+};
+
+const REQUEST_TIMEOUT: Refusal = Refusal {
+    code: 408,
+    reason: "Request Timeout",
+};
+
+/// Accepts connections on `listener` until it fails or the server shuts down, running each
+/// request's program on `runtime`.
+pub async fn serve(mut listener: TcpListener, runtime: BiBiFi, mut shutdown: Shutdown) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let connection = crate::connection_id();
+        info!("connection accepted", connection = connection, peer = peer);
+        let runtime = runtime.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { handle(stream, connection, runtime, shutdown).await });
+    }
+}
+
+async fn handle(mut stream: TcpStream, connection: u64, runtime: BiBiFi, shutdown: Shutdown) {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let outcome = match read_program(&mut reader).await {
+        Ok(program) => {
+            debug!(
+                "program received",
+                connection = connection,
+                program = log::digest(&program),
+                bytes = program.len()
+            );
+            run(&runtime, program).await
+        }
+        Err(refusal) => Err(refusal),
+    };
+    let (code, reason, body, exiting) = match outcome {
+        Ok(entries) => {
+            let (code, reason) = match entries.last().map(|entry| entry.status) {
+                Some(Status::DENIED) => (403, "Forbidden"),
+                Some(Status::FAILED) => (400, "Bad Request"),
+                _ => (200, "OK"),
+            };
+            let exiting = entries.iter().any(|entry| entry.status == Status::EXITING);
+            (
+                code,
+                reason,
+                serde_json::to_string(&entries).unwrap(),
+                exiting,
+            )
+        }
+        Err(refusal) => (
+            refusal.code,
+            refusal.reason,
+            format!("{{\"error\":\"{}\"}}", refusal.reason),
+            false,
+        ),
+    };
+    debug!("request answered", connection = connection, code = code);
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    );
+    if writer.write_all(response.as_bytes()).await.is_ok() {
+        writer.flush().await.unwrap_or(());
+    }
+    if exiting {
+        shutdown.trigger();
+    }
+}
+
+/// Reads a request, returning its body if it is a `POST /` with a body of a reasonable length.
+async fn read_program<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String, Refusal> {
+    let mut head = reader.take(MAX_HEAD);
+    let (method, path, length) = timeout(READ_TIMEOUT, read_head(&mut head))
+        .await
+        .map_err(|_| REQUEST_TIMEOUT)??;
+
+    if path != "/" {
+        return Err(Refusal {
+            code: 404,
+            reason: "Not Found",
+        });
+    }
+    if method != "POST" {
+        return Err(Refusal {
+            code: 405,
+            reason: "Method Not Allowed",
+        });
+    }
+    let length = length.ok_or(Refusal {
+        code: 411,
+        reason: "Length Required",
+    })?;
+    if length > MAX_PROGRAM {
+        return Err(Refusal {
+            code: 413,
+            reason: "Payload Too Large",
+        });
+    }
+    let mut body = vec![0; length];
+    timeout(READ_TIMEOUT, head.into_inner().read_exact(&mut body))
+        .await
+        .map_err(|_| REQUEST_TIMEOUT)?
+        .map_err(|_| BAD_REQUEST)?;
+    Ok(String::from_utf8_lossy(&body).to_string())
+}
+
+/// Reads the request line and headers, returning the method, the path and any content length.
+async fn read_head<R: AsyncBufRead + Unpin>(
+    head: &mut R,
+) -> Result<(String, String, Option<usize>), Refusal> {
+    let mut line = String::new();
+    head.read_line(&mut line).await.map_err(|_| BAD_REQUEST)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = match (words.next(), words.next(), words.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => return Err(BAD_REQUEST),
+    };
+    let mut length = None;
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await.map_err(|_| BAD_REQUEST)? == 0 {
+            return Err(BAD_REQUEST);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or(BAD_REQUEST)?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| BAD_REQUEST)?);
+        }
+    }
+    Ok((method, path, length))
+}
+
+/// Submits the program, and waits for its entries.
+async fn run(runtime: &BiBiFi, program: String) -> Result<Vec<Entry>, Refusal> {
+    let unavailable = Refusal {
+        code: 503,
+        reason: "Service Unavailable",
+    };
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    if runtime.submit(program, sender).await.is_err() {
+        return Err(unavailable);
+    }
+    receiver.recv().await.ok_or(unavailable)
+}
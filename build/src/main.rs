@@ -1,19 +1,58 @@
-#[forbid(unused_must_use)]
+#![forbid(unused_must_use)]
 //This code was modified from code posted by Reddit user u/nsossonko
 //at https://www.reddit.com/r/rust/comments/e82v07/my_introduction_to_tokio_streaming/
-use bibifi_runtime::status::Status::EXITING;
-use bibifi_runtime::BiBiFi;
-use futures::{StreamExt as FStreamExt, TryFutureExt};
+use bibifi_runtime::status::Status::{EXITING, WATCH};
+use bibifi_runtime::{BiBiFi, Engine, FieldPolicy, HistoryPolicy, PasswordPolicy};
+use bibifi_util::log::{self, Format, Level};
+use bibifi_util::{debug, info, trace, warn};
 use regex::Regex;
-use signal_hook::{iterator::Signals, SIGTERM};
+use shutdown::Shutdown;
 use std::env;
-use tokio::io::AsyncReadExt;
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tokio::net::TcpListener;
-use tokio::stream::StreamExt as TStreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+
+mod http;
+mod shutdown;
+#[cfg(test)]
+mod tests;
+
+/// The number given to the last connection accepted, on any listener.
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// A number identifying a new connection in the log.
+fn connection_id() -> u64 {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // events of this level and above are logged, as lines of text unless JSON is asked for
+    let level = match env::var("BIBIFI_LOG").as_deref() {
+        Err(_) => Some(Level::Info),
+        Ok("off") => None,
+        Ok(level) => match level.parse::<Level>() {
+            Ok(level) => Some(level),
+            Err(_) => std::process::exit(255),
+        },
+    };
+    let format = match env::var("BIBIFI_LOG_FORMAT") {
+        Ok(format) => match format.parse::<Format>() {
+            Ok(format) => format,
+            Err(_) => std::process::exit(255),
+        },
+        Err(_) => Format::Human,
+    };
+    if let Some(level) = level {
+        log::init(level, format);
+    }
+
     let mut args = env::args().skip(1);
     let port = args.next();
     if port.is_none() {
@@ -50,87 +89,331 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let (runtime, receiver) = bibifi_runtime::BiBiFi::new();
-    let mut socket = TcpListener::bind(&addr).await.unwrap();
-    println!("Listening on: {}", addr);
-
-    let signals = Signals::new(&[SIGTERM])?;
-    std::thread::spawn(move || {
-        // sighandler
-        for _ in signals.forever() {
-            std::process::exit(0);
+    // TCP can be turned off when only local tools connect, over the Unix socket
+    let socket = if env::var("BIBIFI_TCP").as_deref() == Ok("off") {
+        if env::var("BIBIFI_SOCKET").is_err() {
+            std::process::exit(255);
+        }
+        None
+    } else {
+        let socket = TcpListener::bind(&addr).await.unwrap();
+        info!("listening", protocol = "tcp", address = addr);
+        Some(socket)
+    };
+
+    let (shutdown, drained) = Shutdown::new();
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut sighandler = shutdown.clone();
+    tokio::spawn(async move {
+        // the handler lets go of its handle once shutdown starts, so the server can drain
+        tokio::select! {
+            _ = terminate.recv() => sighandler.trigger(),
+            _ = sighandler.triggered() => (),
+        }
+    });
+
+    let mut engine = Engine::new(admin_hash);
+    // whether principals with read permission on only some fields of a record see those fields
+    if env::var("BIBIFI_FIELD_POLICY").as_deref() == Ok("project") {
+        engine.set_field_policy(FieldPolicy::Project);
+    }
+    if let Ok(rules) = env::var("BIBIFI_PASSWORD_POLICY") {
+        match rules.parse::<PasswordPolicy>() {
+            Ok(policy) => engine.set_password_policy(policy),
+            Err(_) => std::process::exit(255),
+        }
+    }
+    if let Ok(bounds) = env::var("BIBIFI_HISTORY") {
+        match bounds.parse::<HistoryPolicy>() {
+            Ok(policy) => engine.set_history_policy(policy),
+            Err(_) => std::process::exit(255),
+        }
+    }
+    if let Ok(limit) = env::var("BIBIFI_CHECKPOINTS") {
+        match limit.parse::<usize>() {
+            Ok(limit) => engine.set_checkpoint_limit(limit),
+            Err(_) => std::process::exit(255),
+        }
+    }
+    // programs running as a superuser must come over the Unix socket from one of these users
+    if let Ok(uids) = env::var("BIBIFI_ADMIN_UIDS") {
+        match uids
+            .split(',')
+            .map(|uid| uid.trim().parse::<u32>())
+            .collect()
+        {
+            Ok(uids) => engine.set_admin_uids(uids),
+            Err(_) => std::process::exit(255),
         }
+    }
+    let engine = tokio::spawn(async move { BiBiFi::run_with(engine, receiver).await });
+    // local tools can connect over a Unix socket, only accessible to its owner unless a mode is given
+    let local = env::var("BIBIFI_SOCKET").ok();
+    if let Some(path) = &local {
+        let mode = match env::var("BIBIFI_SOCKET_MODE") {
+            Ok(mode) => match u32::from_str_radix(&mode, 8) {
+                Ok(mode) if mode <= 0o777 => mode,
+                _ => std::process::exit(255),
+            },
+            Err(_) => 0o600,
+        };
+        let listener = match bind_local(Path::new(path), mode) {
+            Ok(listener) => listener,
+            Err(_) => std::process::exit(255),
+        };
+        info!(
+            "listening",
+            protocol = "unix",
+            path = path,
+            mode = format!("{:o}", mode)
+        );
+        tokio::spawn(serve_local(listener, runtime.clone(), shutdown.clone()));
+    }
+    // the HTTP front-end is only served if a port is given for it
+    if let Ok(port) = env::var("BIBIFI_HTTP_PORT") {
+        let addr = match port.parse::<u16>() {
+            Ok(port) => format!("{}:{}", "0.0.0.0", port),
+            Err(_) => std::process::exit(255),
+        };
+        let listener = TcpListener::bind(&addr).await?;
+        info!("listening", protocol = "http", address = addr);
+        tokio::spawn(http::serve(listener, runtime.clone(), shutdown.clone()));
+    }
+    if let Some(socket) = socket {
+        tokio::spawn(serve(socket, runtime.clone(), shutdown.clone()));
+    }
+
+    let mut waiting = shutdown;
+    waiting.triggered().await;
+    info!("shutting down");
+    // the listeners have stopped accepting, so local tools should no longer find the socket
+    if let Some(path) = local {
+        fs::remove_file(path).unwrap_or(());
+    }
+    drop(waiting);
+    drop(runtime);
+    // the runtime stops once every connection has gone, and with them the last handles on it
+    let finished = async move {
+        drained.wait().await;
+        engine.await
+    };
+    if tokio::time::timeout(shutdown::DEADLINE, finished)
+        .await
+        .is_err()
+    {
+        warn!(
+            "programs still running at the deadline",
+            deadline_s = shutdown::DEADLINE.as_secs()
+        );
+    }
+    Ok(())
+}
+
+/// Binds a Unix socket at `path` with the given mode. The socket is bound inside a directory only
+/// this user may enter, and is moved into place once its mode is set, so no one else can connect
+/// to it in between. A socket left behind by an earlier run is replaced, but anything else at
+/// `path` is left alone and the socket is not bound.
+fn bind_local(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+        Err(_) => (),
+    }
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("bibifi");
+    let staging = path.with_file_name(format!(".{}.{}", name, std::process::id()));
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
     });
+    fs::remove_dir_all(&staging).unwrap_or(());
+    bound
+}
 
-    tokio::spawn(async move { BiBiFi::run(admin_hash, receiver).await });
+/// Accepts connections over TCP until the listener fails or the server shuts down.
+async fn serve(mut socket: TcpListener, runtime: BiBiFi, mut shutdown: Shutdown) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = socket.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let connection = connection_id();
+        info!("connection accepted", connection = connection, peer = peer);
+        let runtime = runtime.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { handle(stream, connection, runtime, None, shutdown).await });
+    }
+}
 
-    while let Ok((mut stream, peer)) = socket.accept().await {
-        println!("Incoming connection from: {}", peer.to_string());
+/// Accepts connections over the Unix socket until it fails or the server shuts down, passing on
+/// the user id of each peer.
+async fn serve_local(mut socket: UnixListener, runtime: BiBiFi, mut shutdown: Shutdown) {
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = socket.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let connection = connection_id();
+        let uid = match stream.peer_cred() {
+            Ok(credentials) => credentials.uid,
+            Err(e) => {
+                warn!(
+                    "peer credentials unreadable",
+                    connection = connection,
+                    error = e
+                );
+                continue;
+            }
+        };
+        info!("connection accepted", connection = connection, uid = uid);
         let runtime = runtime.clone();
-        tokio::spawn(async move {
-            let (reader, writer) = stream.split();
-
-            let mut buf_reader = BufReader::new(reader).take(1000000u64);
-            let mut buf_writer = BufWriter::new(writer);
-            let mut buf = Vec::with_capacity(1000000usize);
-            let mut ast_count = 0u8;
-
-            buf_reader.set_limit(1000000u64);
-
-            while ast_count < 3 {
-                match buf_reader.read_until(b'*', &mut buf).await {
-                    Ok(n) => {
-                        if n == 0 {
-                            println!("EOF received");
-                            return;
-                        }
-
-                        // Create a String out of the u8 buffer of characters
-                        if ((ast_count == 0) && (n > 1)) || ((ast_count > 0) && (n == 1)) {
-                            ast_count = ast_count + 1;
-                        } else {
-                            ast_count = 0;
-                        }
-                    }
-                    Err(e) => {
-                        println!("Error receiving message: {}", e);
-                        return;
-                    }
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { handle(stream, connection, runtime, Some(uid), shutdown).await });
+    }
+}
+
+/// Reads one program from the stream, and writes back its output, then the events for the
+/// variables it watches if there are any, until the server shuts down. A program which exits
+/// shuts the server down once its output is flushed, and a connection which has not started
+/// sending a program by then is closed.
+async fn handle<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    connection: u64,
+    runtime: BiBiFi,
+    uid: Option<u32>,
+    mut shutdown: Shutdown,
+) {
+    let (reader, writer) = tokio::io::split(stream);
+
+    let mut buf_reader = BufReader::new(reader).take(1000000u64);
+    let mut buf_writer = BufWriter::new(writer);
+    let mut buf = Vec::with_capacity(1000000usize);
+    let mut ast_count = 0u8;
+
+    buf_reader.set_limit(1000000u64);
+
+    // where the read in progress started, as one interrupted by shutdown is carried on
+    let mut start = 0;
+    while ast_count < 3 {
+        // a client which has sent nothing yet is not waited for once the server shuts down
+        let read = if buf.is_empty() {
+            tokio::select! {
+                read = buf_reader.read_until(b'*', &mut buf) => Some(read),
+                _ = shutdown.triggered() => None,
+            }
+        } else {
+            Some(buf_reader.read_until(b'*', &mut buf).await)
+        };
+        let read = match read {
+            Some(read) => read,
+            None if buf.is_empty() => {
+                debug!("connection idle at shutdown", connection = connection);
+                return;
+            }
+            None => continue,
+        };
+        let n = buf.len() - start;
+        start = buf.len();
+        match read {
+            Ok(_) => {
+                if n == 0 {
+                    debug!(
+                        "connection closed before a program",
+                        connection = connection
+                    );
+                    return;
+                }
+
+                // Create a String out of the u8 buffer of characters
+                if ((ast_count == 0) && (n > 1)) || ((ast_count > 0) && (n == 1)) {
+                    ast_count += 1;
+                } else {
+                    ast_count = 0;
                 }
             }
+            Err(e) => {
+                warn!("program not received", connection = connection, error = e);
+                return;
+            }
+        }
+    }
 
-            let buf_string = String::from_utf8_lossy(&buf);
+    let buf_string = String::from_utf8_lossy(&buf);
 
-            let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (events, mut watched) = tokio::sync::mpsc::unbounded_channel();
 
-            if let Some(entries) = runtime
-                .submit(buf_string.to_string(), sender)
-                .and_then(
-                    |_| async move { Ok(tokio::stream::StreamExt::next(&mut receiver).await) },
-                )
+    let program = buf_string.to_string();
+    let digest = log::digest(&program);
+    debug!(
+        "program received",
+        connection = connection,
+        program = digest,
+        bytes = program.len()
+    );
+    match uid {
+        Some(uid) => runtime.watch_from(program, sender, events, uid).await,
+        None => runtime.watch(program, sender, events).await,
+    }
+    .unwrap();
+    if let Some(entries) = tokio::stream::StreamExt::next(&mut receiver).await {
+        let watching = entries.iter().any(|entry| entry.status == WATCH);
+        let exiting = entries.iter().any(|entry| entry.status == EXITING);
+        if let Some(last) = entries.last() {
+            debug!(
+                "program answered",
+                connection = connection,
+                program = digest,
+                status = last.status
+            );
+        }
+        for entry in entries {
+            if buf_writer
+                .write_all(format!("{}\n", serde_json::to_string(&entry).unwrap()).as_bytes())
                 .await
-                .unwrap()
+                .is_err()
             {
-                for entry in entries {
-                    match buf_writer
-                        .write_all(
-                            format!("{}\n", serde_json::to_string(&entry).unwrap()).as_bytes(),
-                        )
-                        .await
-                    {
-                        Ok(_) => {
-                            if entry.status == EXITING {
-                                std::process::exit(0)
-                            }
-                        }
-                        Err(_) => break, // stream closed
-                    }
+                break; // stream closed
+            }
+        }
+        if exiting {
+            buf_writer.flush().await.unwrap_or(());
+            shutdown.trigger();
+        } else if watching {
+            // stay open, passing on changes until nothing is watched, the client leaves or the
+            // server shuts down
+            buf_writer.flush().await.unwrap_or(());
+            while let Some(event) = tokio::select! {
+                event = tokio::stream::StreamExt::next(&mut watched) => event,
+                _ = shutdown.triggered() => None,
+            } {
+                trace!(
+                    "event sent",
+                    connection = connection,
+                    variable = event.variable,
+                    status = event.status
+                );
+                let line = format!("{}\n", serde_json::to_string(&event).unwrap());
+                if buf_writer.write_all(line.as_bytes()).await.is_err()
+                    || buf_writer.flush().await.is_err()
+                {
+                    break;
                 }
             }
-            buf_writer.flush().await.unwrap_or(()); // cheaty hack
-            drop(buf_reader);
-            drop(buf_writer);
-        });
+        }
     }
-
-    Ok(())
+    buf_writer.flush().await.unwrap_or(()); // cheaty hack
+    drop(buf_reader);
+    drop(buf_writer);
 }
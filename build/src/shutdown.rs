@@ -0,0 +1,54 @@
+//! Coordinated shutdown, on SIGTERM or once an `exit` program has been answered. Listeners stop
+//! accepting, and connections already accepted finish their programs and flush their responses,
+//! then the runtime works through whatever is still queued. The server waits for all of this up
+//! to a deadline before it exits.
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// How long the server waits for in-flight programs before exiting regardless.
+pub const DEADLINE: Duration = Duration::from_secs(10);
+
+/// A handle held by every listener and connection. The server has drained once every handle has
+/// been dropped.
+#[derive(Clone)]
+pub struct Shutdown {
+    trigger: Arc<watch::Sender<bool>>,
+    triggered: watch::Receiver<bool>,
+    _guard: mpsc::Sender<()>,
+}
+
+/// Waits for every [Shutdown](struct.Shutdown.html) handle to be dropped.
+pub struct Drained {
+    receiver: mpsc::Receiver<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> (Shutdown, Drained) {
+        let (trigger, triggered) = watch::channel(false);
+        let (guard, receiver) = mpsc::channel(1);
+        let shutdown = Shutdown {
+            trigger: Arc::new(trigger),
+            triggered,
+            _guard: guard,
+        };
+        (shutdown, Drained { receiver })
+    }
+
+    /// Starts shutting down. Triggering more than once has no further effect.
+    pub fn trigger(&self) {
+        self.trigger.broadcast(true).unwrap_or(());
+    }
+
+    /// Completes once shutdown has been triggered, immediately if it already has.
+    pub async fn triggered(&mut self) {
+        while let Some(false) = self.triggered.recv().await {}
+    }
+}
+
+impl Drained {
+    /// Waits until every handle has been dropped.
+    pub async fn wait(mut self) {
+        self.receiver.recv().await;
+    }
+}
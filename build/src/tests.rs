@@ -0,0 +1,248 @@
+use super::shutdown::{Shutdown, DEADLINE};
+use super::{bind_local, http, serve, serve_local};
+use bibifi_runtime::{BiBiFi, Engine};
+use futures::poll;
+use std::net::SocketAddr;
+use std::os::unix::fs::MetadataExt;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+
+/// Serves HTTP on a local port, over a fresh runtime whose admin password is "admin".
+async fn listener() -> SocketAddr {
+    let (runtime, receiver) = BiBiFi::new();
+    tokio::spawn(BiBiFi::run(
+        bibifi_util::hash("admin".to_string()),
+        receiver,
+    ));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(http::serve(listener, runtime, Shutdown::new().0));
+    addr
+}
+
+/// Sends a raw request, and returns the whole response.
+async fn request(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+async fn post(addr: SocketAddr, program: &str) -> String {
+    request(
+        addr,
+        &format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            program.len(),
+            program
+        ),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn http_programs() {
+    let addr = listener().await;
+    let response = post(
+        addr,
+        "as principal admin password \"admin\" do\nset x = \"hi\"\nreturn x\n***",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nContent-Type: application/json\r\n"));
+    assert!(response
+        .ends_with("\r\n\r\n[{\"status\":\"SET\"},{\"status\":\"RETURNING\",\"output\":\"hi\"}]"));
+
+    // the program was committed, and later requests see it
+    let response = post(
+        addr,
+        "as principal admin password \"admin\" do\nreturn x\n***",
+    )
+    .await;
+    assert!(response.ends_with("[{\"status\":\"RETURNING\",\"output\":\"hi\"}]"));
+
+    let response = post(
+        addr,
+        "as principal admin password \"wrong\" do\nreturn x\n***",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    assert!(response.ends_with("[{\"status\":\"DENIED\"}]"));
+
+    let response = post(
+        addr,
+        "as principal admin password \"admin\" do\nreturn y\n***",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.ends_with("[{\"status\":\"FAILED\"}]"));
+}
+
+#[tokio::test]
+async fn http_requests() {
+    let addr = listener().await;
+    let response = request(addr, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    let response = request(addr, "POST /x HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = request(addr, "POST / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 411 Length Required\r\n"));
+    let response = request(addr, "POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    let response = request(addr, "hello\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[tokio::test]
+async fn http_timeouts() {
+    tokio::time::pause();
+    let addr = listener().await;
+    // a client which stops partway through the headers, and one which stops partway through the body
+    for partial in &[
+        "POST / HTTP/1.1\r\nHost: localhost\r\n",
+        "POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\nas principal",
+    ] {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(partial.as_bytes()).await.unwrap();
+        let mut response = tokio::spawn(async move {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        let response = loop {
+            tokio::time::advance(Duration::from_secs(1)).await;
+            if let Poll::Ready(response) = poll!(&mut response) {
+                break response.unwrap();
+            }
+        };
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+}
+
+/// Sends a program over the raw protocol, and returns the whole response.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, program: &str) -> String {
+    stream.write_all(program.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn local_socket() {
+    let path = std::env::temp_dir().join(format!("bibifi-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    // only a socket left behind is replaced, not some other file
+    std::fs::write(&path, "kept").unwrap();
+    assert!(bind_local(&path, 0o600).is_err());
+    assert_eq!("kept", std::fs::read_to_string(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    drop(bind_local(&path, 0o600).unwrap());
+    let local = bind_local(&path, 0o600).unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(0o600, metadata.mode() & 0o777);
+    let uid = metadata.uid();
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+
+    // admin may only run programs from a local process of this user
+    let mut engine = Engine::new(bibifi_util::hash("admin".to_string()));
+    engine.set_admin_uids(vec![uid]);
+    let (runtime, receiver) = BiBiFi::new();
+    tokio::spawn(BiBiFi::run_with(engine, receiver));
+    let (shutdown, _) = Shutdown::new();
+    tokio::spawn(serve(tcp, runtime.clone(), shutdown.clone()));
+    tokio::spawn(serve_local(local, runtime, shutdown));
+
+    let program = "as principal admin password \"admin\" do\nset x = \"hi\"\nreturn x\n***";
+    let response = exchange(TcpStream::connect(addr).await.unwrap(), program).await;
+    assert_eq!("{\"status\":\"DENIED\"}\n", response);
+    let response = exchange(UnixStream::connect(&path).await.unwrap(), program).await;
+    assert_eq!(
+        "{\"status\":\"SET\"}\n{\"status\":\"RETURNING\",\"output\":\"hi\"}\n",
+        response
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn shutdown() {
+    let (runtime, receiver) = BiBiFi::new();
+    let engine = tokio::spawn(BiBiFi::run(
+        bibifi_util::hash("admin".to_string()),
+        receiver,
+    ));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, drained) = Shutdown::new();
+    tokio::spawn(serve(listener, runtime, shutdown.clone()));
+
+    // a client which is still sending its program when the server starts shutting down, and one
+    // which has not sent anything
+    let mut slow = TcpStream::connect(addr).await.unwrap();
+    slow.write_all(b"as principal admin password \"admin\" do\n")
+        .await
+        .unwrap();
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+
+    let clients: Vec<_> = (0..8)
+        .map(|n| {
+            let program = format!(
+                "as principal admin password \"admin\" do\nset x{} = \"{}\"\nreturn x{}\n***",
+                n, n, n
+            );
+            tokio::spawn(async move {
+                exchange(TcpStream::connect(addr).await.unwrap(), &program).await
+            })
+        })
+        .collect();
+    let mut responses = Vec::new();
+    for client in clients {
+        responses.push(client.await.unwrap());
+    }
+    let exit = "as principal admin password \"admin\" do\nexit\n***";
+    let response = exchange(TcpStream::connect(addr).await.unwrap(), exit).await;
+    assert_eq!("{\"status\":\"EXITING\"}\n", response);
+    let mut waiting = shutdown;
+    waiting.triggered().await;
+    drop(waiting);
+
+    // the idle client is let go
+    let mut received = Vec::new();
+    let closed = idle.read_to_end(&mut received);
+    assert_eq!(
+        0,
+        tokio::time::timeout(DEADLINE, closed)
+            .await
+            .unwrap()
+            .unwrap()
+    );
+
+    // no more connections are accepted, but the slow client's program still runs
+    let refused = async {
+        while TcpStream::connect(addr).await.is_ok() {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(tokio::time::timeout(DEADLINE, refused).await.is_ok());
+    let response = exchange(slow, "return \"late\"\n***").await;
+    assert_eq!("{\"status\":\"RETURNING\",\"output\":\"late\"}\n", response);
+    for (n, response) in responses.into_iter().enumerate() {
+        assert_eq!(
+            format!(
+                "{{\"status\":\"SET\"}}\n{{\"status\":\"RETURNING\",\"output\":\"{}\"}}\n",
+                n
+            ),
+            response
+        );
+    }
+
+    // and the runtime finishes once every connection has
+    let finished = async move {
+        drained.wait().await;
+        engine.await.unwrap();
+    };
+    assert!(tokio::time::timeout(DEADLINE, finished).await.is_ok());
+}
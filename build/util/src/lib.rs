@@ -1,9 +1,21 @@
 use arrayref::array_ref;
 use blake2::{Blake2s, Digest};
 
+pub mod log;
+#[cfg(test)]
+mod tests;
+
 pub fn hash(input: String) -> [u8; 32] {
     let mut hasher = Blake2s::new();
     hasher.input(input);
     let res = hasher.result();
     *array_ref!(res.as_slice(), 0, 32)
 }
+
+/// A fresh session token: 32 bytes from the operating system's random source, written as
+/// lowercase hex so that it can be quoted in a program.
+pub fn token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("no source of randomness");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,230 @@
+//! Leveled, structured logging shared by the server, the runtime and the database. An event has a
+//! level, a message and named fields, and is written to standard error as a line of text or as a
+//! JSON object, depending on the format given to [init](fn.init.html). Nothing is written until
+//! then.
+//!
+//! ```
+//! use bibifi_util::info;
+//! use bibifi_util::log::{self, Format, Level};
+//!
+//! log::init(Level::Info, Format::Json);
+//! info!("connection accepted", connection = 1, peer = "127.0.0.1:40000");
+//! ```
+//!
+//! Fields are only ever principal names, counts, digests and the like: passwords, tokens and the
+//! text of programs are never logged.
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How important an event is. Configuring a level logs the events of that level and every more
+/// important one.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// How events are written.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// `time LEVEL target: message key=value ...`
+    Human,
+    /// One JSON object per line, with the fields alongside `time`, `level`, `target` and `message`.
+    Json,
+}
+
+/// The least important level written, or 0 if nothing is.
+static LEVEL: AtomicUsize = AtomicUsize::new(0);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Starts writing events of `level` and above, in `format`.
+pub fn init(level: Level, format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Whether events of `level` are written.
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// A short digest identifying `text` in the log, such as a program whose first line holds a
+/// password. The digest is keyed with a secret chosen when the process starts, so it tells
+/// whether two texts are the same within a run without letting anything in them be guessed.
+pub fn digest(text: &str) -> String {
+    static KEY: OnceLock<String> = OnceLock::new();
+    let key = KEY.get_or_init(crate::token);
+    crate::hash(format!("{}{}", key, text))[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Writes an event; use the [event](../macro.event.html) macro and its shorthands instead.
+#[doc(hidden)]
+pub fn write(level: Level, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let time = format!("{}.{:03}", time.as_secs(), time.subsec_millis());
+    let format = if JSON.load(Ordering::Relaxed) {
+        Format::Json
+    } else {
+        Format::Human
+    };
+    eprintln!("{}", line(format, &time, level, target, message, fields));
+}
+
+/// An event as it is written, without the line break.
+pub(crate) fn line(
+    format: Format,
+    time: &str,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: &[(&str, &dyn Display)],
+) -> String {
+    let mut line = String::new();
+    match format {
+        Format::Json => {
+            let _ = write!(
+                line,
+                "{{\"time\":{},\"level\":\"{}\",\"target\":{},\"message\":{}",
+                time,
+                level,
+                quote(target),
+                quote(message)
+            );
+            for (key, value) in fields {
+                let _ = write!(line, ",{}:{}", quote(key), quote(&value.to_string()));
+            }
+            line.push('}');
+        }
+        Format::Human => {
+            let _ = write!(
+                line,
+                "{} {:5} {}: {}",
+                time,
+                level.to_string().to_uppercase(),
+                target,
+                message
+            );
+            for (key, value) in fields {
+                let _ = write!(line, " {}={}", key, value);
+            }
+        }
+    }
+    line
+}
+
+/// The string as a JSON string literal.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        })
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.trim().to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level {}", level)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.trim().to_lowercase().as_str() {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format {}", format)),
+        }
+    }
+}
+
+/// Logs an event at a level, with a message and any number of `key = value` fields, whose values
+/// are written as they display.
+#[macro_export]
+macro_rules! event {
+    ($level:expr, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
+                module_path!(),
+                $message,
+                &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),*],
+            );
+        }
+    };
+}
+
+/// Logs an [event](macro.event.html) at the error level.
+#[macro_export]
+macro_rules! error {
+    ($($event:tt)*) => { $crate::event!($crate::log::Level::Error, $($event)*) };
+}
+
+/// Logs an [event](macro.event.html) at the warn level.
+#[macro_export]
+macro_rules! warn {
+    ($($event:tt)*) => { $crate::event!($crate::log::Level::Warn, $($event)*) };
+}
+
+/// Logs an [event](macro.event.html) at the info level.
+#[macro_export]
+macro_rules! info {
+    ($($event:tt)*) => { $crate::event!($crate::log::Level::Info, $($event)*) };
+}
+
+/// Logs an [event](macro.event.html) at the debug level.
+#[macro_export]
+macro_rules! debug {
+    ($($event:tt)*) => { $crate::event!($crate::log::Level::Debug, $($event)*) };
+}
+
+/// Logs an [event](macro.event.html) at the trace level.
+#[macro_export]
+macro_rules! trace {
+    ($($event:tt)*) => { $crate::event!($crate::log::Level::Trace, $($event)*) };
+}
@@ -0,0 +1,44 @@
+use crate::log::{digest, line, Format, Level};
+
+#[test]
+fn log_lines() {
+    let fields: &[(&str, &dyn std::fmt::Display)] = &[("principal", &"bob"), ("latency_us", &42)];
+    assert_eq!(
+        "1.000 INFO  bibifi::x: program committed principal=bob latency_us=42",
+        line(
+            Format::Human,
+            "1.000",
+            Level::Info,
+            "bibifi::x",
+            "program committed",
+            fields
+        )
+    );
+    assert_eq!(
+        r#"{"time":1.000,"level":"warn","target":"bibifi::x","message":"a \"b\"\n","principal":"bob","latency_us":"42"}"#,
+        line(
+            Format::Json,
+            "1.000",
+            Level::Warn,
+            "bibifi::x",
+            "a \"b\"\n",
+            fields
+        )
+    );
+    assert!(Level::Warn < Level::Debug);
+    assert_eq!(Ok(Level::Debug), "DEBUG".parse());
+    assert_eq!(Ok(Format::Json), "json".parse());
+    assert!("verbose".parse::<Level>().is_err());
+}
+
+#[test]
+fn log_digest() {
+    let program = "as principal admin password \"secret\" do\nreturn \"\"\n***";
+    assert_eq!(digest(program), digest(program));
+    assert_ne!(
+        digest(program),
+        digest("as principal admin password \"other\" do")
+    );
+    assert_eq!(16, digest(program).len());
+    assert!(!digest(program).contains("secret"));
+}